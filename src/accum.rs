@@ -0,0 +1,202 @@
+//! Math for accumulating statistics, such as those of `unix:0:sysinfo` and `unix:0:vminfo`.
+//!
+//! Once a second the kernel adds the current value of each of these statistics, e.g. the length
+//! of the run queue or the amount of free memory, to a running total and increments an `updates`
+//! counter next to them. The average value over an interval is therefore the change of the total
+//! divided by the change of `updates`, not by the time that passed. The same formula gives the
+//! other figures reported by vmstat(1M) and sar(1):
+//!
+//! * `average(runque, updates)` is the average number of runnable threads, vmstat's `r` column
+//! * `average(runocc, updates)` is the fraction of time the run queue was occupied, sar's
+//!   `%runocc` divided by 100
+//! * `average(runque, runocc)` is the average length of the run queue while it was occupied,
+//!   sar's `runq-sz`
+//!
+//! `KstatDelta` uses `classify` to apply this formula to the statistics it knows accumulate.
+//!
+//! # Example
+//! ```
+//! # use std::thread;
+//! # use std::time::Duration;
+//! use kstat::accum::{self, Width};
+//!
+//! let reader = kstat::KstatReader::new().expect("failed to create kstat reader");
+//! let read = || {
+//!     let sysinfo = reader
+//!         .read_one("unix", 0, "sysinfo")
+//!         .expect("failed to read sysinfo")
+//!         .expect("unix:0:sysinfo not found");
+//!     let get = |key| sysinfo.get_u64(key).unwrap_or(0);
+//!     (get("runque"), get("runocc"), get("updates"))
+//! };
+//! let (que1, occ1, upd1) = read();
+//! thread::sleep(Duration::from_secs(5));
+//! let (que2, occ2, upd2) = read();
+//! // sysinfo's statistics are 32-bit
+//! println!("r: {:?}", accum::average(que1, que2, upd1, upd2, Width::Bits32));
+//! println!("runq-sz: {:?}", accum::average(que1, que2, occ1, occ2, Width::Bits32));
+//! ```
+
+use kstat_named::KstatNamedData;
+
+/// How the change of a numeric statistic between two snapshots is turned into a rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatKind {
+    /// a counter, whose rate is its change per second
+    Counter,
+    /// a running total that is added to whenever the counter `updates` in the same kstat is
+    /// incremented, whose rate is its average value, see `average`
+    Accumulator {
+        /// the name of the statistic counting the updates
+        updates: &'static str,
+    },
+}
+
+/// The width of an unsigned counter, which decides whether it can wrap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Width {
+    /// a KSTAT_DATA_UINT32 counter, which wraps at 2^32
+    Bits32,
+    /// a KSTAT_DATA_UINT64 counter, which does not wrap in practice
+    #[default]
+    Bits64,
+}
+
+impl Width {
+    /// Returns the width of `value`, or `None` if it is not an unsigned integer
+    pub fn of(value: &KstatNamedData) -> Option<Width> {
+        match *value {
+            KstatNamedData::DataUInt32(_) => Some(Width::Bits32),
+            KstatNamedData::DataUInt64(_) => Some(Width::Bits64),
+            _ => None,
+        }
+    }
+}
+
+/// The module, name, update counter, and accumulating statistics of every kstat known to
+/// accumulate
+const ACCUMULATORS: &[(&str, &str, &str, &[&str])] = &[
+    (
+        "unix",
+        "sysinfo",
+        "updates",
+        &["runque", "runocc", "swpque", "swpocc", "waiting"],
+    ),
+    (
+        "unix",
+        "vminfo",
+        "updates",
+        &[
+            "freemem",
+            "swap_resv",
+            "swap_alloc",
+            "swap_avail",
+            "swap_free",
+        ],
+    ),
+];
+
+/// Returns the kind of the statistic `stat` of the kstats named `module:*:name`. Statistics this
+/// crate knows nothing about are counters.
+///
+/// # Example
+/// ```
+/// use kstat::accum::{self, StatKind};
+///
+/// assert_eq!(
+///     accum::classify("unix", "vminfo", "freemem"),
+///     StatKind::Accumulator { updates: "updates" }
+/// );
+/// assert_eq!(accum::classify("unix", "vminfo", "updates"), StatKind::Counter);
+/// ```
+pub fn classify(module: &str, name: &str, stat: &str) -> StatKind {
+    for &(m, n, updates, stats) in ACCUMULATORS {
+        if m == module && n == name && stats.contains(&stat) {
+            return StatKind::Accumulator { updates };
+        }
+    }
+    StatKind::Counter
+}
+
+/// The average value of an accumulating statistic between two snapshots: the change of the
+/// running total from `prev` to `cur` divided by the change of its update counter from
+/// `prev_updates` to `cur_updates`, both of which are `width` wide. Either may have wrapped
+/// once, see `counter_delta`. Returns `None` if the update counter did not advance or either
+/// counter was reset.
+pub fn average(
+    prev: u64,
+    cur: u64,
+    prev_updates: u64,
+    cur_updates: u64,
+    width: Width,
+) -> Option<f64> {
+    match counter_delta(prev_updates, cur_updates, width)? {
+        0 => None,
+        updates => Some(counter_delta(prev, cur, width)? as f64 / updates as f64),
+    }
+}
+
+/// The change of a counter of width `width` from `prev` to `cur`. A 32-bit counter that went
+/// backwards is treated as having wrapped at 2^32. A 64-bit counter cannot wrap in practice, so
+/// one that went backwards was reset, e.g. zeroed by a driver, and `None` is returned.
+///
+/// # Example
+/// ```
+/// use kstat::accum::{self, Width};
+///
+/// assert_eq!(accum::counter_delta(u64::from(u32::MAX), 4, Width::Bits32), Some(5));
+/// assert_eq!(accum::counter_delta(1000, 10, Width::Bits64), None);
+/// ```
+pub fn counter_delta(prev: u64, cur: u64, width: Width) -> Option<u64> {
+    if cur >= prev {
+        return Some(cur - prev);
+    }
+    match width {
+        Width::Bits32 if prev <= u64::from(u32::MAX) => {
+            Some((u64::from(u32::MAX) - prev) + cur + 1)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages() {
+        // 2 runnable threads on average over 5 updates, with the queue occupied 4 of them
+        assert_eq!(average(100, 110, 50, 55, Width::Bits32), Some(2.0));
+        assert_eq!(average(20, 24, 50, 55, Width::Bits32), Some(0.8));
+        assert_eq!(average(100, 110, 20, 24, Width::Bits32), Some(2.5));
+        assert_eq!(average(100, 110, 55, 55, Width::Bits32), None);
+
+        // the 32-bit total and update counter both wrapped
+        let max = u64::from(u32::MAX);
+        assert_eq!(average(max - 3, 6, max, 4, Width::Bits32), Some(2.0));
+
+        // the 64-bit update counter was reset
+        assert_eq!(average(100, 110, 50, 5, Width::Bits64), None);
+    }
+
+    #[test]
+    fn counter_deltas() {
+        let max = u64::from(u32::MAX);
+        assert_eq!(counter_delta(10, 1000, Width::Bits32), Some(990));
+        assert_eq!(counter_delta(max - 1, 2, Width::Bits32), Some(4));
+        assert_eq!(counter_delta(10, 1000, Width::Bits64), Some(990));
+
+        // a 64-bit counter that went backwards was reset rather than wrapped, even if its
+        // previous value would fit in 32 bits
+        assert_eq!(counter_delta(1000, 10, Width::Bits64), None);
+        assert_eq!(counter_delta(u64::MAX, 10, Width::Bits64), None);
+    }
+
+    #[test]
+    fn classification() {
+        let accumulator = StatKind::Accumulator { updates: "updates" };
+        assert_eq!(classify("unix", "sysinfo", "runque"), accumulator);
+        assert_eq!(classify("unix", "sysinfo", "updates"), StatKind::Counter);
+        assert_eq!(classify("cpu", "sys", "runque"), StatKind::Counter);
+    }
+}
@@ -0,0 +1,135 @@
+//! Tools for planning how kstats are exported, such as estimating how many time series an
+//! exporter would create from a sample before pointing it at a real backend.
+
+use exporters::influx;
+#[cfg(feature = "statsd")]
+use exporters::statsd;
+use KstatData;
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+
+/// The naming rules of an exporter, which decide what makes two series distinct
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Naming {
+    /// `InfluxWriter`'s line protocol, where every field of a measurement and tag set is a
+    /// series
+    Influx,
+    /// `StatsdExporter`'s metric names, with the instance sent as a tag if `tags` is set. Only
+    /// numeric statistics are sent, and names that only differ in characters statsd reserves
+    /// end up as the same series.
+    #[cfg(feature = "statsd")]
+    Statsd {
+        /// whether the exporter sends tags, see `StatsdExporter::tags`
+        tags: bool,
+    },
+}
+
+impl Naming {
+    fn series(&self, kstat: &KstatData) -> Vec<String> {
+        match *self {
+            Naming::Influx => influx::series(kstat),
+            #[cfg(feature = "statsd")]
+            Naming::Statsd { tags } => statsd::series(kstat, tags),
+        }
+    }
+}
+
+/// The number of series the kstats of one module and name would produce
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cardinality {
+    /// the module of the kstats
+    pub module: String,
+    /// the name of the kstats
+    pub name: String,
+    /// the number of kstats in the sample with this module and name
+    pub kstats: usize,
+    /// the number of unique series they would produce
+    pub series: usize,
+}
+
+/// Count the unique series every module and name in `sample` would produce under `naming`,
+/// sorted by series in descending order
+///
+/// # Example
+/// ```
+/// use kstat::analyze::{self, Naming};
+///
+/// let reader = kstat::KstatReader::new().expect("failed to create kstat reader");
+/// let sample = reader.read().expect("failed to read kstat(s)");
+/// for c in analyze::cardinality(&sample, Naming::Influx).iter().take(10) {
+///     println!("{}:{} {} series from {} kstats", c.module, c.name, c.series, c.kstats);
+/// }
+/// ```
+pub fn cardinality(sample: &[KstatData], naming: Naming) -> Vec<Cardinality> {
+    let mut groups: BTreeMap<(&str, &str), (usize, HashSet<String>)> = BTreeMap::new();
+    for kstat in sample {
+        let group = groups.entry((&kstat.module, &kstat.name)).or_default();
+        group.0 += 1;
+        group.1.extend(naming.series(kstat));
+    }
+
+    let mut ret: Vec<Cardinality> = groups
+        .into_iter()
+        .map(|((module, name), (kstats, series))| Cardinality {
+            module: module.to_string(),
+            name: name.to_string(),
+            kstats,
+            series: series.len(),
+        })
+        .collect();
+    // The map is sorted by module and name, which the stable sort keeps for ties
+    ret.sort_by_key(|c| Reverse(c.series));
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures;
+    use kstat_named::KstatNamedData;
+
+    fn kstat(module: &str, instance: i32, name: &str, stats: &[&str]) -> KstatData {
+        let stats = stats
+            .iter()
+            .map(|&s| (s, KstatNamedData::DataUInt64(1)))
+            .collect();
+        fixtures::kstat(module, instance, name, stats)
+    }
+
+    #[test]
+    fn sorted_by_series() {
+        let sample = vec![
+            kstat("cpu", 0, "sys", &["intr", "syscall"]),
+            kstat("cpu", 1, "sys", &["intr", "syscall"]),
+            kstat("unix", 0, "system_misc", &["nproc"]),
+            kstat("zfs", 0, "arcstats", &["hits", "misses", "size"]),
+        ];
+        let counts: Vec<_> = cardinality(&sample, Naming::Influx)
+            .into_iter()
+            .map(|c| (c.module, c.kstats, c.series))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("cpu".to_string(), 2, 4),
+                ("zfs".to_string(), 1, 3),
+                ("unix".to_string(), 1, 1),
+            ]
+        );
+    }
+
+    #[cfg(feature = "statsd")]
+    #[test]
+    fn statsd_collisions() {
+        let sample = vec![kstat(
+            "sd",
+            0,
+            "sd0",
+            &["Soft Errors", "Soft_Errors", "Hard Errors"],
+        )];
+        let c = cardinality(&sample, Naming::Statsd { tags: true });
+        assert_eq!(c[0].series, 2);
+        assert_eq!(cardinality(&sample, Naming::Influx)[0].series, 3);
+    }
+}
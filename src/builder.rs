@@ -0,0 +1,361 @@
+use super::kstat_ctl::Limits;
+use super::selector::Instances;
+use super::triplet::TripletCache;
+use {Callback, ErrorPolicy, KstatCtl, KstatMeta, KstatReader, KstatSelector, KstatType};
+use {FilterFn, Matcher, ProgressFn, ReadProgress, ReopenFn, SampleTimings};
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::io;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+/// Configures a `KstatReader`. Every filter left unset matches every kstat, and `build` opens
+/// the kstat handle unless one is shared with `ctl`.
+///
+/// # Example
+/// ```
+/// let reader = kstat::KstatReader::builder()
+///     .module("zfs")
+///     .instance(0)
+///     .name("arcstats")
+///     .build()
+///     .expect("failed to create kstat reader");
+/// let stats = reader.read().expect("failed to read kstat(s)");
+/// ```
+#[derive(Debug, Default)]
+pub struct KstatReaderBuilder {
+    filter: KstatSelector,
+    selectors: Vec<KstatSelector>,
+    kstat_type: Option<KstatType>,
+    filter_fn: Option<FilterFn>,
+    reopen_after: Option<u32>,
+    on_reopen: Option<ReopenFn>,
+    on_progress: Option<(usize, ProgressFn)>,
+    error_policy: ErrorPolicy,
+    limits: Limits,
+    sorted: bool,
+    ctl: Option<Arc<KstatCtl>>,
+}
+
+impl KstatReaderBuilder {
+    /// Returns a `KstatReaderBuilder` whose reader matches every kstat
+    pub fn new() -> Self {
+        KstatReaderBuilder::default()
+    }
+
+    /// Set the module filter. A plain string matches exactly, or a `Matcher` can be passed to
+    /// match a glob or regex.
+    pub fn module<M: Into<Matcher>>(mut self, m: M) -> Self {
+        self.filter.module = Some(m.into());
+        self
+    }
+
+    /// Set the instance filter. When the module and name filters are plain strings and the
+    /// instance is set, the kstat is found with `kstat_lookup()` instead of walking the chain.
+    pub fn instance(mut self, i: i32) -> Self {
+        self.filter.instance = Some(Instances::One(i));
+        self
+    }
+
+    /// Set the instance filter to match every instance in `range`, e.g. `0..=31` for the first
+    /// 32 CPUs
+    ///
+    /// # Example
+    /// ```
+    /// let reader = kstat::KstatReader::builder()
+    ///     .module("cpu")
+    ///     .name("sys")
+    ///     .instance_in(0..=31)
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// ```
+    pub fn instance_in<R: RangeBounds<i32>>(mut self, range: R) -> Self {
+        self.filter = self.filter.instance_in(range);
+        self
+    }
+
+    /// Set the instance filter to match any of `instances`
+    pub fn instances(mut self, instances: &[i32]) -> Self {
+        self.filter = self.filter.instances(instances);
+        self
+    }
+
+    /// Set the name filter. A plain string matches exactly, or a `Matcher` can be passed to
+    /// match a glob or regex.
+    pub fn name<M: Into<Matcher>>(mut self, n: M) -> Self {
+        self.filter.name = Some(n.into());
+        self
+    }
+
+    /// Set the class filter. A plain string matches exactly, or a `Matcher` can be passed to
+    /// match a glob or regex.
+    pub fn class<M: Into<Matcher>>(mut self, c: M) -> Self {
+        self.filter.class = Some(c.into());
+        self
+    }
+
+    /// Set the module filter to match any of `modules`, so that a set of modules can be read
+    /// without reading every kstat
+    ///
+    /// # Example
+    /// ```
+    /// let reader = kstat::KstatReader::builder()
+    ///     .modules(["sd", "ssd", "blkdev"])
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// let stats = reader.read().expect("failed to read kstat(s)");
+    /// ```
+    pub fn modules<I, S>(self, modules: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.module(Matcher::one_of(modules))
+    }
+
+    /// Set the name filter to match any of `names`
+    pub fn names<I, S>(self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.name(Matcher::one_of(names))
+    }
+
+    /// Set the class filter to match any of `classes`
+    pub fn classes<I, S>(self, classes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.class(Matcher::one_of(classes))
+    }
+
+    /// Only match kstats of type `t`. Note that only KSTAT_TYPE_NAMED and KSTAT_TYPE_IO kstats
+    /// are ever read; the other types can only be listed.
+    ///
+    /// # Example
+    /// ```
+    /// let reader = kstat::KstatReader::builder()
+    ///     .kstat_type(kstat::KstatType::Io)
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// ```
+    pub fn kstat_type(mut self, t: KstatType) -> Self {
+        self.kstat_type = Some(t);
+        self
+    }
+
+    /// Add a selector. Once any selectors have been added, a kstat is read if it matches the
+    /// builder's own filters and at least one of the selectors.
+    ///
+    /// # Example
+    /// ```
+    /// # use kstat::{KstatReader, KstatSelector};
+    /// let reader = KstatReader::builder()
+    ///     .add_selector(KstatSelector::new().module("cpu").name("sys"))
+    ///     .add_selector(KstatSelector::new().module("zone_vfs"))
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// let stats = reader.read().expect("failed to read kstat(s)");
+    /// ```
+    pub fn add_selector(mut self, selector: KstatSelector) -> Self {
+        self.selectors.push(selector);
+        self
+    }
+
+    /// Set a predicate that is called with the metadata of every kstat that passes the other
+    /// filters. The kstat is only read if the predicate returns true. Like the other filters, the
+    /// predicate is only evaluated again once the kstat chain changes.
+    ///
+    /// # Example
+    /// ```
+    /// let reader = kstat::KstatReader::builder()
+    ///     .filter_fn(|meta| meta.name.starts_with("nfs_client") && meta.class != "misc")
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// ```
+    pub fn filter_fn<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&KstatMeta) -> bool + Send + 'static,
+    {
+        self.filter_fn = Some(Callback(Box::new(f)));
+        self
+    }
+
+    /// Close and reopen the underlying kstat handle once `failures` consecutive chain updates or
+    /// kstat reads have failed with a non-transient error, so that long running consumers recover
+    /// from libkstat getting into a bad state. Reads the `ErrorPolicy` skips are not counted.
+    /// Disabled by default.
+    ///
+    /// # Example
+    /// ```
+    /// let reader = kstat::KstatReader::builder()
+    ///     .reopen_after(3)
+    ///     .on_reopen(|failures| eprintln!("reopened kstat handle after {} failures", failures))
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// ```
+    pub fn reopen_after(mut self, failures: u32) -> Self {
+        self.reopen_after = Some(failures);
+        self
+    }
+
+    /// Set a callback that is called with the number of consecutive failures whenever the
+    /// reader reopens its kstat handle. See `reopen_after`.
+    pub fn on_reopen<F>(mut self, f: F) -> Self
+    where
+        F: Fn(u32) + Send + 'static,
+    {
+        self.on_reopen = Some(Callback(Box::new(f)));
+        self
+    }
+
+    /// Set what the reader does when reading a single kstat fails. Defaults to
+    /// `ErrorPolicy::IgnoreTransient`.
+    pub fn error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.error_policy = policy;
+        self
+    }
+
+    /// Keep at most `len` bytes of each KSTAT_DATA_STRING value, to bound the memory of a
+    /// single sample. Longer values are truncated and the names of their statistics are listed
+    /// in `KstatData::truncated`. Strings are not limited by default.
+    ///
+    /// # Example
+    /// ```
+    /// let reader = kstat::KstatReader::builder()
+    ///     .max_string_len(4096)
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// for stat in reader.read().expect("failed to read kstat(s)") {
+    ///     for name in &stat.truncated {
+    ///         eprintln!("{}:{}:{}:{} was truncated", stat.module, stat.instance, stat.name, name);
+    ///     }
+    /// }
+    /// ```
+    pub fn max_string_len(mut self, len: usize) -> Self {
+        self.limits.max_string_len = len;
+        self
+    }
+
+    /// Reject kstats with more than `max` named statistics as malformed instead of decoding
+    /// them, so that a provider reporting a bogus count cannot make a sample allocate without
+    /// bound. The rejected kstat's error is handled by the reader's `ErrorPolicy`. Defaults to
+    /// 65536.
+    pub fn max_ndata(mut self, max: usize) -> Self {
+        self.limits.max_ndata = max;
+        self
+    }
+
+    /// Only decode the named statistics in `stats`, e.g. `rbytes64` and `obytes64` of every link
+    /// kstat, skipping the others without copying them. This saves a lot of time and memory when
+    /// polling wide kstats frequently. KSTAT_TYPE_IO kstats are always decoded in full.
+    ///
+    /// # Example
+    /// ```
+    /// let reader = kstat::KstatReader::builder()
+    ///     .module("link")
+    ///     .stats(["rbytes64", "obytes64"])
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// for stat in reader.read().expect("failed to read kstat(s)") {
+    ///     println!("{}: {:?} {:?}", stat.name, stat.get("rbytes64"), stat.get("obytes64"));
+    /// }
+    /// ```
+    pub fn stats<I, S>(mut self, stats: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.limits.stats = Some(Matcher::one_of(stats));
+        self
+    }
+
+    /// Set a callback that is called with the progress of the walk every `every` kstats, so
+    /// that interactive tools can show progress on hosts where a full read takes a while.
+    ///
+    /// # Example
+    /// ```
+    /// let reader = kstat::KstatReader::builder()
+    ///     .on_progress(1000, |p| eprint!("\r{} kstats, {} matched", p.processed, p.matched))
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// let stats = reader.read().expect("failed to read kstat(s)");
+    /// ```
+    pub fn on_progress<F>(mut self, every: usize, f: F) -> Self
+    where
+        F: Fn(ReadProgress) + Send + 'static,
+    {
+        self.on_progress = Some((cmp::max(every, 1), Callback(Box::new(f))));
+        self
+    }
+
+    /// Return the kstats of `read` and `read_outcome` sorted by module, instance, and name
+    /// instead of in the order of the chain, which changes as kstats come and go. Sorted output
+    /// diffs cleanly between snapshots and makes for stable golden files and CLI output. Enable
+    /// the `ordered` feature as well to keep the named statistics of each kstat in a stable
+    /// order. Disabled by default.
+    ///
+    /// # Example
+    /// ```
+    /// let reader = kstat::KstatReader::builder()
+    ///     .module("cpu")
+    ///     .sorted(true)
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// for stat in reader.read().expect("failed to read kstat(s)") {
+    ///     println!("{}:{}:{}", stat.module, stat.instance, stat.name);
+    /// }
+    /// ```
+    pub fn sorted(mut self, enabled: bool) -> Self {
+        self.sorted = enabled;
+        self
+    }
+
+    /// Read through the shared handle `ctl` instead of opening a new one. See `KstatCtl`.
+    pub fn ctl(mut self, ctl: Arc<KstatCtl>) -> Self {
+        self.ctl = Some(ctl);
+        self
+    }
+
+    /// Returns the configured `KstatReader`, opening a kstat handle for it unless one was shared
+    /// with `ctl`
+    pub fn build(mut self) -> io::Result<KstatReader> {
+        let ctl = match self.ctl.take() {
+            Some(ctl) => ctl,
+            None => Arc::new(KstatCtl::new()?),
+        };
+        Ok(self.build_with(ctl))
+    }
+
+    /// Returns the configured `KstatReader` reading through `ctl`, which unlike `build` cannot
+    /// fail since no handle has to be opened
+    pub(crate) fn build_with(self, ctl: Arc<KstatCtl>) -> KstatReader {
+        let triplet = if self.selectors.is_empty() {
+            self.filter.triplet()
+        } else {
+            None
+        };
+
+        KstatReader {
+            filter: self.filter,
+            selectors: self.selectors,
+            kstat_type: self.kstat_type,
+            filter_fn: self.filter_fn,
+            reopen_after: self.reopen_after,
+            on_reopen: self.on_reopen,
+            on_progress: self.on_progress,
+            error_policy: self.error_policy,
+            limits: self.limits,
+            sorted: self.sorted,
+            timings: Cell::new(SampleTimings::default()),
+            failures: Cell::new(0),
+            match_cache: RefCell::new(None),
+            triplet,
+            triplet_cache: RefCell::new(TripletCache::default()),
+            ctl,
+        }
+    }
+}
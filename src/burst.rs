@@ -0,0 +1,77 @@
+use exporters::influx::InfluxWriter;
+use sampler::run_every;
+use KstatReader;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Samples a set of kstats at a high frequency for a bounded time, e.g. every 100ms for 30
+/// seconds while investigating a transient problem, writing every sample to a capture as InfluxDB
+/// line protocol. The capture holds absolute values with nanosecond timestamps, so that rates can
+/// be computed at any resolution afterwards.
+///
+/// A burst is usually started next to a long running `KstatSampler` with its own, narrower,
+/// reader: `spawn` runs it on a dedicated thread so that the regular sampling is not delayed.
+///
+/// # Example
+/// ```
+/// # use std::time::Duration;
+/// let reader = kstat::KstatReader::builder()
+///     .module("cpu")
+///     .name("sys")
+///     .build()
+///     .expect("failed to create kstat reader");
+/// let burst = kstat::BurstCapture::new(Duration::from_millis(100), Duration::from_secs(30));
+/// let handle = burst.spawn(reader, "/var/tmp/cpu-burst.influx");
+/// let samples = handle.join().expect("burst panicked").expect("burst failed");
+/// println!("captured {} samples", samples);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct BurstCapture {
+    interval: Duration,
+    duration: Duration,
+}
+
+impl BurstCapture {
+    /// Returns a `BurstCapture` that samples once every `interval` until `duration` has passed
+    pub fn new(interval: Duration, duration: Duration) -> Self {
+        BurstCapture { interval, duration }
+    }
+
+    /// Run the burst on the current thread, writing every sample read by `reader` to `writer`.
+    /// Returns the number of samples taken, or the first error, which ends the burst early. A
+    /// zero interval is rejected with an `InvalidInput` error.
+    pub fn capture<W: Write>(&self, reader: &KstatReader, writer: W) -> io::Result<usize> {
+        let mut influx = InfluxWriter::new(writer);
+        let start = Instant::now();
+        let mut samples = 0;
+        let mut result = Ok(());
+
+        run_every(self.interval, || {
+            if start.elapsed() >= self.duration {
+                return false;
+            }
+            result = reader.read().and_then(|data| influx.write(&data));
+            samples += 1;
+            result.is_ok()
+        })?;
+
+        result.map(|_| samples)
+    }
+
+    /// Run the burst on a new thread, writing it to a capture file created at `path`. The
+    /// returned handle yields the result of `capture`.
+    pub fn spawn<P>(self, reader: KstatReader, path: P) -> thread::JoinHandle<io::Result<usize>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        thread::spawn(move || {
+            let file = File::create(path)?;
+            self.capture(&reader, BufWriter::new(file))
+        })
+    }
+}
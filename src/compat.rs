@@ -0,0 +1,206 @@
+//! Shims for the older shapes of the API, so that existing consumers can migrate one call site
+//! at a time. Everything in here is deprecated and will be removed in the next major version.
+//!
+//! This includes the setters `KstatReader` had before readers were configured with
+//! `KstatReaderBuilder`, so that code like the following keeps compiling with a deprecation
+//! warning:
+//!
+//! ```
+//! # #![allow(deprecated)]
+//! let mut reader = kstat::KstatReader::new().expect("failed to create kstat reader");
+//! reader.module("zone_vfs").class("zone_vfs");
+//! let stats = reader.read().expect("failed to read kstat(s)");
+//! ```
+
+use kstat_named::KstatNamedData;
+use selector::Instances;
+use {Callback, ErrorPolicy, KstatCtl, KstatMeta, KstatReader, KstatReaderBuilder, KstatSelector};
+use {Data, KstatData, Matcher, ReadProgress};
+
+use std::cmp;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+/// Returns a `KstatReader` that only matches the given module, instance, name and class, like
+/// the original four argument `KstatReader::new`.
+#[deprecated(since = "0.1.0", note = "use `KstatReader::builder`")]
+pub fn reader<S>(
+    module: Option<S>,
+    instance: Option<i32>,
+    name: Option<S>,
+    class: Option<S>,
+) -> io::Result<KstatReader>
+where
+    S: Into<String>,
+{
+    let mut builder = KstatReader::builder();
+    if let Some(m) = module {
+        builder = builder.module(m.into());
+    }
+    if let Some(i) = instance {
+        builder = builder.instance(i);
+    }
+    if let Some(n) = name {
+        builder = builder.name(n.into());
+    }
+    if let Some(c) = class {
+        builder = builder.class(c.into());
+    }
+    builder.build()
+}
+
+/// Consume `data`, returning its named-value pairs as the `HashMap` that `KstatData::data` used
+/// to be.
+/// Kstats of type KSTAT_TYPE_IO have no named-value pairs and return an empty map.
+#[deprecated(
+    since = "0.1.0",
+    note = "use `KstatData::get` or match on `KstatData::data`"
+)]
+pub fn into_named_data(data: KstatData) -> HashMap<String, KstatNamedData> {
+    match data.data {
+        Data::Named(named) => named.into_iter().collect(),
+        Data::Io(_) => HashMap::new(),
+    }
+}
+
+/// The setters `KstatReader` had before it was configured with `KstatReaderBuilder`. Each one
+/// changes the reader in place and does the same as the builder method of the same name.
+impl KstatReader {
+    /// Returns a `KstatReader` that reads through the shared handle `ctl` instead of opening its
+    /// own
+    #[deprecated(since = "0.1.0", note = "use `KstatReaderBuilder::ctl`")]
+    pub fn with_ctl(ctl: Arc<KstatCtl>) -> Self {
+        KstatReaderBuilder::new().build_with(ctl)
+    }
+
+    /// Set the module filter
+    #[deprecated(since = "0.1.0", note = "use `KstatReaderBuilder::module`")]
+    pub fn module<M: Into<Matcher>>(&mut self, m: M) -> &mut Self {
+        self.filter.module = Some(m.into());
+        self.filters_changed()
+    }
+
+    /// Set the instance filter
+    #[deprecated(since = "0.1.0", note = "use `KstatReaderBuilder::instance`")]
+    pub fn instance(&mut self, i: i32) -> &mut Self {
+        self.filter.instance = Some(Instances::One(i));
+        self.filters_changed()
+    }
+
+    /// Set the name filter
+    #[deprecated(since = "0.1.0", note = "use `KstatReaderBuilder::name`")]
+    pub fn name<M: Into<Matcher>>(&mut self, n: M) -> &mut Self {
+        self.filter.name = Some(n.into());
+        self.filters_changed()
+    }
+
+    /// Set the class filter
+    #[deprecated(since = "0.1.0", note = "use `KstatReaderBuilder::class`")]
+    pub fn class<M: Into<Matcher>>(&mut self, c: M) -> &mut Self {
+        self.filter.class = Some(c.into());
+        self.filters_changed()
+    }
+
+    /// Add a selector
+    #[deprecated(since = "0.1.0", note = "use `KstatReaderBuilder::add_selector`")]
+    pub fn add_selector(&mut self, selector: KstatSelector) -> &mut Self {
+        self.selectors.push(selector);
+        self.filters_changed()
+    }
+
+    /// Set a predicate over the metadata of every kstat that passes the other filters
+    #[deprecated(since = "0.1.0", note = "use `KstatReaderBuilder::filter_fn`")]
+    pub fn filter_fn<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&KstatMeta) -> bool + Send + 'static,
+    {
+        self.filter_fn = Some(Callback(Box::new(f)));
+        self.filters_changed()
+    }
+
+    /// Reopen the kstat handle after `failures` consecutive failures
+    #[deprecated(since = "0.1.0", note = "use `KstatReaderBuilder::reopen_after`")]
+    pub fn reopen_after(&mut self, failures: u32) -> &mut Self {
+        self.reopen_after = Some(failures);
+        self
+    }
+
+    /// Set a callback that is called whenever the kstat handle is reopened
+    #[deprecated(since = "0.1.0", note = "use `KstatReaderBuilder::on_reopen`")]
+    pub fn on_reopen<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(u32) + Send + 'static,
+    {
+        self.on_reopen = Some(Callback(Box::new(f)));
+        self
+    }
+
+    /// Set what the reader does when reading a single kstat fails
+    #[deprecated(since = "0.1.0", note = "use `KstatReaderBuilder::error_policy`")]
+    pub fn error_policy(&mut self, policy: ErrorPolicy) -> &mut Self {
+        self.error_policy = policy;
+        self
+    }
+
+    /// Keep at most `len` bytes of each KSTAT_DATA_STRING value
+    #[deprecated(since = "0.1.0", note = "use `KstatReaderBuilder::max_string_len`")]
+    pub fn max_string_len(&mut self, len: usize) -> &mut Self {
+        self.limits.max_string_len = len;
+        self
+    }
+
+    /// Reject kstats with more than `max` named statistics as malformed
+    #[deprecated(since = "0.1.0", note = "use `KstatReaderBuilder::max_ndata`")]
+    pub fn max_ndata(&mut self, max: usize) -> &mut Self {
+        self.limits.max_ndata = max;
+        self
+    }
+
+    /// Set a callback that is called with the progress of the walk every `every` kstats
+    #[deprecated(since = "0.1.0", note = "use `KstatReaderBuilder::on_progress`")]
+    pub fn on_progress<F>(&mut self, every: usize, f: F) -> &mut Self
+    where
+        F: Fn(ReadProgress) + Send + 'static,
+    {
+        self.on_progress = Some((cmp::max(every, 1), Callback(Box::new(f))));
+        self
+    }
+
+    /// Forget everything derived from the filters after one of them changes
+    fn filters_changed(&mut self) -> &mut Self {
+        *self.match_cache.get_mut() = None;
+        self.triplet = if self.selectors.is_empty() {
+            self.filter.triplet()
+        } else {
+            None
+        };
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(deprecated)]
+    fn old_call_sites_compile() {
+        // Reading needs a live kstat device, so only check that the old call sites compile
+        fn old_call_site() -> io::Result<Vec<KstatData>> {
+            let mut r = KstatReader::new()?;
+            r.module("zone_vfs").class("zone_vfs");
+            r.instance(0).name("global");
+            r.add_selector(KstatSelector::new());
+            r.filter_fn(|meta| meta.class != "misc");
+            r.error_policy(ErrorPolicy::Strict);
+            r.reopen_after(3).on_reopen(|_| ());
+            r.max_string_len(4096).max_ndata(1024);
+            r.on_progress(100, |_| ());
+            r.read()
+        }
+        let read: fn() -> io::Result<Vec<KstatData>> = old_call_site;
+        let with_ctl: fn(Arc<KstatCtl>) -> KstatReader = KstatReader::with_ctl;
+        let _ = (read, with_ctl);
+    }
+}
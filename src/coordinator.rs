@@ -0,0 +1,194 @@
+use libc;
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+
+/// The lock files opened by this process, keyed by device and inode. `fcntl(2)` locks belong to
+/// the process rather than to a file descriptor, so every coordinator on the same file must share
+/// one descriptor and one in-process mutex: a second descriptor would be granted the lock while
+/// another thread holds it, and closing it would release that thread's lock.
+static OPEN: Mutex<Vec<(u64, u64, Weak<LockFile>)>> = Mutex::new(Vec::new());
+
+/// A lock file and the mutex that serializes the threads of this process taking its lock
+#[derive(Debug)]
+struct LockFile {
+    file: File,
+    local: Mutex<()>,
+}
+
+/// An advisory lock shared by every process on a host that samples kstats through the same lock
+/// file. Taking the lock around each chain walk keeps the walks of independent agents from
+/// running at the same time, so their load on /dev/kstat is spread out instead of arriving all at
+/// once.
+///
+/// The lock is an `fcntl(2)` write lock on the whole file, and threads of the same process are
+/// serialized by an in-process mutex, including threads using different coordinators on the
+/// same file. Note the limits of this scheme:
+///
+/// * walks are serialized, not scheduled: agents that sample on the same interval still wake up
+///   together and then wait for each other, so each should add its own offset to its schedule
+///   if the waiting matters
+/// * the lock is advisory and only agents using the same lock file take part
+/// * the lock file should live on a local file system, since `fcntl` locks may not work over
+///   NFS
+///
+/// # Example
+/// ```
+/// let coordinator = kstat::SampleCoordinator::new("/var/run/kstat-sample.lock")
+///     .expect("failed to open lock file");
+/// let reader = kstat::KstatReader::builder()
+///     .module("zone_vfs")
+///     .build()
+///     .expect("failed to create kstat reader");
+/// let stats = {
+///     let _guard = coordinator.lock().expect("failed to take sampling lock");
+///     reader.read().expect("failed to read kstat(s)")
+/// };
+/// ```
+#[derive(Debug)]
+pub struct SampleCoordinator {
+    lock_file: Arc<LockFile>,
+}
+
+/// Holds the lock of a `SampleCoordinator` until it is dropped
+#[derive(Debug)]
+pub struct CoordinatorGuard<'a> {
+    coordinator: &'a SampleCoordinator,
+    _local: MutexGuard<'a, ()>,
+}
+
+impl SampleCoordinator {
+    /// Open, creating it if needed, the lock file at `path`. Every agent that should be
+    /// staggered must use the same path.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut open = OPEN.lock().unwrap_or_else(PoisonError::into_inner);
+        open.retain(|(_, _, f)| f.strong_count() > 0);
+
+        // Look for the file before opening it, since closing a second descriptor of a file
+        // this process has locked would release the lock
+        if let Ok(meta) = fs::metadata(path) {
+            let (dev, ino) = (meta.dev(), meta.ino());
+            let shared = open
+                .iter()
+                .find(|&&(d, i, _)| d == dev && i == ino)
+                .and_then(|(_, _, f)| f.upgrade());
+            if let Some(lock_file) = shared {
+                return Ok(SampleCoordinator { lock_file });
+            }
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let meta = file.metadata()?;
+        let lock_file = Arc::new(LockFile {
+            file,
+            local: Mutex::new(()),
+        });
+        open.push((meta.dev(), meta.ino(), Arc::downgrade(&lock_file)));
+        Ok(SampleCoordinator { lock_file })
+    }
+
+    /// Take the lock, blocking while another process or thread holds it
+    pub fn lock(&self) -> io::Result<CoordinatorGuard<'_>> {
+        let local = self
+            .lock_file
+            .local
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.fcntl(libc::F_SETLKW, libc::F_WRLCK)?;
+        Ok(CoordinatorGuard {
+            coordinator: self,
+            _local: local,
+        })
+    }
+
+    /// Take the lock if nobody else holds it, returning `None` instead of blocking
+    pub fn try_lock(&self) -> io::Result<Option<CoordinatorGuard<'_>>> {
+        let local = match self.lock_file.local.try_lock() {
+            Ok(l) => l,
+            Err(_) => return Ok(None),
+        };
+        match self.fcntl(libc::F_SETLK, libc::F_WRLCK) {
+            Ok(()) => Ok(Some(CoordinatorGuard {
+                coordinator: self,
+                _local: local,
+            })),
+            Err(ref e)
+                if e.raw_os_error() == Some(libc::EAGAIN)
+                    || e.raw_os_error() == Some(libc::EACCES) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn fcntl(&self, cmd: libc::c_int, lock_type: libc::c_int) -> io::Result<()> {
+        let mut lock: libc::flock = unsafe { mem::zeroed() };
+        lock.l_type = lock_type as libc::c_short;
+        lock.l_whence = libc::SEEK_SET as libc::c_short;
+        // l_start and l_len of 0 cover the whole file
+        loop {
+            if unsafe { libc::fcntl(self.lock_file.file.as_raw_fd(), cmd, &lock) } == 0 {
+                return Ok(());
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+}
+
+impl<'a> Drop for CoordinatorGuard<'a> {
+    fn drop(&mut self) {
+        let _ = self.coordinator.fcntl(libc::F_SETLK, libc::F_UNLCK);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn exclusive_within_process() {
+        let path = env::temp_dir().join(format!("kstat-coordinator-{}.lock", process::id()));
+        let coordinator = SampleCoordinator::new(&path).expect("failed to open lock file");
+        {
+            let _guard = coordinator.lock().expect("failed to take lock");
+            assert!(coordinator.try_lock().expect("try_lock failed").is_none());
+        }
+        assert!(coordinator.try_lock().expect("try_lock failed").is_some());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn exclusive_between_coordinators() {
+        let path = env::temp_dir().join(format!("kstat-coordinators-{}.lock", process::id()));
+        let first = SampleCoordinator::new(&path).expect("failed to open lock file");
+        let second = SampleCoordinator::new(&path).expect("failed to open lock file");
+        {
+            let _guard = first.lock().expect("failed to take lock");
+            assert!(second.try_lock().expect("try_lock failed").is_none());
+            // Dropping another coordinator must not release the lock
+            drop(SampleCoordinator::new(&path).expect("failed to open lock file"));
+            assert!(second.try_lock().expect("try_lock failed").is_none());
+        }
+        assert!(second.try_lock().expect("try_lock failed").is_some());
+        let _ = fs::remove_file(&path);
+    }
+}
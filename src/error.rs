@@ -0,0 +1,305 @@
+use super::meta::KstatId;
+
+use libc;
+use regex;
+
+use std::error;
+use std::fmt;
+use std::io;
+
+/// The device libkstat opens in `kstat_open()`
+const KSTAT_DEVICE: &str = "/dev/kstat";
+
+/// Why `kstat_open()` failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenFailure {
+    /// the caller is not allowed to open the kstat device
+    PermissionDenied,
+    /// the kstat device does not exist, e.g. in a zone without it
+    MissingDevice,
+    /// the kernel or process ran out of memory or file descriptors
+    ResourceExhausted,
+    /// any other error
+    Other,
+}
+
+impl OpenFailure {
+    fn from_io(e: &io::Error) -> Self {
+        match e.raw_os_error() {
+            Some(libc::EACCES) | Some(libc::EPERM) => OpenFailure::PermissionDenied,
+            Some(libc::ENOENT) | Some(libc::ENXIO) | Some(libc::ENODEV) => {
+                OpenFailure::MissingDevice
+            }
+            Some(libc::EAGAIN) | Some(libc::ENOMEM) | Some(libc::EMFILE) | Some(libc::ENFILE) => {
+                OpenFailure::ResourceExhausted
+            }
+            _ => OpenFailure::Other,
+        }
+    }
+}
+
+impl fmt::Display for OpenFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            OpenFailure::PermissionDenied => "permission denied",
+            OpenFailure::MissingDevice => "device missing",
+            OpenFailure::ResourceExhausted => "resources exhausted",
+            OpenFailure::Other => "unexpected error",
+        };
+        f.write_str(s)
+    }
+}
+
+/// The errors produced by this crate.
+///
+/// Functions in this crate return `io::Error` so that callers can keep using `?` with other I/O,
+/// but every error that originates here carries an `Error` describing what failed, which can be
+/// recovered with `Error::from_io`. The `io::ErrorKind` of the wrapper matches the underlying OS
+/// error.
+///
+/// # Example
+/// ```
+/// match kstat::KstatReader::new().and_then(|r| r.read()) {
+///     Ok(stats) => println!("read {} kstats", stats.len()),
+///     Err(e) => match kstat::Error::from_io(&e) {
+///         Some(&kstat::Error::ReadFailed { ref module, ref name, .. }) => {
+///             eprintln!("failed to read {}:{}: {}", module, name, e)
+///         }
+///         _ => eprintln!("{}", e),
+///     },
+/// }
+/// ```
+#[derive(Debug)]
+pub enum Error {
+    /// `kstat_open()` failed
+    OpenFailed {
+        /// the broad category of the failure
+        reason: OpenFailure,
+        /// the zone we are running in
+        zone: String,
+        /// the underlying OS error
+        source: io::Error,
+    },
+    /// `kstat_chain_update()` failed
+    ChainUpdateFailed {
+        /// the underlying OS error
+        source: io::Error,
+    },
+    /// `kstat_read()` failed for a single kstat
+    ReadFailed {
+        /// module of the kstat
+        module: String,
+        /// instance of the kstat
+        instance: i32,
+        /// name of the kstat
+        name: String,
+        /// the underlying OS error
+        source: io::Error,
+    },
+    /// The data section of a kstat could not be decoded
+    MalformedData {
+        /// module of the kstat
+        module: String,
+        /// instance of the kstat
+        instance: i32,
+        /// name of the kstat
+        name: String,
+        /// what was wrong with the data section
+        reason: String,
+    },
+    /// A named statistic has a data type this crate cannot decode. Reading a kstat leaves such
+    /// statistics out and lists them in `KstatData::unsupported` instead.
+    UnsupportedType {
+        /// module of the kstat
+        module: String,
+        /// instance of the kstat
+        instance: i32,
+        /// name of the kstat
+        name: String,
+        /// name of the statistic
+        statistic: String,
+        /// the statistic's `data_type`
+        data_type: u8,
+    },
+    /// A `Matcher` pattern could not be compiled
+    InvalidFilter {
+        /// the pattern
+        pattern: String,
+        /// why it failed to compile
+        source: regex::Error,
+    },
+    /// A collection loop was asked to run with a zero interval
+    ZeroInterval,
+}
+
+impl Error {
+    /// Returns the `Error` carried by an `io::Error` returned from this crate, if any
+    pub fn from_io(e: &io::Error) -> Option<&Error> {
+        e.get_ref().and_then(|inner| inner.downcast_ref::<Error>())
+    }
+
+    /// Returns the identity of the kstat the error is about, if it is about a single kstat
+    pub fn id(&self) -> Option<KstatId> {
+        match *self {
+            Error::ReadFailed {
+                ref module,
+                instance,
+                ref name,
+                ..
+            }
+            | Error::MalformedData {
+                ref module,
+                instance,
+                ref name,
+                ..
+            }
+            | Error::UnsupportedType {
+                ref module,
+                instance,
+                ref name,
+                ..
+            } => Some(KstatId {
+                module: module.clone(),
+                instance,
+                name: name.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns the OS error code behind the error, if there is one
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match *self {
+            Error::OpenFailed { ref source, .. }
+            | Error::ChainUpdateFailed { ref source }
+            | Error::ReadFailed { ref source, .. } => source.raw_os_error(),
+            Error::MalformedData { .. }
+            | Error::UnsupportedType { .. }
+            | Error::InvalidFilter { .. }
+            | Error::ZeroInterval => None,
+        }
+    }
+
+    pub(crate) fn open_failed(source: io::Error, zone: String) -> Self {
+        Error::OpenFailed {
+            reason: OpenFailure::from_io(&source),
+            zone,
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::OpenFailed {
+                reason,
+                ref zone,
+                ref source,
+            } => write!(
+                f,
+                "failed to open {} in zone {} ({}): {}",
+                KSTAT_DEVICE, zone, reason, source
+            ),
+            Error::ChainUpdateFailed { ref source } => {
+                write!(f, "failed to update kstat chain: {}", source)
+            }
+            Error::ReadFailed {
+                ref module,
+                instance,
+                ref name,
+                ref source,
+            } => write!(
+                f,
+                "failed to read {}:{}:{}: {}",
+                module, instance, name, source
+            ),
+            Error::MalformedData {
+                ref module,
+                instance,
+                ref name,
+                ref reason,
+            } => write!(
+                f,
+                "malformed kstat {}:{}:{}: {}",
+                module, instance, name, reason
+            ),
+            Error::UnsupportedType {
+                ref module,
+                instance,
+                ref name,
+                ref statistic,
+                data_type,
+            } => write!(
+                f,
+                "unsupported data type {} of {}:{}:{}:{}",
+                data_type, module, instance, name, statistic
+            ),
+            Error::InvalidFilter {
+                ref pattern,
+                ref source,
+            } => write!(f, "invalid filter {}: {}", pattern, source),
+            Error::ZeroInterval => f.write_str("interval must be greater than zero"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::OpenFailed { ref source, .. }
+            | Error::ChainUpdateFailed { ref source }
+            | Error::ReadFailed { ref source, .. } => Some(source),
+            Error::InvalidFilter { ref source, .. } => Some(source),
+            Error::MalformedData { .. } | Error::UnsupportedType { .. } | Error::ZeroInterval => {
+                None
+            }
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        let kind = match e {
+            Error::OpenFailed { ref source, .. }
+            | Error::ChainUpdateFailed { ref source }
+            | Error::ReadFailed { ref source, .. } => source.kind(),
+            Error::MalformedData { .. } | Error::UnsupportedType { .. } => {
+                io::ErrorKind::InvalidData
+            }
+            Error::InvalidFilter { .. } | Error::ZeroInterval => io::ErrorKind::InvalidInput,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// Returns the OS error code behind an error returned from this crate
+pub(crate) fn raw_os_error(e: &io::Error) -> Option<i32> {
+    e.raw_os_error()
+        .or_else(|| Error::from_io(e).and_then(Error::raw_os_error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrapped_in_io_error() {
+        let e: io::Error = Error::ReadFailed {
+            module: "zfs".to_string(),
+            instance: 0,
+            name: "arcstats".to_string(),
+            source: io::Error::from_raw_os_error(libc::ENXIO),
+        }
+        .into();
+
+        assert_eq!(raw_os_error(&e), Some(libc::ENXIO));
+        let id = Error::from_io(&e)
+            .and_then(Error::id)
+            .expect("expected a kstat id");
+        assert_eq!(
+            (id.module.as_str(), id.instance, id.name.as_str()),
+            ("zfs", 0, "arcstats")
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Serialize kstat snapshots as InfluxDB line protocol.
+//!
+//! Every kstat becomes one line with a measurement of `module:name`, `instance`, `class`, and
+//! optionally `host` tags, one field per statistic, and a nanosecond timestamp derived from the
+//! kstat's snaptime. Tags with an empty value are left out, since line protocol does not allow
+//! them. With an `IdentityResolver`, kstats of a known device or zone are tagged with its serial
+//! number or zone name instead of their instance, so their series survive renumbering.
+
+use helpers::identity::IdentityResolver;
+use hrtime;
+use kstat_named::KstatNamedData;
+use {Data, KstatData};
+
+use std::io::{self, Write};
+
+/// Writes kstat data as InfluxDB line protocol to any `io::Write`, such as a socket connected to
+/// Telegraf's socket listener.
+///
+/// # Example
+/// ```
+/// let reader = kstat::KstatReader::new().expect("failed to create kstat reader");
+/// let stats = reader.read().expect("failed to read kstats");
+///
+/// let stdout = std::io::stdout();
+/// let mut influx = kstat::exporters::influx::InfluxWriter::new(stdout.lock());
+/// influx.host("myhost");
+/// influx.write(&stats).expect("failed to write line protocol");
+/// ```
+#[derive(Debug)]
+pub struct InfluxWriter<W: Write> {
+    writer: W,
+    host: Option<String>,
+    identities: Option<IdentityResolver>,
+}
+
+impl<W: Write> InfluxWriter<W> {
+    /// Returns an `InfluxWriter` that writes to `writer`
+    pub fn new(writer: W) -> Self {
+        InfluxWriter {
+            writer,
+            host: None,
+            identities: None,
+        }
+    }
+
+    /// Add a `host` tag to every line
+    pub fn host<S: Into<String>>(&mut self, host: S) -> &mut Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Tag the kstats that `resolver` knows the identity of with a `serial` or `zone` tag in
+    /// place of `instance`
+    pub fn identities(&mut self, resolver: IdentityResolver) -> &mut Self {
+        self.identities = Some(resolver);
+        self
+    }
+
+    /// Write one line per kstat in `data`. Kstats without any statistics are skipped since a
+    /// line must have at least one field.
+    pub fn write(&mut self, data: &[KstatData]) -> io::Result<()> {
+        let boot = hrtime::boot_time_nanos();
+        for kstat in data {
+            let tags = Tags {
+                host: self.host.as_deref(),
+                identities: self.identities.as_ref(),
+            };
+            if let Some(line) = format_line(kstat, &tags, boot) {
+                writeln!(self.writer, "{}", line)?;
+            }
+        }
+        self.writer.flush()
+    }
+
+    /// Consume the `InfluxWriter`, returning the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// The tags added to every line besides those of the kstat itself
+#[derive(Default)]
+struct Tags<'a> {
+    host: Option<&'a str>,
+    identities: Option<&'a IdentityResolver>,
+}
+
+fn format_line(kstat: &KstatData, tags: &Tags, boot: i64) -> Option<String> {
+    let fields = fields(kstat);
+    if fields.is_empty() {
+        return None;
+    }
+
+    let mut line = series_key(kstat, tags);
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect();
+    line.push(' ');
+    line.push_str(&fields.join(","));
+    line.push_str(&format!(" {}", boot + kstat.snaptime));
+
+    Some(line)
+}
+
+/// Returns the series `kstat` is written to, one per field, each as its series key followed by a
+/// space and the field key
+pub(crate) fn series(kstat: &KstatData) -> Vec<String> {
+    let key = series_key(kstat, &Tags::default());
+    fields(kstat)
+        .into_iter()
+        .map(|(k, _)| format!("{} {}", key, k))
+        .collect()
+}
+
+/// The measurement and tag set of `kstat`'s line
+fn series_key(kstat: &KstatData, tags: &Tags) -> String {
+    let mut key = escape_measurement(&format!("{}:{}", kstat.module, kstat.name));
+    if !kstat.class.is_empty() {
+        key.push_str(&format!(",class={}", escape_key(&kstat.class)));
+    }
+    if let Some(h) = tags.host.filter(|h| !h.is_empty()) {
+        key.push_str(&format!(",host={}", escape_key(h)));
+    }
+    match tags.identities.and_then(|ids| ids.resolve(kstat)) {
+        Some(id) => key.push_str(&format!(",{}={}", id.label(), escape_key(id.value()))),
+        None => key.push_str(&format!(",instance={}", kstat.instance)),
+    }
+    key
+}
+
+/// The escaped key and formatted value of every field of `kstat`'s line, sorted by key
+fn fields(kstat: &KstatData) -> Vec<(String, String)> {
+    let mut fields = match kstat.data {
+        Data::Named(ref named) => named
+            .iter()
+            .map(|(k, v)| (escape_key(k), format_value(v)))
+            .collect::<Vec<_>>(),
+        Data::Io(ref io) => vec![
+            ("nread".to_string(), format!("{}u", io.nread)),
+            ("nwritten".to_string(), format!("{}u", io.nwritten)),
+            ("reads".to_string(), format!("{}u", io.reads)),
+            ("writes".to_string(), format!("{}u", io.writes)),
+            ("wtime".to_string(), format!("{}i", io.wtime)),
+            ("wlentime".to_string(), format!("{}i", io.wlentime)),
+            ("wlastupdate".to_string(), format!("{}i", io.wlastupdate)),
+            ("rtime".to_string(), format!("{}i", io.rtime)),
+            ("rlentime".to_string(), format!("{}i", io.rlentime)),
+            ("rlastupdate".to_string(), format!("{}i", io.rlastupdate)),
+            ("wcnt".to_string(), format!("{}u", io.wcnt)),
+            ("rcnt".to_string(), format!("{}u", io.rcnt)),
+        ],
+    };
+    fields.sort();
+    fields
+}
+
+fn format_value(v: &KstatNamedData) -> String {
+    match *v {
+        KstatNamedData::DataChar(_) => format_string(&v.char_str().unwrap_or_default()),
+        KstatNamedData::DataInt32(i) => format!("{}i", i),
+        KstatNamedData::DataUInt32(u) => format!("{}u", u),
+        KstatNamedData::DataInt64(i) => format!("{}i", i),
+        KstatNamedData::DataUInt64(u) => format!("{}u", u),
+        KstatNamedData::DataString(ref s) => format_string(s),
+    }
+}
+
+/// A newline would end the line, so it is written as `\n` like the other escapes
+fn format_string(s: &str) -> String {
+    let escaped = s.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped.replace('\n', "\\n"))
+}
+
+fn escape_measurement(s: &str) -> String {
+    s.replace(',', "\\,")
+        .replace(' ', "\\ ")
+        .replace('\n', "\\n")
+}
+
+fn escape_key(s: &str) -> String {
+    s.replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+    use std::slice;
+
+    #[test]
+    fn named_line() {
+        let stats = vec![
+            ("Soft Errors", KstatNamedData::DataUInt32(1)),
+            ("Product", KstatNamedData::DataString("a \"b\"".to_string())),
+        ];
+        let kstat = KstatData {
+            class: "device_error".to_string(),
+            snaptime: 5,
+            ..kstat("sderr", 0, "sd0,err", stats)
+        };
+        let host = Tags {
+            host: Some("myhost"),
+            ..Tags::default()
+        };
+        assert_eq!(
+            format_line(&kstat, &host, 1_000).unwrap(),
+            "sderr:sd0\\,err,class=device_error,host=myhost,instance=0 \
+             Product=\"a \\\"b\\\"\",Soft\\ Errors=1u 1005"
+        );
+    }
+
+    #[test]
+    fn empty_class_and_newlines() {
+        let stats = vec![("motd", KstatNamedData::DataString("a\nb".to_string()))];
+        let kstat = KstatData {
+            class: String::new(),
+            snaptime: 5,
+            ..kstat("unix", 0, "motd", stats)
+        };
+        let line = format_line(&kstat, &Tags::default(), 1_000).unwrap();
+        assert_eq!(line, "unix:motd,instance=0 motd=\"a\\nb\" 1005");
+        assert!(!line.contains('\n'));
+    }
+
+    #[test]
+    fn identity_tags() {
+        let device = |serial: &str| KstatData {
+            class: "device_error".to_string(),
+            ..kstat(
+                "sderr",
+                0,
+                "sd0,err",
+                vec![("Serial No", KstatNamedData::DataString(serial.to_string()))],
+            )
+        };
+        let line = |kstat: &KstatData| {
+            let ids = IdentityResolver::from_kstats(slice::from_ref(kstat));
+            let tags = Tags {
+                identities: Some(&ids),
+                ..Tags::default()
+            };
+            format_line(kstat, &tags, 1_000).unwrap()
+        };
+
+        let serial = line(&device("S3Z 1"));
+        assert!(serial.starts_with("sderr:sd0\\,err,class=device_error,serial=S3Z\\ 1 "));
+        // Without a serial number the device keeps its instance
+        assert!(line(&device("  ")).contains(",instance=0 "));
+    }
+}
@@ -0,0 +1,10 @@
+//! Exporters that send or serialize kstat snapshots in the formats used by common metrics
+//! pipelines.
+//!
+//! Every exporter can render to an `io::Write` instead of a real backend, which is handy for
+//! checking metric names and labels: `InfluxWriter` always writes to one, and
+//! `StatsdExporter::preview` writes the lines it would otherwise send.
+
+pub mod influx;
+#[cfg(feature = "statsd")]
+pub mod statsd;
@@ -0,0 +1,378 @@
+//! Emit kstat snapshots as statsd metrics over UDP.
+//!
+//! Absolute values are sent as gauges and `KstatDelta`s as counters. Tags use the DogStatsD
+//! `|#key:value` extension; when tags are disabled the instance is folded into the metric name
+//! instead.
+//!
+//! `StatsdExporter::preview` renders the same lines to a writer instead of a socket, one metric
+//! per line, so names and tags can be checked before pointing an agent at a real server.
+
+use sampler::numeric_values;
+use {KstatData, KstatDelta};
+
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+
+/// Keep packets under the typical 1500 byte MTU once IP and UDP headers are added
+const MAX_PACKET_SIZE: usize = 1432;
+
+/// Sends kstat data to a statsd server
+///
+/// # Example
+/// ```
+/// let reader = kstat::KstatReader::new().expect("failed to create kstat reader");
+/// let stats = reader.read().expect("failed to read kstats");
+///
+/// let mut statsd = kstat::exporters::statsd::StatsdExporter::new("127.0.0.1:8125")
+///     .expect("failed to create statsd exporter");
+/// statsd.prefix("kstat").tags(true);
+/// statsd.send_gauges(&stats).expect("failed to send gauges");
+/// ```
+#[derive(Debug)]
+pub struct StatsdExporter {
+    sink: Sink,
+    prefix: Option<String>,
+    tags: bool,
+}
+
+impl StatsdExporter {
+    /// Returns a `StatsdExporter` that sends to the statsd server at `addr`, which may be an
+    /// IPv4 or IPv6 address. If `addr` resolves to several addresses, the first is used.
+    pub fn new<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "statsd address resolved to nothing",
+            )
+        })?;
+        let local = if addr.is_ipv6() {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        Ok(StatsdExporter {
+            sink: Sink::Socket(socket),
+            prefix: None,
+            tags: false,
+        })
+    }
+
+    /// Returns a `StatsdExporter` that writes every metric to `writer`, one per line, instead of
+    /// sending it, e.g. to preview the output on stdout
+    ///
+    /// # Example
+    /// ```
+    /// let reader = kstat::KstatReader::new().expect("failed to create kstat reader");
+    /// let stats = reader.read().expect("failed to read kstats");
+    ///
+    /// let mut statsd = kstat::exporters::statsd::StatsdExporter::preview(std::io::stdout());
+    /// statsd.prefix("kstat").tags(true);
+    /// statsd.send_gauges(&stats).expect("failed to write gauges");
+    /// ```
+    pub fn preview<W: Write + Send + 'static>(writer: W) -> Self {
+        StatsdExporter {
+            sink: Sink::Preview(Mutex::new(Box::new(writer))),
+            prefix: None,
+            tags: false,
+        }
+    }
+
+    /// Set a prefix that is prepended to every metric name
+    pub fn prefix<S: Into<String>>(&mut self, prefix: S) -> &mut Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Tag every metric with its kstat's module, instance, and class
+    pub fn tags(&mut self, enabled: bool) -> &mut Self {
+        self.tags = enabled;
+        self
+    }
+
+    /// Send every numeric statistic in `data` as a gauge, the statistics of each kstat sorted by
+    /// name
+    pub fn send_gauges(&self, data: &[KstatData]) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for kstat in data {
+            let id = Id::new(&kstat.module, kstat.instance, &kstat.name, &kstat.class);
+            let mut values: Vec<_> = numeric_values(&kstat.data).into_iter().collect();
+            values.sort();
+            for (stat, value) in values {
+                lines.push(self.line(&id, &stat, &value.to_string(), "g"));
+            }
+        }
+        self.send(&lines)
+    }
+
+    /// Send the change of every statistic in `deltas` as a counter, the statistics of each kstat
+    /// sorted by name. Statsd counters only go up, so statistics that went down, e.g. gauges or
+    /// counters that were reset, are skipped.
+    pub fn send_counters(&self, deltas: &[KstatDelta]) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for kstat in deltas {
+            let id = Id::new(&kstat.module, kstat.instance, &kstat.name, &kstat.class);
+            let mut stats: Vec<_> = kstat.stats.iter().collect();
+            stats.sort_by(|a, b| a.0.cmp(b.0));
+            for (stat, delta) in stats.into_iter().filter(|(_, d)| d.delta >= 0) {
+                lines.push(self.line(&id, stat, &delta.delta.to_string(), "c"));
+            }
+        }
+        self.send(&lines)
+    }
+
+    fn line(&self, id: &Id, stat: &str, value: &str, kind: &str) -> String {
+        format_line(self.prefix.as_deref(), self.tags, id, stat, value, kind)
+    }
+
+    fn send(&self, lines: &[String]) -> io::Result<()> {
+        match self.sink {
+            Sink::Socket(ref socket) => send_packets(socket, lines),
+            Sink::Preview(ref writer) => {
+                let mut writer = writer.lock().unwrap_or_else(|e| e.into_inner());
+                for line in lines {
+                    writeln!(writer, "{}", line)?;
+                }
+                writer.flush()
+            }
+        }
+    }
+}
+
+/// Where a `StatsdExporter` sends its lines
+enum Sink {
+    Socket(UdpSocket),
+    Preview(Mutex<Box<dyn Write + Send>>),
+}
+
+impl fmt::Debug for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Sink::Socket(ref socket) => f.debug_tuple("Socket").field(socket).finish(),
+            Sink::Preview(_) => f.write_str("Preview"),
+        }
+    }
+}
+
+/// Pack as many newline separated lines into each datagram as will fit
+fn send_packets(socket: &UdpSocket, lines: &[String]) -> io::Result<()> {
+    let mut packet = String::with_capacity(MAX_PACKET_SIZE);
+    for line in lines {
+        if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET_SIZE {
+            socket.send(packet.as_bytes())?;
+            packet.clear();
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(line);
+    }
+    if !packet.is_empty() {
+        socket.send(packet.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// The identifying fields of the kstat a metric came from
+struct Id<'a> {
+    module: &'a str,
+    instance: i32,
+    name: &'a str,
+    class: &'a str,
+}
+
+impl<'a> Id<'a> {
+    fn new(module: &'a str, instance: i32, name: &'a str, class: &'a str) -> Self {
+        Id {
+            module,
+            instance,
+            name,
+            class,
+        }
+    }
+}
+
+/// Returns the series every numeric statistic of `kstat` is sent as, each as its metric name
+/// followed by its tags, if any
+pub(crate) fn series(kstat: &KstatData, tags: bool) -> Vec<String> {
+    let id = Id::new(&kstat.module, kstat.instance, &kstat.name, &kstat.class);
+    numeric_values(&kstat.data)
+        .keys()
+        .map(|stat| {
+            let mut key = metric_name(None, tags, &id, stat);
+            if tags {
+                key.push_str(&format_tags(&id));
+            }
+            key
+        })
+        .collect()
+}
+
+fn format_line(
+    prefix: Option<&str>,
+    tags: bool,
+    id: &Id,
+    stat: &str,
+    value: &str,
+    kind: &str,
+) -> String {
+    let mut line = format!("{}:{}|{}", metric_name(prefix, tags, id, stat), value, kind);
+    if tags {
+        line.push_str(&format_tags(id));
+    }
+    line
+}
+
+/// The dotted metric name, which includes the instance unless it is sent as a tag
+fn metric_name(prefix: Option<&str>, tags: bool, id: &Id, stat: &str) -> String {
+    let mut parts = Vec::with_capacity(5);
+    if let Some(p) = prefix {
+        parts.push(sanitize(p));
+    }
+    parts.push(sanitize(id.module));
+    if !tags {
+        parts.push(id.instance.to_string());
+    }
+    parts.push(sanitize(id.name));
+    parts.push(sanitize(stat));
+    parts.join(".")
+}
+
+fn format_tags(id: &Id) -> String {
+    format!(
+        "|#module:{},instance:{},class:{}",
+        sanitize(id.module),
+        id.instance,
+        sanitize(id.class)
+    )
+}
+
+/// Replace the characters that are significant in the statsd wire format
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            ':' | '|' | '@' | '#' | ',' | '.' | ' ' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+    use kstat_named::KstatNamedData;
+    use sampler::StatDelta;
+    use std::collections::HashMap;
+
+    #[test]
+    fn gauge_without_tags() {
+        let id = Id::new("zone_vfs", 3, "global", "zone_vfs");
+        assert_eq!(
+            format_line(Some("kstat"), false, &id, "nread", "42", "g"),
+            "kstat.zone_vfs.3.global.nread:42|g"
+        );
+    }
+
+    #[test]
+    fn counter_with_tags() {
+        let id = Id::new("sd", 0, "sd0,err", "device_error");
+        assert_eq!(
+            format_line(None, true, &id, "Soft Errors", "1", "c"),
+            "sd.sd0_err.Soft_Errors:1|c|#module:sd,instance:0,class:device_error"
+        );
+    }
+
+    /// A writer whose output can still be inspected once it has been boxed
+    #[derive(Clone, Default)]
+    struct Shared(::std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn preview_writes_lines() {
+        let out = Shared::default();
+        let mut statsd = StatsdExporter::preview(out.clone());
+        statsd.prefix("kstat");
+        let lines = vec!["a:1|g".to_string(), "b:2|g".to_string()];
+        statsd.send(&lines).unwrap();
+        assert_eq!(&*out.0.lock().unwrap(), b"a:1|g\nb:2|g\n");
+    }
+
+    #[test]
+    fn gauges_sorted_by_stat() {
+        let stats = ["writes", "nread", "reads"]
+            .iter()
+            .enumerate()
+            .map(|(i, &stat)| (stat, KstatNamedData::DataUInt64(i as u64)))
+            .collect();
+        let kstat = kstat("zfs", 0, "tank", stats);
+
+        let out = Shared::default();
+        let statsd = StatsdExporter::preview(out.clone());
+        statsd.send_gauges(&[kstat]).unwrap();
+        assert_eq!(
+            String::from_utf8(out.0.lock().unwrap().clone()).unwrap(),
+            "zfs.0.tank.nread:1|g\nzfs.0.tank.reads:2|g\nzfs.0.tank.writes:0|g\n"
+        );
+    }
+
+    #[test]
+    fn negative_counters_skipped() {
+        let mut stats = HashMap::new();
+        stats.insert(
+            "hits".to_string(),
+            StatDelta {
+                delta: 5,
+                rate: 5.0,
+            },
+        );
+        stats.insert(
+            "size".to_string(),
+            StatDelta {
+                delta: -3,
+                rate: -3.0,
+            },
+        );
+        let delta = KstatDelta {
+            class: "misc".to_string(),
+            module: "zfs".to_string(),
+            instance: 0,
+            name: "arcstats".to_string(),
+            interval: 1_000_000_000,
+            stats,
+            warm_up: false,
+            gap: false,
+        };
+
+        let out = Shared::default();
+        let statsd = StatsdExporter::preview(out.clone());
+        statsd.send_counters(&[delta]).unwrap();
+        assert_eq!(&*out.0.lock().unwrap(), b"zfs.0.arcstats.hits:5|c\n");
+    }
+
+    #[test]
+    fn binds_to_the_target_family() {
+        let v6 = match UdpSocket::bind("[::1]:0") {
+            Ok(server) => server,
+            // No IPv6 loopback to test against
+            Err(_) => return,
+        };
+        let statsd = StatsdExporter::new(v6.local_addr().unwrap()).unwrap();
+        match statsd.sink {
+            Sink::Socket(ref socket) => assert!(socket.local_addr().unwrap().is_ipv6()),
+            Sink::Preview(_) => panic!("expected a socket"),
+        }
+    }
+}
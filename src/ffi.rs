@@ -1,13 +1,26 @@
 use byteorder::{ByteOrder, NativeEndian};
-use libc::{c_char, c_int, c_longlong, c_uchar, c_uint, c_void, size_t};
+use libc::{c_char, c_int, c_longlong, c_uchar, c_uint, c_void, size_t, ssize_t};
 use std::borrow::Cow;
-use std::ffi::CStr;
+use std::slice;
 
+pub const KSTAT_TYPE_RAW: c_uchar = 0; // can be anything
 pub const KSTAT_TYPE_NAMED: c_uchar = 1; // name/value pair
+pub const KSTAT_TYPE_INTR: c_uchar = 2; // interrupt statistics
 pub const KSTAT_TYPE_IO: c_uchar = 3; // I/O statistics
+pub const KSTAT_TYPE_TIMER: c_uchar = 4; // event timer
+
+pub const KSTAT_FLAG_VIRTUAL: c_uchar = 0x01; // ks_data points to storage owned by the provider
+pub const KSTAT_FLAG_VAR_SIZE: c_uchar = 0x02; // the data section size may change
+pub const KSTAT_FLAG_WRITABLE: c_uchar = 0x04; // the kstat can be written to
+pub const KSTAT_FLAG_PERSISTENT: c_uchar = 0x08; // the kstat survives its provider going away
+pub const KSTAT_FLAG_DORMANT: c_uchar = 0x10; // a persistent kstat whose provider went away
+pub const KSTAT_FLAG_INVALID: c_uchar = 0x20; // the kstat is being initialized
+pub const KSTAT_FLAG_LONGSTRINGS: c_uchar = 0x40; // the kstat has KSTAT_DATA_STRING values
 
 pub const KSTAT_STRLEN: usize = 31; // 30 chars + NULL; must be 16 * n - 1
 
+pub const ZONENAME_MAX: usize = 64; // max length of a zone name including NULL
+
 pub const KSTAT_DATA_CHAR: c_uchar = 0;
 pub const KSTAT_DATA_INT32: c_uchar = 1;
 pub const KSTAT_DATA_UINT32: c_uchar = 2;
@@ -32,26 +45,23 @@ pub struct kstat_t {
     pub ks_ndata: c_uint,                  // # of type-specific data records
     pub ks_data_size: size_t,              // total size of kstat data section
     pub ks_snaptime: c_longlong,           // time of last data snapshot
-    ks_update: extern "C" fn(kstat: *const kstat_t, c_int) -> c_int, // kernel only
+    ks_update: Option<extern "C" fn(kstat: *const kstat_t, c_int) -> c_int>, // kernel only
     ks_private: *const c_void,             // kernel only
-    ks_snapshot: extern "C" fn(kstat: *const kstat_t, c_int) -> c_int, // kernel only
+    ks_snapshot: Option<extern "C" fn(kstat: *const kstat_t, c_int) -> c_int>, // kernel only
     ks_lock: *const c_void,                // kernel only
 }
 
 impl kstat_t {
-    pub fn get_name(&self) -> Cow<str> {
-        let cstr = unsafe { CStr::from_ptr(self.ks_name.as_ptr()) };
-        cstr.to_string_lossy()
+    pub fn get_name(&self) -> Cow<'_, str> {
+        fixed_str(&self.ks_name)
     }
 
-    pub fn get_class(&self) -> Cow<str> {
-        let cstr = unsafe { CStr::from_ptr(self.ks_class.as_ptr()) };
-        cstr.to_string_lossy()
+    pub fn get_class(&self) -> Cow<'_, str> {
+        fixed_str(&self.ks_class)
     }
 
-    pub fn get_module(&self) -> Cow<str> {
-        let cstr = unsafe { CStr::from_ptr(self.ks_module.as_ptr()) };
-        cstr.to_string_lossy()
+    pub fn get_module(&self) -> Cow<'_, str> {
+        fixed_str(&self.ks_module)
     }
 }
 
@@ -62,6 +72,23 @@ pub struct kstat_ctl_t {
     pub kc_id: c_int,             // /dev/kstat descriptor
 }
 
+#[repr(C)]
+#[derive(Debug)]
+pub struct kstat_io_t {
+    pub nread: u64,        // number of bytes read
+    pub nwritten: u64,     // number of bytes written
+    pub reads: c_uint,     // number of read operations
+    pub writes: c_uint,    // number of write operations
+    pub wtime: i64,        // cumulative wait (pre-service) time
+    pub wlentime: i64,     // cumulative wait length*time product
+    pub wlastupdate: i64,  // last time wait queue changed
+    pub rtime: i64,        // cumulative run (service) time
+    pub rlentime: i64,     // cumulative run length*time product
+    pub rlastupdate: i64,  // last time run queue changed
+    pub wcnt: c_uint,      // count of elements in wait state
+    pub rcnt: c_uint,      // count of elements in run state
+}
+
 #[repr(C)]
 pub struct kstat_named_t {
     pub name: [c_char; KSTAT_STRLEN], // name of counter
@@ -70,12 +97,8 @@ pub struct kstat_named_t {
 }
 
 impl kstat_named_t {
-    pub fn get_name(&self) -> Cow<str> {
-        let cstr = unsafe { CStr::from_ptr(self.name.as_ptr()) };
-        cstr.to_string_lossy()
-    }
-    pub fn value_as_char(&self) -> c_char {
-        c_char::from_le(self.value[0] as i8)
+    pub fn get_name(&self) -> Cow<'_, str> {
+        fixed_str(&self.name)
     }
 
     pub fn value_as_i32(&self) -> i32 {
@@ -94,25 +117,48 @@ impl kstat_named_t {
         NativeEndian::read_u64(&self.value)
     }
 
-    pub fn value_as_string(&self) -> String {
-        let ptr = NativeEndian::read_u64(&self.value);
-        let cstr = unsafe { CStr::from_ptr(ptr as *const c_char) };
-        cstr.to_string_lossy().into_owned()
+    /// Returns at most `max` bytes of the string, and whether it was truncated to fit
+    pub fn value_as_string(&self, max: usize) -> (String, bool) {
+        // struct { union { char *ptr; char __pad[8]; } addr; uint32_t len; } where len counts
+        // the terminating NUL
+        let ptr = NativeEndian::read_u64(&self.value[..8]) as *const u8;
+        let len = NativeEndian::read_u32(&self.value[8..12]) as usize;
+        if ptr.is_null() || len == 0 {
+            return (String::new(), false);
+        }
+        let bytes = unsafe { slice::from_raw_parts(ptr, len) };
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(len);
+        let truncated = end > max;
+        let end = if truncated { max } else { end };
+        (String::from_utf8_lossy(&bytes[..end]).into_owned(), truncated)
     }
 }
 
+/// Decode a fixed size string field. The field is not trusted to be NUL terminated, so the
+/// string ends at the first NUL or the end of the field, whichever comes first.
+fn fixed_str(buf: &[c_char]) -> Cow<'_, str> {
+    let bytes = unsafe { slice::from_raw_parts(buf.as_ptr() as *const u8, buf.len()) };
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end])
+}
+
 #[link(name = "kstat")]
 extern "C" {
     pub fn kstat_open() -> *const kstat_ctl_t;
     pub fn kstat_close(kc: *const kstat_ctl_t) -> c_int;
     pub fn kstat_chain_update(kc: *const kstat_ctl_t) -> c_int;
-    // In case we want this again one day
-    //pub fn kstat_lookup(
-    //    kc: *const kstat_ctl_t,
-    //    ks_module: *const c_char,
-    //    ks_instance: c_int,
-    //    ks_name: *const c_char,
-    //) -> *const kstat_t;
+    pub fn kstat_lookup(
+        kc: *const kstat_ctl_t,
+        ks_module: *const c_char,
+        ks_instance: c_int,
+        ks_name: *const c_char,
+    ) -> *const kstat_t;
     // Marking the buf as const instead of mut because we don't plan on using it in this API
     pub fn kstat_read(kc: *const kstat_ctl_t, ksp: *const kstat_t, buf: *const c_void) -> c_int;
 }
+
+extern "C" {
+    pub fn gethrtime() -> c_longlong;
+    pub fn getzoneid() -> c_int;
+    pub fn getzonenamebyid(id: c_int, buf: *mut c_char, buflen: size_t) -> ssize_t;
+}
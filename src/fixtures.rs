@@ -0,0 +1,50 @@
+//! Kstats built in memory for the unit tests, shared by the library and the `kstat` binary. Only
+//! items from the crate root are used so that the binary can include this file with `#[path]`.
+
+use super::{Data, KstatData, KstatFlags, KstatIoData, KstatNamedData};
+
+/// A KSTAT_TYPE_NAMED kstat of class `misc` holding `stats`
+pub fn kstat(
+    module: &str,
+    instance: i32,
+    name: &str,
+    stats: Vec<(&str, KstatNamedData)>,
+) -> KstatData {
+    let named = stats.into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+    with_data(module, instance, name, "misc", Data::Named(named))
+}
+
+/// A KSTAT_TYPE_IO kstat of class `disk` whose I/O statistics are all zero
+pub fn io(module: &str, instance: i32, name: &str) -> KstatData {
+    let io = KstatIoData {
+        nread: 0,
+        nwritten: 0,
+        reads: 0,
+        writes: 0,
+        wtime: 0,
+        wlentime: 0,
+        wlastupdate: 0,
+        rtime: 0,
+        rlentime: 0,
+        rlastupdate: 0,
+        wcnt: 0,
+        rcnt: 0,
+    };
+    with_data(module, instance, name, "disk", Data::Io(io))
+}
+
+fn with_data(module: &str, instance: i32, name: &str, class: &str, data: Data) -> KstatData {
+    KstatData {
+        class: class.to_string(),
+        module: module.to_string(),
+        instance,
+        name: name.to_string(),
+        snaptime: 0,
+        crtime: 0,
+        kid: 1,
+        flags: KstatFlags::default(),
+        data,
+        truncated: Vec::new(),
+        unsupported: Vec::new(),
+    }
+}
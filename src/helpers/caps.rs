@@ -0,0 +1,275 @@
+//! Typed access to the per zone CPU cap kstats, `caps:<zoneid>:cpucaps_zone_<zoneid>`, and the
+//! utilization, bursting and throttling numbers derived from them.
+
+use super::{rate, Counter};
+use {KstatData, KstatReader};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+
+/// The decoded `cpucaps_zone_<zoneid>` kstat of a single zone. CPU amounts are in percent of a
+/// single CPU, so a cap of 200 allows two full CPUs.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CpuCap {
+    /// the zone id, which is also the kstat instance
+    pub zoneid: i32,
+    /// the zone name
+    pub zonename: String,
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// the cap
+    pub value: u64,
+    /// the baseline the zone may use without bursting
+    pub baseline: u64,
+    /// the cap currently in effect
+    pub effective: u64,
+    /// how long the zone may burst above its baseline, in seconds
+    pub burst_limit_sec: u64,
+    /// how long the zone has been bursting, in seconds
+    pub bursting_sec: u64,
+    /// current usage
+    pub usage: u64,
+    /// highest usage seen
+    pub maxusage: u64,
+    /// threads currently waiting on the cap
+    pub nwait: u64,
+    /// seconds spent below the cap
+    pub below_sec: Counter,
+    /// seconds spent at or above the cap, i.e. throttled
+    pub above_sec: Counter,
+    /// seconds spent above the baseline
+    pub above_base_sec: Counter,
+}
+
+/// The utilization of a zone's CPU cap between two `CpuCap` snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuCapUsage {
+    /// the zone id
+    pub zoneid: i32,
+    /// the zone name
+    pub zonename: String,
+    /// current usage as a percentage of the cap, or `None` if the zone is uncapped
+    pub cap_utilization_pct: Option<f64>,
+    /// the part of the current usage within the baseline
+    pub baseline_usage: u64,
+    /// the part of the current usage above the baseline
+    pub burst_usage: u64,
+    /// the fraction of the interval spent throttled at the cap, from 0 to 1
+    pub throttled: f64,
+    /// the fraction of the interval spent above the baseline, from 0 to 1
+    pub above_baseline: f64,
+    /// threads waiting on the cap at the end of the interval
+    pub nwait: u64,
+}
+
+impl CpuCap {
+    /// Decode a `cpucaps_zone_<zoneid>` kstat, returning `None` if `data` is not one
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        if data.module != "caps" || !data.name.starts_with("cpucaps_zone_") {
+            return None;
+        }
+        let get = |key| data.get_u64(key).unwrap_or(0);
+        let counter = |key| Counter::of(data, key);
+        Some(CpuCap {
+            zoneid: data.instance,
+            zonename: data.get_str("zonename").unwrap_or_default().to_string(),
+            snaptime: data.snaptime,
+            value: get("value"),
+            baseline: get("baseline"),
+            effective: get("effective"),
+            burst_limit_sec: get("burst_limit_sec"),
+            bursting_sec: get("bursting_sec"),
+            usage: get("usage"),
+            maxusage: get("maxusage"),
+            nwait: get("nwait"),
+            below_sec: counter("below_sec"),
+            above_sec: counter("above_sec"),
+            above_base_sec: counter("above_base_sec"),
+        })
+    }
+
+    /// Compute the cap utilization between `prev` and `self`, returning `None` if they are
+    /// snapshots of different zones
+    pub fn usage_since(&self, prev: &CpuCap) -> Option<CpuCapUsage> {
+        if self.zoneid != prev.zoneid {
+            return None;
+        }
+
+        let interval = self.snaptime - prev.snaptime;
+        // The *_sec counters are in seconds, so their rate is the fraction of time spent there
+        let fraction = |p, c| rate(p, c, interval).min(1.0);
+        let cap_utilization_pct = if self.value == 0 {
+            None
+        } else {
+            Some(self.usage as f64 * 100.0 / self.value as f64)
+        };
+
+        Some(CpuCapUsage {
+            zoneid: self.zoneid,
+            zonename: self.zonename.clone(),
+            cap_utilization_pct,
+            baseline_usage: self.usage.min(self.baseline),
+            burst_usage: self.usage.saturating_sub(self.baseline),
+            throttled: fraction(prev.above_sec, self.above_sec),
+            above_baseline: fraction(prev.above_base_sec, self.above_base_sec),
+            nwait: self.nwait,
+        })
+    }
+}
+
+/// Read the CPU cap of every capped zone, sorted by zone id
+pub fn read() -> io::Result<Vec<CpuCap>> {
+    let reader = KstatReader::builder().module("caps").build()?;
+    let mut ret: Vec<CpuCap> = reader
+        .read()?
+        .iter()
+        .filter_map(CpuCap::from_kstat)
+        .collect();
+    ret.sort_by_key(|c| c.zoneid);
+    Ok(ret)
+}
+
+/// A single point of a zone's `CapSeries`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CapPoint {
+    /// nanoseconds since boot of the snapshot
+    pub snaptime: i64,
+    /// CPU usage in percent of a single CPU
+    pub usage: u64,
+    /// threads waiting on the cap
+    pub nwait: u64,
+    /// the fraction of the time since the previous point spent above the baseline, from 0 to 1
+    pub above_base: f64,
+}
+
+/// The recorded history of a single zone's CPU cap
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapSeries {
+    /// the zone id
+    pub zoneid: i32,
+    /// the zone name
+    pub zonename: String,
+    /// the points, oldest first
+    pub points: VecDeque<CapPoint>,
+    last: CpuCap,
+}
+
+/// Tracks the usage, waiting threads and time above baseline of every capped zone over time, so
+/// that contention and fairness between tenants can be plotted.
+///
+/// # Example
+/// ```
+/// # use std::thread;
+/// # use std::time::Duration;
+/// let mut tracker = kstat::helpers::caps::CpuCapTracker::new(60);
+/// for _ in 0..3 {
+///     tracker.record(&kstat::helpers::caps::read().expect("failed to read cpucaps"));
+///     thread::sleep(Duration::from_secs(1));
+/// }
+/// for series in tracker.series() {
+///     let waits: Vec<u64> = series.points.iter().map(|p| p.nwait).collect();
+///     println!("{}: {:?}", series.zonename, waits);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CpuCapTracker {
+    max_points: usize,
+    zones: BTreeMap<i32, CapSeries>,
+}
+
+impl CpuCapTracker {
+    /// Returns a tracker that keeps the most recent `max_points` points of every zone
+    pub fn new(max_points: usize) -> Self {
+        CpuCapTracker {
+            max_points,
+            zones: BTreeMap::new(),
+        }
+    }
+
+    /// Record a snapshot of the caps of every zone. A zone's first snapshot only establishes a
+    /// baseline. Zones missing from `caps` are forgotten, since they are no longer capped or no
+    /// longer running.
+    pub fn record(&mut self, caps: &[CpuCap]) {
+        let max_points = self.max_points;
+        let mut zones = BTreeMap::new();
+        for cap in caps {
+            let series = match self.zones.remove(&cap.zoneid) {
+                // A zone id can be reused by a different zone once the first one halts
+                Some(mut series) if series.zonename == cap.zonename => {
+                    let interval = cap.snaptime - series.last.snaptime;
+                    if interval > 0 {
+                        series.points.push_back(CapPoint {
+                            snaptime: cap.snaptime,
+                            usage: cap.usage,
+                            nwait: cap.nwait,
+                            above_base: rate(
+                                series.last.above_base_sec,
+                                cap.above_base_sec,
+                                interval,
+                            )
+                            .min(1.0),
+                        });
+                        while series.points.len() > max_points {
+                            series.points.pop_front();
+                        }
+                    }
+                    series.last = cap.clone();
+                    series
+                }
+                _ => CapSeries {
+                    zoneid: cap.zoneid,
+                    zonename: cap.zonename.clone(),
+                    points: VecDeque::new(),
+                    last: cap.clone(),
+                },
+            };
+            zones.insert(cap.zoneid, series);
+        }
+        self.zones = zones;
+    }
+
+    /// Returns the series of a single zone
+    pub fn zone(&self, zoneid: i32) -> Option<&CapSeries> {
+        self.zones.get(&zoneid)
+    }
+
+    /// Returns the series of every tracked zone, ordered by zone id
+    pub fn series(&self) -> impl Iterator<Item = &CapSeries> {
+        self.zones.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cap(snaptime: i64, usage: u64, nwait: u64, above_base_sec: u64) -> CpuCap {
+        CpuCap {
+            zoneid: 3,
+            zonename: "tenant".to_string(),
+            snaptime,
+            usage,
+            nwait,
+            above_base_sec: Counter {
+                value: above_base_sec,
+                ..Counter::default()
+            },
+            ..CpuCap::default()
+        }
+    }
+
+    #[test]
+    fn bounded_series() {
+        let mut tracker = CpuCapTracker::new(2);
+        for i in 0..4 {
+            tracker.record(&[cap(i * 2_000_000_000, 10 * i as u64, i as u64, i as u64)]);
+        }
+        let series = tracker.zone(3).expect("expected a series");
+        assert_eq!(series.points.len(), 2);
+        assert_eq!(series.points[1].usage, 30);
+        assert_eq!(series.points[1].above_base, 0.5);
+
+        tracker.record(&[]);
+        assert!(tracker.zone(3).is_none());
+    }
+}
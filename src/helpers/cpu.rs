@@ -0,0 +1,484 @@
+//! Typed access to the `cpu_info` kstats, and a CPU topology built from them that is used to
+//! aggregate per-CPU kstats such as `cpu:*:sys` by core or chip, and the CPU utilization computed
+//! from successive `cpu:*:sys` snapshots.
+
+use super::named_i64;
+use sampler::numeric_values;
+use {Data, KstatData, KstatReader};
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::time::Duration;
+
+/// The decoded `cpu_info:<cpu>:cpu_info<cpu>` kstat of a single CPU
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuInfo {
+    /// the CPU id, which is also the kstat instance
+    pub cpu_id: i32,
+    /// the processor brand string, e.g. "Intel(r) Xeon(r) CPU E5-2690 v4 @ 2.60GHz"
+    pub brand: String,
+    /// the processor vendor, e.g. "GenuineIntel", where present
+    pub vendor_id: Option<String>,
+    /// the CPU state, e.g. "on-line" or "off-line"
+    pub state: String,
+    /// the nominal clock speed in MHz
+    pub clock_mhz: i64,
+    /// the current clock speed in Hz, where present
+    pub current_clock_hz: Option<i64>,
+    /// the chip (socket) the CPU is on
+    pub chip_id: i64,
+    /// the core the CPU is on; unique across the whole system
+    pub core_id: i64,
+    /// any cache size statistics the platform exposes, keyed by statistic name
+    pub cache_sizes: HashMap<String, i64>,
+}
+
+impl CpuInfo {
+    /// Decode a `cpu_info` kstat, returning `None` if it is missing any required statistic
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        let cache_sizes = match data.data {
+            Data::Named(ref named) => named
+                .keys()
+                .filter(|k| k.contains("cache") && k.contains("size"))
+                .filter_map(|k| named_i64(data, k).map(|v| (k.clone(), v)))
+                .collect(),
+            Data::Io(_) => return None,
+        };
+
+        Some(CpuInfo {
+            cpu_id: data.instance,
+            brand: data.get_str("brand")?.to_string(),
+            vendor_id: data.get_str("vendor_id").map(|s| s.to_string()),
+            state: data.get_str("state")?.to_string(),
+            clock_mhz: named_i64(data, "clock_MHz")?,
+            current_clock_hz: named_i64(data, "current_clock_Hz"),
+            chip_id: named_i64(data, "chip_id")?,
+            core_id: named_i64(data, "core_id")?,
+            cache_sizes,
+        })
+    }
+}
+
+/// Read the `cpu_info` kstat of every CPU, sorted by CPU id
+///
+/// # Example
+/// ```
+/// for cpu in kstat::cpu::info().expect("failed to read cpu_info") {
+///     println!("cpu{}: {} @ {} MHz", cpu.cpu_id, cpu.brand, cpu.clock_mhz);
+/// }
+/// ```
+pub fn info() -> io::Result<Vec<CpuInfo>> {
+    let reader = KstatReader::builder().module("cpu_info").build()?;
+    let mut ret: Vec<CpuInfo> = reader
+        .read()?
+        .iter()
+        .filter_map(CpuInfo::from_kstat)
+        .collect();
+    ret.sort_by_key(|c| c.cpu_id);
+    Ok(ret)
+}
+
+/// The power management state of a single CPU, decoded from its `cpu_info` kstat
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuPower {
+    /// the CPU id, which is also the kstat instance
+    pub cpu_id: i32,
+    /// the current clock speed in Hz
+    pub current_clock_hz: u64,
+    /// the clock speeds the CPU can run at in Hz, in the order the kernel lists them
+    pub supported_frequencies_hz: Vec<u64>,
+    /// the current C-state, where present
+    pub current_cstate: Option<i64>,
+    /// the deepest supported C-state, where present
+    pub supported_max_cstates: Option<i64>,
+}
+
+impl CpuPower {
+    /// Decode the power state from a `cpu_info` kstat, returning `None` if the platform does not
+    /// expose the current clock speed
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        let supported_frequencies_hz = data
+            .get_str("supported_frequencies_Hz")
+            .map(|s| s.split(':').filter_map(|f| f.parse().ok()).collect())
+            .unwrap_or_default();
+
+        Some(CpuPower {
+            cpu_id: data.instance,
+            current_clock_hz: named_i64(data, "current_clock_Hz")? as u64,
+            supported_frequencies_hz,
+            current_cstate: named_i64(data, "current_cstate"),
+            supported_max_cstates: named_i64(data, "supported_max_cstates"),
+        })
+    }
+}
+
+/// Read the power management state of every CPU, sorted by CPU id
+pub fn power() -> io::Result<Vec<CpuPower>> {
+    let reader = KstatReader::builder().module("cpu_info").build()?;
+    let mut ret: Vec<CpuPower> = reader
+        .read()?
+        .iter()
+        .filter_map(CpuPower::from_kstat)
+        .collect();
+    ret.sort_by_key(|c| c.cpu_id);
+    Ok(ret)
+}
+
+/// Estimates how long each CPU spends at each clock speed. The kernel does not keep time in
+/// state counters, so residency is accumulated by attributing the interval between samples to
+/// the clock speed seen at each sample; the estimate gets better as the interval shrinks.
+///
+/// # Example
+/// ```
+/// # use std::thread;
+/// # use std::time::Duration;
+/// let mut residency = kstat::cpu::FrequencyResidency::new();
+/// for _ in 0..10 {
+///     let power = kstat::cpu::power().expect("failed to read cpu_info");
+///     residency.record(&power, Duration::from_millis(100));
+///     thread::sleep(Duration::from_millis(100));
+/// }
+/// println!("{:?}", residency.distribution(0));
+/// ```
+#[derive(Debug, Default)]
+pub struct FrequencyResidency {
+    cpus: HashMap<i32, BTreeMap<u64, Duration>>,
+}
+
+impl FrequencyResidency {
+    /// Returns an empty `FrequencyResidency`
+    pub fn new() -> Self {
+        FrequencyResidency::default()
+    }
+
+    /// Attribute `interval` to the current clock speed of every CPU in `sample`
+    pub fn record(&mut self, sample: &[CpuPower], interval: Duration) {
+        for cpu in sample {
+            let time = self
+                .cpus
+                .entry(cpu.cpu_id)
+                .or_default()
+                .entry(cpu.current_clock_hz)
+                .or_default();
+            *time += interval;
+        }
+    }
+
+    /// Returns the time recorded at each clock speed for `cpu`
+    pub fn residency(&self, cpu: i32) -> Option<&BTreeMap<u64, Duration>> {
+        self.cpus.get(&cpu)
+    }
+
+    /// Returns the fraction of recorded time `cpu` spent at each clock speed, in ascending order
+    /// of clock speed
+    pub fn distribution(&self, cpu: i32) -> Vec<(u64, f64)> {
+        let residency = match self.cpus.get(&cpu) {
+            Some(r) => r,
+            None => return Vec::new(),
+        };
+        let secs = |d: &Duration| d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1e9;
+        let total: f64 = residency.values().map(secs).sum();
+        if total == 0.0 {
+            return Vec::new();
+        }
+        residency
+            .iter()
+            .map(|(&hz, d)| (hz, secs(d) / total))
+            .collect()
+    }
+}
+
+/// Where a CPU lives in the system
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuLocation {
+    /// the chip (socket) the CPU is on
+    pub chip_id: i64,
+    /// the core the CPU is on; unique across the whole system
+    pub core_id: i64,
+}
+
+/// The level at which per-CPU stats are aggregated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuGrouping {
+    /// One group per CPU (hardware thread)
+    Cpu,
+    /// One group per core
+    Core,
+    /// One group per chip, which on illumos corresponds to a socket
+    Chip,
+    /// A single group for the whole host
+    Host,
+}
+
+/// The key of a group of CPUs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CpuGroup {
+    /// A single CPU by id
+    Cpu(i32),
+    /// A core by core_id
+    Core(i64),
+    /// A chip by chip_id
+    Chip(i64),
+    /// Every CPU in the host
+    Host,
+}
+
+/// The sum of each statistic over a group of CPUs
+#[derive(Debug)]
+pub struct CpuAggregate {
+    /// the group these stats belong to
+    pub group: CpuGroup,
+    /// the CPU ids that were summed
+    pub cpus: Vec<i32>,
+    /// A hashmap of statistic name to its sum over `cpus`
+    pub stats: HashMap<String, i64>,
+}
+
+/// A map of CPU id to its location in the chip/core hierarchy
+#[derive(Debug, Default)]
+pub struct CpuTopology {
+    cpus: HashMap<i32, CpuLocation>,
+}
+
+impl CpuTopology {
+    /// Read the topology from the `cpu_info` kstats
+    pub fn read() -> io::Result<Self> {
+        let reader = KstatReader::builder().module("cpu_info").build()?;
+        Ok(CpuTopology::from_cpu_info(&reader.read()?))
+    }
+
+    /// Build the topology from already read `cpu_info` kstats
+    pub fn from_cpu_info(data: &[KstatData]) -> Self {
+        let cpus = data
+            .iter()
+            .filter(|k| k.module == "cpu_info")
+            .filter_map(|k| {
+                let chip_id = named_i64(k, "chip_id")?;
+                let core_id = named_i64(k, "core_id")?;
+                Some((k.instance, CpuLocation { chip_id, core_id }))
+            })
+            .collect();
+        CpuTopology { cpus }
+    }
+
+    /// Returns the location of `cpu`, if it is known
+    pub fn location(&self, cpu: i32) -> Option<CpuLocation> {
+        self.cpus.get(&cpu).cloned()
+    }
+
+    /// Returns the group `cpu` belongs to at the given level, if it is known
+    pub fn group(&self, cpu: i32, by: CpuGrouping) -> Option<CpuGroup> {
+        match by {
+            CpuGrouping::Cpu => Some(CpuGroup::Cpu(cpu)),
+            CpuGrouping::Host => Some(CpuGroup::Host),
+            CpuGrouping::Core => self.location(cpu).map(|l| CpuGroup::Core(l.core_id)),
+            CpuGrouping::Chip => self.location(cpu).map(|l| CpuGroup::Chip(l.chip_id)),
+        }
+    }
+
+    /// Sum the numeric statistics of per-CPU kstats, whose instance is the CPU id, over each
+    /// group. CPUs missing from the topology are left out. The result is sorted by group.
+    pub fn aggregate(&self, stats: &[KstatData], by: CpuGrouping) -> Vec<CpuAggregate> {
+        let mut groups: HashMap<CpuGroup, CpuAggregate> = HashMap::new();
+        for kstat in stats {
+            let group = match self.group(kstat.instance, by) {
+                Some(g) => g,
+                None => continue,
+            };
+            let agg = groups.entry(group).or_insert_with(|| CpuAggregate {
+                group,
+                cpus: Vec::new(),
+                stats: HashMap::new(),
+            });
+            agg.cpus.push(kstat.instance);
+            for (k, v) in numeric_values(&kstat.data) {
+                *agg.stats.entry(k).or_insert(0) += v as i64;
+            }
+        }
+
+        let mut ret: Vec<CpuAggregate> = groups.into_values().collect();
+        ret.sort_by_key(|a| a.group);
+        ret
+    }
+}
+
+/// The cumulative CPU time of a single CPU, decoded from its `cpu:<cpu>:sys` kstat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuTimes {
+    /// the CPU id, which is also the kstat instance
+    pub cpu_id: i32,
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// nanoseconds spent in user mode
+    pub user: u64,
+    /// nanoseconds spent in kernel mode, including interrupts
+    pub kernel: u64,
+    /// nanoseconds spent idle
+    pub idle: u64,
+}
+
+impl CpuTimes {
+    /// Decode a `cpu:<cpu>:sys` kstat, returning `None` if `data` is not one
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        if data.module != "cpu" || data.name != "sys" {
+            return None;
+        }
+        let get = |key| data.get_u64(key).unwrap_or(0);
+        Some(CpuTimes {
+            cpu_id: data.instance,
+            snaptime: data.snaptime,
+            user: get("cpu_nsec_user"),
+            // mpstat(1M) reports interrupt time as system time
+            kernel: get("cpu_nsec_kernel") + get("cpu_nsec_intr"),
+            idle: get("cpu_nsec_idle"),
+        })
+    }
+}
+
+/// Read the CPU time of every CPU, sorted by CPU id
+pub fn times() -> io::Result<Vec<CpuTimes>> {
+    let reader = KstatReader::builder().module("cpu").name("sys").build()?;
+    let mut ret: Vec<CpuTimes> = reader
+        .read()?
+        .iter()
+        .filter_map(CpuTimes::from_kstat)
+        .collect();
+    ret.sort_by_key(|c| c.cpu_id);
+    Ok(ret)
+}
+
+/// How a CPU, or every CPU together, spent its time between two `CpuTimes` snapshots
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuUtilization {
+    /// `CpuGroup::Cpu` for a single CPU or `CpuGroup::Host` for the aggregate
+    pub group: CpuGroup,
+    /// percentage of time spent in user mode
+    pub user_pct: f64,
+    /// percentage of time spent in kernel mode
+    pub kernel_pct: f64,
+    /// percentage of time spent idle
+    pub idle_pct: f64,
+}
+
+impl CpuUtilization {
+    fn from_deltas(group: CpuGroup, user: u64, kernel: u64, idle: u64) -> Option<Self> {
+        let total = user + kernel + idle;
+        if total == 0 {
+            return None;
+        }
+        let pct = |v: u64| v as f64 * 100.0 / total as f64;
+        Some(CpuUtilization {
+            group,
+            user_pct: pct(user),
+            kernel_pct: pct(kernel),
+            idle_pct: pct(idle),
+        })
+    }
+}
+
+/// Compute the utilization of each CPU present in both `prev` and `cur`, followed by the
+/// aggregate over all of them as `CpuGroup::Host`. CPUs that were offline for the whole interval
+/// are left out.
+///
+/// # Example
+/// ```
+/// # use std::thread;
+/// # use std::time::Duration;
+/// let prev = kstat::cpu::times().expect("failed to read cpu times");
+/// thread::sleep(Duration::from_secs(1));
+/// let cur = kstat::cpu::times().expect("failed to read cpu times");
+/// for u in kstat::cpu::utilization(&prev, &cur) {
+///     println!("{:?}: {:.1}% user {:.1}% sys", u.group, u.user_pct, u.kernel_pct);
+/// }
+/// ```
+pub fn utilization(prev: &[CpuTimes], cur: &[CpuTimes]) -> Vec<CpuUtilization> {
+    let prev: HashMap<i32, &CpuTimes> = prev.iter().map(|t| (t.cpu_id, t)).collect();
+    let mut ret = Vec::with_capacity(cur.len() + 1);
+    let (mut user, mut kernel, mut idle) = (0, 0, 0);
+    for c in cur {
+        let p = match prev.get(&c.cpu_id) {
+            Some(p) => p,
+            None => continue,
+        };
+        let du = c.user.saturating_sub(p.user);
+        let dk = c.kernel.saturating_sub(p.kernel);
+        let di = c.idle.saturating_sub(p.idle);
+        if let Some(u) = CpuUtilization::from_deltas(CpuGroup::Cpu(c.cpu_id), du, dk, di) {
+            ret.push(u);
+            user += du;
+            kernel += dk;
+            idle += di;
+        }
+    }
+    if let Some(u) = CpuUtilization::from_deltas(CpuGroup::Host, user, kernel, idle) {
+        ret.push(u);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+    use kstat_named::KstatNamedData;
+
+    #[test]
+    fn aggregate_by_core() {
+        let info: Vec<KstatData> = (0..4)
+            .map(|cpu| {
+                kstat(
+                    "cpu_info",
+                    cpu,
+                    "test",
+                    vec![
+                        ("chip_id", KstatNamedData::DataInt64(0)),
+                        ("core_id", KstatNamedData::DataInt64(i64::from(cpu / 2))),
+                    ],
+                )
+            })
+            .collect();
+        let topo = CpuTopology::from_cpu_info(&info);
+
+        let sys: Vec<KstatData> = (0..4)
+            .map(|cpu| {
+                let stats = vec![("cpu_ticks_idle", KstatNamedData::DataUInt64(10))];
+                kstat("cpu", cpu, "sys", stats)
+            })
+            .collect();
+
+        let cores = topo.aggregate(&sys, CpuGrouping::Core);
+        assert_eq!(cores.len(), 2);
+        assert_eq!(cores[0].group, CpuGroup::Core(0));
+        assert_eq!(cores[0].cpus, vec![0, 1]);
+        assert_eq!(cores[0].stats["cpu_ticks_idle"], 20);
+
+        let host = topo.aggregate(&sys, CpuGrouping::Host);
+        assert_eq!(host[0].stats["cpu_ticks_idle"], 40);
+    }
+
+    #[test]
+    fn utilization_percentages() {
+        let times = |cpu_id, user, kernel, idle| CpuTimes {
+            cpu_id,
+            snaptime: 0,
+            user,
+            kernel,
+            idle,
+        };
+        let prev = vec![times(0, 0, 0, 0), times(1, 0, 0, 0)];
+        let cur = vec![
+            times(0, 50, 25, 25),
+            times(1, 0, 0, 100),
+            times(2, 10, 10, 10),
+        ];
+
+        let u = utilization(&prev, &cur);
+        assert_eq!(u.len(), 3);
+        assert_eq!(u[0].group, CpuGroup::Cpu(0));
+        assert_eq!(u[0].user_pct, 50.0);
+        assert_eq!(u[1].idle_pct, 100.0);
+        assert_eq!(u[2].group, CpuGroup::Host);
+        assert_eq!(u[2].user_pct, 25.0);
+        assert_eq!(u[2].idle_pct, 62.5);
+    }
+}
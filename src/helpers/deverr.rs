@@ -0,0 +1,191 @@
+//! Typed access to the per device error kstats (class `device_error`, e.g. `sderr:0:sd0,err`),
+//! keyed so that error trends can be joined with fault management events by an external tool.
+
+use super::{rate, Counter};
+use {KstatData, KstatReader};
+
+use std::io;
+
+/// The identity of a device, normalized so that it can be matched against the identity found in
+/// fault management events
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DeviceKey {
+    /// the driver name, e.g. `sd`
+    pub driver: String,
+    /// the driver instance
+    pub instance: i32,
+    /// the serial number with padding removed, if the device reports one
+    pub serial: Option<String>,
+}
+
+impl DeviceKey {
+    /// The `<driver><instance>` name of the device, e.g. `sd3`, as used by iostat(1M)
+    pub fn instance_name(&self) -> String {
+        format!("{}{}", self.driver, self.instance)
+    }
+}
+
+/// The decoded error counters of a single device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceErrors {
+    /// the identity of the device
+    pub key: DeviceKey,
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// the vendor string, with padding removed
+    pub vendor: Option<String>,
+    /// the product string, with padding removed
+    pub product: Option<String>,
+    /// soft errors
+    pub soft_errors: Counter,
+    /// hard errors
+    pub hard_errors: Counter,
+    /// transport errors
+    pub transport_errors: Counter,
+    /// media errors
+    pub media_errors: Counter,
+    /// device not ready errors
+    pub device_not_ready: Counter,
+    /// no device errors
+    pub no_device: Counter,
+    /// recoverable errors
+    pub recoverable: Counter,
+    /// illegal request errors
+    pub illegal_request: Counter,
+    /// predictive failure analysis notifications
+    pub predictive_failure: Counter,
+}
+
+/// Per second error rates between two `DeviceErrors` snapshots of the same device
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceErrorRates {
+    /// the identity of the device
+    pub key: DeviceKey,
+    /// soft errors per second
+    pub soft_errors: f64,
+    /// hard errors per second
+    pub hard_errors: f64,
+    /// transport errors per second
+    pub transport_errors: f64,
+    /// media errors per second
+    pub media_errors: f64,
+    /// predictive failure analysis notifications per second
+    pub predictive_failure: f64,
+}
+
+impl DeviceErrors {
+    /// Decode an error kstat, returning `None` if `data` is not one
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        if data.class != "device_error" {
+            return None;
+        }
+
+        let instance_name = data.name.trim_end_matches(",err");
+        let driver = instance_name.trim_end_matches(|c: char| c.is_ascii_digit());
+        if driver.is_empty() || driver.len() == instance_name.len() {
+            return None;
+        }
+
+        let counter = |key| Counter::of(data, key);
+        let text = |key| data.get_str(key).and_then(normalize_value);
+        Some(DeviceErrors {
+            key: DeviceKey {
+                driver: driver.to_string(),
+                instance: data.instance,
+                serial: text("Serial No"),
+            },
+            snaptime: data.snaptime,
+            vendor: text("Vendor"),
+            product: text("Product"),
+            soft_errors: counter("Soft Errors"),
+            hard_errors: counter("Hard Errors"),
+            transport_errors: counter("Transport Errors"),
+            media_errors: counter("Media Error"),
+            device_not_ready: counter("Device Not Ready"),
+            no_device: counter("No Device"),
+            recoverable: counter("Recoverable"),
+            illegal_request: counter("Illegal Request"),
+            predictive_failure: counter("Predictive Failure Analysis"),
+        })
+    }
+
+    /// The total of the soft, hard and transport error counters
+    pub fn total(&self) -> u64 {
+        self.soft_errors
+            .value
+            .saturating_add(self.hard_errors.value)
+            .saturating_add(self.transport_errors.value)
+    }
+
+    /// Compute the per second rates between `prev` and `self`, returning `None` if they are
+    /// snapshots of different devices
+    pub fn rates(&self, prev: &DeviceErrors) -> Option<DeviceErrorRates> {
+        if self.key != prev.key {
+            return None;
+        }
+        let interval = self.snaptime - prev.snaptime;
+        Some(DeviceErrorRates {
+            key: self.key.clone(),
+            soft_errors: rate(prev.soft_errors, self.soft_errors, interval),
+            hard_errors: rate(prev.hard_errors, self.hard_errors, interval),
+            transport_errors: rate(prev.transport_errors, self.transport_errors, interval),
+            media_errors: rate(prev.media_errors, self.media_errors, interval),
+            predictive_failure: rate(prev.predictive_failure, self.predictive_failure, interval),
+        })
+    }
+}
+
+/// Read the error kstats of every device, sorted by device
+pub fn read() -> io::Result<Vec<DeviceErrors>> {
+    let reader = KstatReader::builder().class("device_error").build()?;
+    let mut ret: Vec<DeviceErrors> = reader
+        .read()?
+        .iter()
+        .filter_map(DeviceErrors::from_kstat)
+        .collect();
+    ret.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(ret)
+}
+
+/// Normalize a statistic name such as `Soft Errors` or `Predictive Failure Analysis` into a
+/// lower case, underscore separated key such as `soft_errors`
+pub fn normalize_key(key: &str) -> String {
+    let mut ret = String::with_capacity(key.len());
+    for c in key.chars() {
+        if c.is_ascii_alphanumeric() {
+            ret.push(c.to_ascii_lowercase());
+        } else if !ret.is_empty() && !ret.ends_with('_') {
+            ret.push('_');
+        }
+    }
+    let len = ret.trim_end_matches('_').len();
+    ret.truncate(len);
+    ret
+}
+
+/// Normalize an identity string such as a vendor or serial number. Drivers pad these with
+/// spaces or NULs to the width of the SCSI inquiry field; an empty value is `None`.
+pub fn normalize_value(value: &str) -> Option<String> {
+    let trimmed = value.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_key("Soft Errors"), "soft_errors");
+        assert_eq!(
+            normalize_key(" Predictive  Failure Analysis "),
+            "predictive_failure_analysis"
+        );
+        assert_eq!(normalize_value("  ABC123\0\0"), Some("ABC123".to_string()));
+        assert_eq!(normalize_value("    "), None);
+    }
+}
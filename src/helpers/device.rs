@@ -0,0 +1,266 @@
+//! Joins the kstats a driver keeps for each of its devices, such as `sd:0:sd0` (I/O),
+//! `sd:0:sd0,a` (partition I/O) and `sderr:0:sd0,err` (named error counters), into a single
+//! record per device. A `DeviceResolver` can attach the `/devices` path and `cXtYdZ` name of each
+//! device.
+
+use {Data, KstatData, KstatReader, KstatSelector};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Every kstat of a single device
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DeviceStats {
+    /// the driver name, e.g. `sd`
+    pub driver: String,
+    /// the driver instance
+    pub instance: i32,
+    /// the I/O kstat of the whole device, e.g. `sd:0:sd0`
+    pub io: Option<KstatData>,
+    /// the I/O kstats of the device's partitions, e.g. `sd:0:sd0,a`, sorted by name
+    pub partitions: Vec<KstatData>,
+    /// the companion named kstats, e.g. `sderr:0:sd0,err`, sorted by module and name
+    pub named: Vec<KstatData>,
+    /// where the device is, if it has been resolved with `DeviceResolver::annotate`
+    pub path: Option<DevicePath>,
+}
+
+impl DeviceStats {
+    /// The `<driver><instance>` name of the device, e.g. `sd3`, as used by iostat(1M)
+    pub fn instance_name(&self) -> String {
+        format!("{}{}", self.driver, self.instance)
+    }
+}
+
+/// The driver a kstat belongs to. Error kstats live in a module of their own named after the
+/// driver, e.g. `sderr` for `sd`.
+pub(super) fn driver(data: &KstatData) -> &str {
+    if data.class == "device_error" && data.module.len() > 3 && data.module.ends_with("err") {
+        &data.module[..data.module.len() - 3]
+    } else {
+        &data.module
+    }
+}
+
+/// Join already read kstats into one record per driver and instance, sorted by driver and
+/// instance. Devices without an I/O kstat, e.g. those only present through their error
+/// counters, are still returned.
+pub fn join(data: Vec<KstatData>) -> Vec<DeviceStats> {
+    let mut devices: BTreeMap<(String, i32), DeviceStats> = BTreeMap::new();
+    for kstat in data {
+        let key = (driver(&kstat).to_string(), kstat.instance);
+        let device = devices.entry(key).or_insert_with(|| DeviceStats {
+            driver: driver(&kstat).to_string(),
+            instance: kstat.instance,
+            ..DeviceStats::default()
+        });
+        match kstat.data {
+            Data::Io(_) if kstat.name == device.instance_name() => device.io = Some(kstat),
+            Data::Io(_) => device.partitions.push(kstat),
+            Data::Named(_) => device.named.push(kstat),
+        }
+    }
+
+    devices
+        .into_values()
+        .map(|mut device| {
+            device.partitions.sort_by(|a, b| a.name.cmp(&b.name));
+            device
+                .named
+                .sort_by(|a, b| (&a.module, &a.name).cmp(&(&b.module, &b.name)));
+            device
+        })
+        .collect()
+}
+
+/// Read the disk, partition and device error kstats of every device and join them per device
+///
+/// # Example
+/// ```
+/// for disk in kstat::helpers::device::read().expect("failed to read device kstats") {
+///     println!("{}: {} partitions", disk.instance_name(), disk.partitions.len());
+/// }
+/// ```
+pub fn read() -> io::Result<Vec<DeviceStats>> {
+    let reader = KstatReader::builder()
+        .add_selector(KstatSelector::new().class("disk"))
+        .add_selector(KstatSelector::new().class("partition"))
+        .add_selector(KstatSelector::new().class("device_error"))
+        .build()?;
+    Ok(join(reader.read()?))
+}
+
+/// The names of a device outside of the kstat framework
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DevicePath {
+    /// the physical path below `/devices`, e.g. `/pci@0,0/pci15ad,1976@10/sd@0,0`
+    pub physical: String,
+    /// the logical disk name, e.g. `c0t0d0`, if the device has a link in `/dev/dsk`
+    pub logical: Option<String>,
+}
+
+/// Maps driver instances to their physical path, using `/etc/path_to_inst`, and to their
+/// logical `cXtYdZ` name, using the links in `/dev/dsk`.
+///
+/// # Example
+/// ```
+/// let resolver = kstat::helpers::device::DeviceResolver::new().expect("failed to read paths");
+/// let mut disks = kstat::helpers::device::read().expect("failed to read device kstats");
+/// resolver.annotate(&mut disks);
+/// for disk in &disks {
+///     let name = disk.path.as_ref().and_then(|p| p.logical.clone());
+///     println!("{}: {:?}", disk.instance_name(), name);
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct DeviceResolver {
+    paths: HashMap<(String, i32), DevicePath>,
+}
+
+impl DeviceResolver {
+    /// Build a resolver from the running system
+    pub fn new() -> io::Result<Self> {
+        let path_to_inst = fs::read_to_string("/etc/path_to_inst")?;
+        let mut links = Vec::new();
+        for entry in fs::read_dir("/dev/dsk")? {
+            let entry = entry?;
+            // Dangling links are left behind by devices that have been removed
+            if let Ok(target) = fs::read_link(entry.path()) {
+                links.push((entry.file_name().to_string_lossy().into_owned(), target));
+            }
+        }
+        Ok(DeviceResolver::from_sources(
+            &path_to_inst,
+            links
+                .iter()
+                .map(|(name, target)| (name.as_str(), target.as_path())),
+        ))
+    }
+
+    /// Build a resolver from the contents of `/etc/path_to_inst` and the name and target of each
+    /// link in `/dev/dsk`
+    pub fn from_sources<'a, I>(path_to_inst: &str, links: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a str, &'a Path)>,
+    {
+        let mut physical: HashMap<String, (String, i32)> = HashMap::new();
+        for line in path_to_inst.lines() {
+            if let Some((path, instance, driver)) = parse_path_to_inst(line) {
+                physical.insert(path.to_string(), (driver.to_string(), instance));
+            }
+        }
+
+        let mut logical: HashMap<String, String> = HashMap::new();
+        for (name, target) in links {
+            // e.g. ../../devices/pci@0,0/pci15ad,1976@10/sd@0,0:a for c0t0d0s0
+            let target = target.to_string_lossy();
+            let path = match target.find("/devices/") {
+                Some(i) => &target[i + "/devices".len()..],
+                None => continue,
+            };
+            let path = path.rsplitn(2, ':').last().unwrap_or(path);
+            logical
+                .entry(path.to_string())
+                .or_insert_with(|| disk_name(name).to_string());
+        }
+
+        let paths = physical
+            .into_iter()
+            .map(|(path, key)| {
+                let logical = logical.get(&path).cloned();
+                (
+                    key,
+                    DevicePath {
+                        physical: path,
+                        logical,
+                    },
+                )
+            })
+            .collect();
+        DeviceResolver { paths }
+    }
+
+    /// Returns the path of instance `instance` of `driver`
+    pub fn resolve(&self, driver: &str, instance: i32) -> Option<&DevicePath> {
+        self.paths.get(&(driver.to_string(), instance))
+    }
+
+    /// Fill in the `path` of every device that can be resolved
+    pub fn annotate(&self, devices: &mut [DeviceStats]) {
+        for device in devices {
+            device.path = self.resolve(&device.driver, device.instance).cloned();
+        }
+    }
+}
+
+/// Parse a `"<physical path>" <instance> "<driver>"` line of `/etc/path_to_inst`
+fn parse_path_to_inst(line: &str) -> Option<(&str, i32, &str)> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return None;
+    }
+    let mut fields = line.split_whitespace();
+    let path = fields.next()?.trim_matches('"');
+    let instance = fields.next()?.parse().ok()?;
+    let driver = fields.next()?.trim_matches('"');
+    Some((path, instance, driver))
+}
+
+/// Strip the slice or partition suffix from a `/dev/dsk` name, e.g. `c0t0d0s0` to `c0t0d0`
+fn disk_name(name: &str) -> &str {
+    match name.rfind(['s', 'p']) {
+        Some(i) if i > 0 && name[i + 1..].chars().all(|c| c.is_ascii_digit()) => &name[..i],
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::{io, kstat};
+
+    fn with_class(class: &str, data: KstatData) -> KstatData {
+        KstatData {
+            class: class.to_string(),
+            ..data
+        }
+    }
+
+    #[test]
+    fn join_by_driver_and_instance() {
+        let devices = join(vec![
+            with_class("partition", io("sd", 1, "sd1,b")),
+            with_class("device_error", kstat("sderr", 1, "sd1,err", Vec::new())),
+            io("sd", 1, "sd1"),
+            with_class("partition", io("sd", 1, "sd1,a")),
+            io("blkdev", 1, "blkdev1"),
+        ]);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].instance_name(), "blkdev1");
+        let sd = &devices[1];
+        assert_eq!(sd.io.as_ref().map(|k| k.name.as_str()), Some("sd1"));
+        let partitions: Vec<&str> = sd.partitions.iter().map(|k| k.name.as_str()).collect();
+        assert_eq!(partitions, vec!["sd1,a", "sd1,b"]);
+        assert_eq!(sd.named.len(), 1);
+    }
+
+    #[test]
+    fn resolve_paths() {
+        let path_to_inst = "#\tCaution! This file contains critical kernel state\n\
+                            \"/pci@0,0/pci15ad,1976@10/sd@0,0\" 0 \"sd\"\n\
+                            \"/pci@0,0/pci15ad,1976@10/sd@1,0\" 1 \"sd\"\n";
+        let s0 = Path::new("../../devices/pci@0,0/pci15ad,1976@10/sd@0,0:a");
+        let p0 = Path::new("../../devices/pci@0,0/pci15ad,1976@10/sd@0,0:q");
+        let resolver =
+            DeviceResolver::from_sources(path_to_inst, vec![("c0t0d0s0", s0), ("c0t0d0p0", p0)]);
+
+        let sd0 = resolver.resolve("sd", 0).expect("sd0 not resolved");
+        assert_eq!(sd0.physical, "/pci@0,0/pci15ad,1976@10/sd@0,0");
+        assert_eq!(sd0.logical.as_deref(), Some("c0t0d0"));
+        let sd1 = resolver.resolve("sd", 1).expect("sd1 not resolved");
+        assert_eq!(sd1.logical, None);
+        assert!(resolver.resolve("sd", 2).is_none());
+    }
+}
@@ -0,0 +1,134 @@
+//! Typed access to the `ip:<stack>:icmp` MIB kstats, with the message counters and rates reported
+//! by `netstat -s -P icmp`. There is one per IP stack, see `helpers::tcp`.
+
+use super::{rate, read_mib, read_mibs, Counter};
+use KstatData;
+
+use std::io;
+
+/// A decoded `ip:<stack>:icmp` kstat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IcmpStats {
+    /// the IP stack these counters belong to
+    pub stack: i32,
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// ICMP messages received
+    pub in_msgs: Counter,
+    /// ICMP messages received with errors
+    pub in_errors: Counter,
+    /// ICMP messages received with a bad checksum
+    pub in_cksum_errs: Counter,
+    /// destination unreachable messages received
+    pub in_dest_unreachs: Counter,
+    /// echo requests received
+    pub in_echos: Counter,
+    /// echo replies received
+    pub in_echo_reps: Counter,
+    /// ICMP messages sent
+    pub out_msgs: Counter,
+    /// ICMP messages not sent because of errors
+    pub out_errors: Counter,
+    /// destination unreachable messages sent
+    pub out_dest_unreachs: Counter,
+    /// echo requests sent
+    pub out_echos: Counter,
+    /// echo replies sent
+    pub out_echo_reps: Counter,
+}
+
+/// Per second rates between two `IcmpStats` snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct IcmpRates {
+    /// messages received per second
+    pub in_msgs: f64,
+    /// errored messages received per second
+    pub in_errors: f64,
+    /// destination unreachable messages received per second
+    pub in_dest_unreachs: f64,
+    /// messages sent per second
+    pub out_msgs: f64,
+    /// send errors per second
+    pub out_errors: f64,
+    /// destination unreachable messages sent per second
+    pub out_dest_unreachs: f64,
+}
+
+impl IcmpStats {
+    /// Read the `ip:<stack>:icmp` kstat of IP stack `stack`
+    pub fn read(stack: i32) -> io::Result<Self> {
+        read_mib("ip", stack, "icmp", IcmpStats::from_kstat)
+    }
+
+    /// Read the `ip:<stack>:icmp` kstat of every IP stack, sorted by stack
+    pub fn read_all() -> io::Result<Vec<Self>> {
+        read_mibs("ip", "icmp", IcmpStats::from_kstat)
+    }
+
+    /// Decode an already read `ip:<stack>:icmp` kstat, returning `None` if `data` is not one
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        if data.module != "ip" || data.name != "icmp" {
+            return None;
+        }
+        let counter = |key| Counter::of(data, key);
+        Some(IcmpStats {
+            stack: data.instance,
+            snaptime: data.snaptime,
+            in_msgs: counter("inMsgs"),
+            in_errors: counter("inErrors"),
+            in_cksum_errs: counter("inCksumErrs"),
+            in_dest_unreachs: counter("inDestUnreachs"),
+            in_echos: counter("inEchos"),
+            in_echo_reps: counter("inEchoReps"),
+            out_msgs: counter("outMsgs"),
+            out_errors: counter("outErrors"),
+            out_dest_unreachs: counter("outDestUnreachs"),
+            out_echos: counter("outEchos"),
+            out_echo_reps: counter("outEchoReps"),
+        })
+    }
+
+    /// Compute the per second rates between `prev` and `self`
+    pub fn rates(&self, prev: &IcmpStats) -> IcmpRates {
+        let interval = self.snaptime - prev.snaptime;
+        IcmpRates {
+            in_msgs: rate(prev.in_msgs, self.in_msgs, interval),
+            in_errors: rate(prev.in_errors, self.in_errors, interval),
+            in_dest_unreachs: rate(prev.in_dest_unreachs, self.in_dest_unreachs, interval),
+            out_msgs: rate(prev.out_msgs, self.out_msgs, interval),
+            out_errors: rate(prev.out_errors, self.out_errors, interval),
+            out_dest_unreachs: rate(prev.out_dest_unreachs, self.out_dest_unreachs, interval),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+    use kstat_named::KstatNamedData::DataUInt64;
+
+    #[test]
+    fn decode_and_rates() {
+        let stats = vec![("inMsgs", DataUInt64(40)), ("outEchoReps", DataUInt64(7))];
+        let prev = IcmpStats::from_kstat(&kstat("ip", 4, "icmp", stats)).unwrap();
+        assert_eq!(prev.stack, 4);
+        assert_eq!(prev.in_msgs.value, 40);
+        assert_eq!(prev.out_echo_reps.value, 7);
+        assert_eq!(prev.in_errors.value, 0);
+
+        let cur = IcmpStats {
+            snaptime: prev.snaptime + 4_000_000_000,
+            in_msgs: Counter {
+                value: 60,
+                ..prev.in_msgs
+            },
+            ..prev
+        };
+        let rates = cur.rates(&prev);
+        assert_eq!(rates.in_msgs, 5.0);
+        assert_eq!(rates.out_msgs, 0.0);
+
+        assert!(IcmpStats::from_kstat(&kstat("ip", 0, "ip", Vec::new())).is_none());
+    }
+}
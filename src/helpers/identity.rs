@@ -0,0 +1,170 @@
+//! Durable identities for kstats whose instance number can change across reboots.
+//!
+//! A disk that is renumbered shows up under a new `sd` instance, and a zone that is rebooted gets
+//! a new zone id, so keying time series by instance fragments them. An `IdentityResolver` learns
+//! the serial number of every device from its error kstat and the name of every zone from its
+//! zone kstats, and resolves other kstats of the same device or zone to that identity.
+//!
+//! Datalinks need no resolving: their kstats are already named after the link, e.g.
+//! `link:0:net0`, and do not carry the MAC address.
+
+use super::deverr::{self, DeviceErrors};
+use super::device;
+use super::zones::{self, ZoneStats};
+use KstatData;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+/// The durable identity of a kstat
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Identity {
+    /// a device, by serial number
+    Serial(String),
+    /// a zone, by name
+    Zone(String),
+}
+
+impl Identity {
+    /// The name of the label or tag to file the identity under, `serial` or `zone`
+    pub fn label(&self) -> &'static str {
+        match *self {
+            Identity::Serial(_) => "serial",
+            Identity::Zone(_) => "zone",
+        }
+    }
+
+    /// The serial number or zone name
+    pub fn value(&self) -> &str {
+        match *self {
+            Identity::Serial(ref s) | Identity::Zone(ref s) => s,
+        }
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.label(), self.value())
+    }
+}
+
+/// Maps driver instances to device serial numbers and zone ids to zone names
+///
+/// # Example
+/// ```
+/// use kstat::helpers::identity::IdentityResolver;
+///
+/// let resolver = IdentityResolver::new().expect("failed to read identities");
+/// let reader = kstat::KstatReader::builder().class("disk").build().unwrap();
+/// for stat in reader.read().expect("failed to read kstats") {
+///     match resolver.resolve(&stat) {
+///         Some(id) => println!("{}:{}:{} is {}", stat.module, stat.instance, stat.name, id),
+///         None => println!("{}:{}:{}", stat.module, stat.instance, stat.name),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct IdentityResolver {
+    serials: HashMap<(String, i32), String>,
+    zones: HashMap<i32, String>,
+}
+
+impl IdentityResolver {
+    /// Build a resolver from the device error and zone kstats of the running system
+    pub fn new() -> io::Result<Self> {
+        let mut ret = IdentityResolver::default();
+        ret.add_devices(&deverr::read()?);
+        ret.add_zones(&zones::read()?);
+        Ok(ret)
+    }
+
+    /// Build a resolver from the device error and zone kstats among already read kstats
+    pub fn from_kstats(data: &[KstatData]) -> Self {
+        let devices: Vec<DeviceErrors> = data.iter().filter_map(DeviceErrors::from_kstat).collect();
+        let mut ret = IdentityResolver::default();
+        ret.add_devices(&devices);
+        ret.add_zones(&zones::from_kstats(data));
+        ret
+    }
+
+    /// Learn the serial numbers of `devices`. Devices that do not report one are left out.
+    pub fn add_devices(&mut self, devices: &[DeviceErrors]) {
+        for device in devices {
+            if let Some(ref serial) = device.key.serial {
+                let key = (device.key.driver.clone(), device.key.instance);
+                self.serials.insert(key, serial.clone());
+            }
+        }
+    }
+
+    /// Learn the names of `zones`
+    pub fn add_zones(&mut self, zones: &[ZoneStats]) {
+        for zone in zones.iter().filter(|z| !z.zonename.is_empty()) {
+            self.zones.insert(zone.zoneid, zone.zonename.clone());
+        }
+    }
+
+    /// Returns the identity of `kstat`, or `None` if it belongs to neither a known device nor
+    /// a known zone
+    pub fn resolve(&self, kstat: &KstatData) -> Option<Identity> {
+        if is_zone_kstat(kstat) {
+            return self.zones.get(&kstat.instance).cloned().map(Identity::Zone);
+        }
+        let key = (device::driver(kstat).to_string(), kstat.instance);
+        self.serials.get(&key).cloned().map(Identity::Serial)
+    }
+}
+
+/// Whether `kstat` is one of the per zone kstats, whose instance is the zone id
+fn is_zone_kstat(kstat: &KstatData) -> bool {
+    match kstat.module.as_str() {
+        "zone_vfs" | "zones" | "memory_cap" => true,
+        "caps" => kstat.name.starts_with("cpucaps_zone_"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::{io, kstat};
+    use kstat_named::KstatNamedData::{DataString, DataUInt32};
+
+    #[test]
+    fn resolves_devices_and_zones() {
+        let err = KstatData {
+            class: "device_error".to_string(),
+            ..kstat(
+                "sderr",
+                3,
+                "sd3,err",
+                vec![
+                    ("Serial No", DataString("  S3Z1NB0K  ".to_string())),
+                    ("Soft Errors", DataUInt32(0)),
+                ],
+            )
+        };
+        let vfs = kstat(
+            "zone_vfs",
+            7,
+            "web",
+            vec![("zonename", DataString("webserver".to_string()))],
+        );
+        let resolver = IdentityResolver::from_kstats(&[err.clone(), vfs.clone()]);
+
+        let serial = Some(Identity::Serial("S3Z1NB0K".to_string()));
+        assert_eq!(resolver.resolve(&err), serial);
+        assert_eq!(resolver.resolve(&io("sd", 3, "sd3")), serial);
+        assert_eq!(resolver.resolve(&io("sd", 4, "sd4")), None);
+
+        let zone = resolver.resolve(&vfs).expect("expected a zone");
+        assert_eq!(zone.to_string(), "zone=webserver");
+        let cap = kstat("memory_cap", 7, "web", Vec::new());
+        assert_eq!(resolver.resolve(&cap), Some(zone));
+        assert_eq!(
+            resolver.resolve(&kstat("link", 0, "net0", Vec::new())),
+            None
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! The `iostat -x` metrics of disks, derived from the I/O kstats of drivers such as `sd` and
+//! `blkdev` and of ZFS vdevs.
+//!
+//! The kernel keeps Riemann sums of the length of each device's wait and run queues over time, so
+//! the average queue lengths and service times over an interval fall out of the difference
+//! between two snapshots.
+
+use accum::{self, Width};
+use {Data, KstatData, KstatReader};
+
+use std::io;
+
+/// The I/O counters of a single disk at one point in time
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DiskStats {
+    /// the device name, e.g. `sd0`, which is the kstat name
+    pub name: String,
+    /// the driver, e.g. `sd`, which is the kstat module
+    pub module: String,
+    /// the driver instance
+    pub instance: i32,
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// bytes read
+    pub nread: u64,
+    /// bytes written
+    pub nwritten: u64,
+    /// read operations
+    pub reads: u32,
+    /// write operations
+    pub writes: u32,
+    /// cumulative time the wait queue was not empty
+    pub wtime: i64,
+    /// cumulative wait queue length * time
+    pub wlentime: i64,
+    /// cumulative time the run queue was not empty
+    pub rtime: i64,
+    /// cumulative run queue length * time
+    pub rlentime: i64,
+}
+
+/// The `iostat -x` metrics of a disk over an interval
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DiskRates {
+    /// the device name
+    pub name: String,
+    /// reads per second
+    pub reads: f64,
+    /// writes per second
+    pub writes: f64,
+    /// kilobytes read per second
+    pub kr: f64,
+    /// kilobytes written per second
+    pub kw: f64,
+    /// average number of transactions waiting for service
+    pub wait: f64,
+    /// average number of transactions being serviced
+    pub actv: f64,
+    /// average response time of transactions, waiting and active, in milliseconds
+    pub svc_t: f64,
+    /// percent of time there were transactions waiting for service
+    pub pct_w: f64,
+    /// percent of time the disk was busy, i.e. there were transactions being serviced
+    pub pct_b: f64,
+}
+
+impl DiskStats {
+    /// Decode an I/O kstat, returning `None` if `data` is not one
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        let io = match data.data {
+            Data::Io(ref io) => io,
+            Data::Named(_) => return None,
+        };
+        Some(DiskStats {
+            name: data.name.clone(),
+            module: data.module.clone(),
+            instance: data.instance,
+            snaptime: data.snaptime,
+            nread: io.nread,
+            nwritten: io.nwritten,
+            reads: io.reads,
+            writes: io.writes,
+            wtime: io.wtime,
+            wlentime: io.wlentime,
+            rtime: io.rtime,
+            rlentime: io.rlentime,
+        })
+    }
+
+    /// Compute the `iostat -x` metrics between two snapshots of the same disk, returning `None`
+    /// if they are of different disks, no time passed between them or the byte counters went
+    /// backwards because they were reset
+    ///
+    /// # Example
+    /// ```
+    /// # use std::thread;
+    /// # use std::time::Duration;
+    /// use kstat::helpers::iostat::{self, DiskStats};
+    ///
+    /// let prev = iostat::read().expect("failed to read disk kstats");
+    /// thread::sleep(Duration::from_secs(1));
+    /// for cur in iostat::read().expect("failed to read disk kstats") {
+    ///     let p = prev.iter().find(|p| p.name == cur.name);
+    ///     if let Some(r) = p.and_then(|p| DiskStats::delta(p, &cur)) {
+    ///         println!("{} {:.1} {:.1} {:.1} {:.0}", r.name, r.reads, r.writes, r.svc_t, r.pct_b);
+    ///     }
+    /// }
+    /// ```
+    pub fn delta(prev: &DiskStats, cur: &DiskStats) -> Option<DiskRates> {
+        if prev.name != cur.name || prev.module != cur.module || prev.instance != cur.instance {
+            return None;
+        }
+        let interval = cur.snaptime - prev.snaptime;
+        if interval <= 0 {
+            return None;
+        }
+
+        let interval = interval as f64;
+        let secs = interval / 1_000_000_000.0;
+        let delta = |prev, cur| accum::counter_delta(prev, cur, Width::Bits64).map(|d| d as f64);
+        let reads = f64::from(cur.reads.wrapping_sub(prev.reads));
+        let writes = f64::from(cur.writes.wrapping_sub(prev.writes));
+        let wlentime = cur.wlentime.wrapping_sub(prev.wlentime) as f64;
+        let rlentime = cur.rlentime.wrapping_sub(prev.rlentime) as f64;
+        let ops = reads + writes;
+
+        Some(DiskRates {
+            name: cur.name.clone(),
+            reads: reads / secs,
+            writes: writes / secs,
+            kr: delta(prev.nread, cur.nread)? / 1024.0 / secs,
+            kw: delta(prev.nwritten, cur.nwritten)? / 1024.0 / secs,
+            wait: wlentime / interval,
+            actv: rlentime / interval,
+            // By Little's law the response time is the queue length over the throughput
+            svc_t: if ops > 0.0 {
+                (wlentime + rlentime) / ops / 1_000_000.0
+            } else {
+                0.0
+            },
+            pct_w: (cur.wtime.wrapping_sub(prev.wtime) as f64 / interval * 100.0).min(100.0),
+            pct_b: (cur.rtime.wrapping_sub(prev.rtime) as f64 / interval * 100.0).min(100.0),
+        })
+    }
+}
+
+/// Read the I/O kstats of every disk, sorted by name
+pub fn read() -> io::Result<Vec<DiskStats>> {
+    let reader = KstatReader::builder().class("disk").build()?;
+    let mut ret: Vec<DiskStats> = reader
+        .read()?
+        .iter()
+        .filter_map(DiskStats::from_kstat)
+        .collect();
+    ret.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iostat_metrics() {
+        let prev = DiskStats {
+            name: "sd0".to_string(),
+            ..DiskStats::default()
+        };
+        // one second with 100 reads of 4k, the run queue 1 deep for half of it and 2 deep for
+        // the other half
+        let cur = DiskStats {
+            name: "sd0".to_string(),
+            snaptime: 1_000_000_000,
+            nread: 409_600,
+            reads: 100,
+            rtime: 1_000_000_000,
+            rlentime: 1_500_000_000,
+            ..DiskStats::default()
+        };
+        let r = DiskStats::delta(&prev, &cur).expect("expected rates");
+        assert_eq!(r.reads, 100.0);
+        assert_eq!(r.kr, 400.0);
+        assert_eq!(r.actv, 1.5);
+        assert_eq!(r.wait, 0.0);
+        assert_eq!(r.svc_t, 15.0);
+        assert_eq!(r.pct_b, 100.0);
+
+        // nread went backwards, so the disk's counters were reset
+        let reset = DiskStats {
+            snaptime: 2_000_000_000,
+            ..DiskStats::default()
+        };
+        assert!(DiskStats::delta(&cur, &reset).is_none());
+    }
+}
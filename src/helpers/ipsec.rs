@@ -0,0 +1,229 @@
+//! Typed access to the `ipsecah:<stack>:ah_stat` and `ipsecesp:<stack>:esp_stat` kstats, for
+//! checking the health of IPsec tunnels. There is one of each per IP stack, see `helpers::tcp`.
+
+use super::{rate, read_mib, Counter};
+use KstatData;
+
+use std::io;
+
+/// A decoded `ipsecah:<stack>:ah_stat` kstat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AhStats {
+    /// the IP stack these counters belong to
+    pub stack: i32,
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// inbound packets that passed authentication
+    pub good_auth: Counter,
+    /// inbound packets that failed authentication
+    pub bad_auth: Counter,
+    /// inbound packets that failed the replay check
+    pub replay_failures: Counter,
+    /// inbound packets that failed the replay check before authentication
+    pub replay_early_failures: Counter,
+    /// outbound packets submitted for protection
+    pub out_requests: Counter,
+    /// outbound packets discarded
+    pub out_discards: Counter,
+    /// SA acquire requests sent to key management
+    pub acquire_requests: Counter,
+    /// SAs expired because of their byte lifetime
+    pub bytes_expired: Counter,
+    /// crypto framework requests that failed
+    pub crypto_failures: Counter,
+}
+
+/// Per second rates between two `AhStats` snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AhRates {
+    /// packets authenticated per second
+    pub good_auth: f64,
+    /// authentication failures per second
+    pub bad_auth: f64,
+    /// replay check failures per second
+    pub replay_failures: f64,
+    /// outbound packets protected per second
+    pub out_requests: f64,
+    /// outbound packets discarded per second
+    pub out_discards: f64,
+    /// crypto failures per second
+    pub crypto_failures: f64,
+}
+
+impl AhStats {
+    /// Read the `ipsecah:<stack>:ah_stat` kstat of IP stack `stack`
+    pub fn read(stack: i32) -> io::Result<Self> {
+        read_mib("ipsecah", stack, "ah_stat", AhStats::from_kstat)
+    }
+
+    /// Decode an already read `ipsecah:<stack>:ah_stat` kstat, returning `None` if `data` is not
+    /// one
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        if data.module != "ipsecah" || data.name != "ah_stat" {
+            return None;
+        }
+        let counter = |key| Counter::of(data, key);
+        Some(AhStats {
+            stack: data.instance,
+            snaptime: data.snaptime,
+            good_auth: counter("good_auth"),
+            bad_auth: counter("bad_auth"),
+            replay_failures: counter("replay_failures"),
+            replay_early_failures: counter("replay_early_failures"),
+            out_requests: counter("out_requests"),
+            out_discards: counter("out_discards"),
+            acquire_requests: counter("acquire_requests"),
+            bytes_expired: counter("bytes_expired"),
+            crypto_failures: counter("crypto_failures"),
+        })
+    }
+
+    /// Compute the per second rates between `prev` and `self`
+    pub fn rates(&self, prev: &AhStats) -> AhRates {
+        let interval = self.snaptime - prev.snaptime;
+        AhRates {
+            good_auth: rate(prev.good_auth, self.good_auth, interval),
+            bad_auth: rate(prev.bad_auth, self.bad_auth, interval),
+            replay_failures: rate(prev.replay_failures, self.replay_failures, interval),
+            out_requests: rate(prev.out_requests, self.out_requests, interval),
+            out_discards: rate(prev.out_discards, self.out_discards, interval),
+            crypto_failures: rate(prev.crypto_failures, self.crypto_failures, interval),
+        }
+    }
+}
+
+/// A decoded `ipsecesp:<stack>:esp_stat` kstat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EspStats {
+    /// the IP stack these counters belong to
+    pub stack: i32,
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// inbound packets that passed authentication
+    pub good_auth: Counter,
+    /// inbound packets that failed authentication
+    pub bad_auth: Counter,
+    /// inbound packets with invalid padding
+    pub bad_padding: Counter,
+    /// inbound packets that failed to decrypt
+    pub bad_decrypt: Counter,
+    /// inbound packets that failed the replay check
+    pub replay_failures: Counter,
+    /// inbound packets that failed the replay check before authentication
+    pub replay_early_failures: Counter,
+    /// outbound packets submitted for protection
+    pub out_requests: Counter,
+    /// outbound packets discarded
+    pub out_discards: Counter,
+    /// SA acquire requests sent to key management
+    pub acquire_requests: Counter,
+    /// SAs expired because of their byte lifetime
+    pub bytes_expired: Counter,
+    /// crypto framework requests that failed
+    pub crypto_failures: Counter,
+}
+
+/// Per second rates between two `EspStats` snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EspRates {
+    /// packets authenticated per second
+    pub good_auth: f64,
+    /// authentication failures per second
+    pub bad_auth: f64,
+    /// decryption failures per second
+    pub bad_decrypt: f64,
+    /// replay check failures per second
+    pub replay_failures: f64,
+    /// outbound packets protected per second
+    pub out_requests: f64,
+    /// outbound packets discarded per second
+    pub out_discards: f64,
+    /// crypto failures per second
+    pub crypto_failures: f64,
+}
+
+impl EspStats {
+    /// Read the `ipsecesp:<stack>:esp_stat` kstat of IP stack `stack`
+    pub fn read(stack: i32) -> io::Result<Self> {
+        read_mib("ipsecesp", stack, "esp_stat", EspStats::from_kstat)
+    }
+
+    /// Decode an already read `ipsecesp:<stack>:esp_stat` kstat, returning `None` if `data` is not
+    /// one
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        if data.module != "ipsecesp" || data.name != "esp_stat" {
+            return None;
+        }
+        let counter = |key| Counter::of(data, key);
+        Some(EspStats {
+            stack: data.instance,
+            snaptime: data.snaptime,
+            good_auth: counter("good_auth"),
+            bad_auth: counter("bad_auth"),
+            bad_padding: counter("bad_padding"),
+            bad_decrypt: counter("bad_decrypt"),
+            replay_failures: counter("replay_failures"),
+            replay_early_failures: counter("replay_early_failures"),
+            out_requests: counter("out_requests"),
+            out_discards: counter("out_discards"),
+            acquire_requests: counter("acquire_requests"),
+            bytes_expired: counter("bytes_expired"),
+            crypto_failures: counter("crypto_failures"),
+        })
+    }
+
+    /// Compute the per second rates between `prev` and `self`
+    pub fn rates(&self, prev: &EspStats) -> EspRates {
+        let interval = self.snaptime - prev.snaptime;
+        EspRates {
+            good_auth: rate(prev.good_auth, self.good_auth, interval),
+            bad_auth: rate(prev.bad_auth, self.bad_auth, interval),
+            bad_decrypt: rate(prev.bad_decrypt, self.bad_decrypt, interval),
+            replay_failures: rate(prev.replay_failures, self.replay_failures, interval),
+            out_requests: rate(prev.out_requests, self.out_requests, interval),
+            out_discards: rate(prev.out_discards, self.out_discards, interval),
+            crypto_failures: rate(prev.crypto_failures, self.crypto_failures, interval),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+    use kstat_named::KstatNamedData::{DataUInt32, DataUInt64};
+
+    #[test]
+    fn decode_and_rates() {
+        let stats = vec![("good_auth", DataUInt64(100)), ("bad_auth", DataUInt32(2))];
+        let prev = AhStats::from_kstat(&kstat("ipsecah", 2, "ah_stat", stats)).unwrap();
+        assert_eq!(prev.stack, 2);
+        assert_eq!(prev.good_auth.value, 100);
+        assert_eq!(prev.bad_auth.value, 2);
+        let cur = AhStats {
+            snaptime: prev.snaptime + 1_000_000_000,
+            good_auth: Counter {
+                value: 150,
+                ..prev.good_auth
+            },
+            ..prev
+        };
+        assert_eq!(cur.rates(&prev).good_auth, 50.0);
+        assert_eq!(cur.rates(&prev).bad_auth, 0.0);
+
+        let stats = vec![("bad_decrypt", DataUInt32(u32::MAX))];
+        let prev = EspStats::from_kstat(&kstat("ipsecesp", 0, "esp_stat", stats)).unwrap();
+        let cur = EspStats {
+            snaptime: prev.snaptime + 2_000_000_000,
+            bad_decrypt: Counter {
+                value: 3,
+                ..prev.bad_decrypt
+            },
+            ..prev
+        };
+        assert_eq!(cur.rates(&prev).bad_decrypt, 2.0);
+
+        let ah = kstat("ipsecah", 0, "ah_stat", Vec::new());
+        assert!(EspStats::from_kstat(&ah).is_none());
+    }
+}
@@ -0,0 +1,295 @@
+//! Typed access to the per datalink `link:0:<link>` kstats, with the throughput reported by
+//! nicstat and `dladm show-link -s`.
+//!
+//! On SmartOS the kstat of every VNIC also names the zone the VNIC belongs to, which `by_zone`
+//! and `zone_rates` use to account network traffic per zone.
+
+use super::{rate, Counter};
+use {KstatData, KstatReader};
+
+use std::collections::BTreeMap;
+use std::io;
+
+/// The decoded `link:0:<link>` kstat of a single datalink
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LinkStats {
+    /// the datalink name, e.g. `net0` or `vnic1`
+    pub link: String,
+    /// the zone the datalink belongs to, if its kstat names one
+    pub zonename: Option<String>,
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// bytes received
+    pub rbytes: Counter,
+    /// bytes sent
+    pub obytes: Counter,
+    /// packets received
+    pub ipackets: Counter,
+    /// packets sent
+    pub opackets: Counter,
+    /// receive errors
+    pub ierrors: Counter,
+    /// send errors
+    pub oerrors: Counter,
+}
+
+/// Per second rates between two `LinkStats` snapshots
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LinkRates {
+    /// the datalink name
+    pub link: String,
+    /// bytes received per second
+    pub rbytes: f64,
+    /// bytes sent per second
+    pub obytes: f64,
+    /// packets received per second
+    pub ipackets: f64,
+    /// packets sent per second
+    pub opackets: f64,
+    /// receive errors per second
+    pub ierrors: f64,
+    /// send errors per second
+    pub oerrors: f64,
+}
+
+impl LinkStats {
+    /// Decode a `link:0:<link>` kstat, returning `None` if `data` is not one
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        if data.module != "link" {
+            return None;
+        }
+        let counter = |key| Counter::of(data, key);
+        Some(LinkStats {
+            link: data.name.clone(),
+            zonename: data.get_str("zonename").map(str::to_string),
+            snaptime: data.snaptime,
+            rbytes: counter("rbytes64"),
+            obytes: counter("obytes64"),
+            ipackets: counter("ipackets64"),
+            opackets: counter("opackets64"),
+            ierrors: counter("ierrors"),
+            oerrors: counter("oerrors"),
+        })
+    }
+
+    /// Compute the per second rates between `prev` and `self`, returning `None` if they are
+    /// snapshots of different links
+    pub fn rates(&self, prev: &LinkStats) -> Option<LinkRates> {
+        if self.link != prev.link {
+            return None;
+        }
+        let interval = self.snaptime - prev.snaptime;
+        Some(LinkRates {
+            link: self.link.clone(),
+            rbytes: rate(prev.rbytes, self.rbytes, interval),
+            obytes: rate(prev.obytes, self.obytes, interval),
+            ipackets: rate(prev.ipackets, self.ipackets, interval),
+            opackets: rate(prev.opackets, self.opackets, interval),
+            ierrors: rate(prev.ierrors, self.ierrors, interval),
+            oerrors: rate(prev.oerrors, self.oerrors, interval),
+        })
+    }
+}
+
+/// The combined traffic of the datalinks of a single zone
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ZoneTraffic {
+    /// the zone name
+    pub zonename: String,
+    /// the zone's datalinks, sorted by name
+    pub links: Vec<String>,
+    /// bytes received
+    pub rbytes: u64,
+    /// bytes sent
+    pub obytes: u64,
+    /// packets received
+    pub ipackets: u64,
+    /// packets sent
+    pub opackets: u64,
+    /// receive errors
+    pub ierrors: u64,
+    /// send errors
+    pub oerrors: u64,
+}
+
+/// Per second rates of the combined traffic of the datalinks of a single zone
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ZoneRates {
+    /// the zone name
+    pub zonename: String,
+    /// bytes received per second
+    pub rbytes: f64,
+    /// bytes sent per second
+    pub obytes: f64,
+    /// packets received per second
+    pub ipackets: f64,
+    /// packets sent per second
+    pub opackets: f64,
+    /// receive errors per second
+    pub ierrors: f64,
+    /// send errors per second
+    pub oerrors: f64,
+}
+
+/// Total the traffic of `links` per zone, sorted by zone name. Datalinks whose kstat does not
+/// name a zone, which is every datalink outside of SmartOS, are left out.
+pub fn by_zone(links: &[LinkStats]) -> Vec<ZoneTraffic> {
+    let mut zones: BTreeMap<&str, ZoneTraffic> = BTreeMap::new();
+    for link in links {
+        let zonename = match link.zonename {
+            Some(ref zonename) => zonename,
+            None => continue,
+        };
+        let zone = zones.entry(zonename).or_insert_with(|| ZoneTraffic {
+            zonename: zonename.clone(),
+            ..ZoneTraffic::default()
+        });
+        zone.links.push(link.link.clone());
+        zone.rbytes = zone.rbytes.saturating_add(link.rbytes.value);
+        zone.obytes = zone.obytes.saturating_add(link.obytes.value);
+        zone.ipackets = zone.ipackets.saturating_add(link.ipackets.value);
+        zone.opackets = zone.opackets.saturating_add(link.opackets.value);
+        zone.ierrors = zone.ierrors.saturating_add(link.ierrors.value);
+        zone.oerrors = zone.oerrors.saturating_add(link.oerrors.value);
+    }
+    zones
+        .into_values()
+        .map(|mut zone| {
+            zone.links.sort();
+            zone
+        })
+        .collect()
+}
+
+/// Compute the per second rates of the traffic of every zone between the `prev` and `cur`
+/// snapshots of its datalinks, sorted by zone name. Only datalinks present in both snapshots
+/// count, so a VNIC that is created or deleted in between does not show up as a burst of
+/// traffic.
+pub fn zone_rates(prev: &[LinkStats], cur: &[LinkStats]) -> Vec<ZoneRates> {
+    let mut zones: BTreeMap<&str, ZoneRates> = BTreeMap::new();
+    for link in cur {
+        let zonename = match link.zonename {
+            Some(ref zonename) => zonename,
+            None => continue,
+        };
+        let r = match prev.iter().find(|p| p.link == link.link) {
+            Some(p) => link.rates(p).unwrap_or_default(),
+            None => continue,
+        };
+        let zone = zones.entry(zonename).or_insert_with(|| ZoneRates {
+            zonename: zonename.clone(),
+            ..ZoneRates::default()
+        });
+        zone.rbytes += r.rbytes;
+        zone.obytes += r.obytes;
+        zone.ipackets += r.ipackets;
+        zone.opackets += r.opackets;
+        zone.ierrors += r.ierrors;
+        zone.oerrors += r.oerrors;
+    }
+    zones.into_values().collect()
+}
+
+/// Read the statistics of every datalink, sorted by name
+///
+/// # Example
+/// ```
+/// # use std::thread;
+/// # use std::time::Duration;
+/// let prev = kstat::helpers::link::read().expect("failed to read link kstats");
+/// thread::sleep(Duration::from_secs(1));
+/// for cur in kstat::helpers::link::read().expect("failed to read link kstats") {
+///     if let Some(r) = prev.iter().find(|p| p.link == cur.link).and_then(|p| cur.rates(p)) {
+///         println!("{}: {:.0} B/s in, {:.0} B/s out", r.link, r.rbytes, r.obytes);
+///     }
+/// }
+/// ```
+pub fn read() -> io::Result<Vec<LinkStats>> {
+    let reader = KstatReader::builder().module("link").instance(0).build()?;
+    let mut ret: Vec<LinkStats> = reader
+        .read()?
+        .iter()
+        .filter_map(LinkStats::from_kstat)
+        .collect();
+    ret.sort_by(|a, b| a.link.cmp(&b.link));
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+    use kstat_named::KstatNamedData::{DataString, DataUInt32, DataUInt64};
+
+    #[test]
+    fn decode_and_rates() {
+        let stats = vec![("rbytes64", DataUInt64(1000)), ("ierrors", DataUInt32(1))];
+        let prev = LinkStats::from_kstat(&kstat("link", 0, "net0", stats)).unwrap();
+        assert_eq!(prev.link, "net0");
+        assert_eq!(prev.rbytes.value, 1000);
+        assert_eq!(prev.ierrors.value, 1);
+        assert_eq!(prev.obytes.value, 0);
+
+        let cur = LinkStats {
+            snaptime: prev.snaptime + 2_000_000_000,
+            rbytes: Counter {
+                value: 5000,
+                ..prev.rbytes
+            },
+            ..prev.clone()
+        };
+        let rates = cur.rates(&prev).unwrap();
+        assert_eq!(rates.link, "net0");
+        assert_eq!(rates.rbytes, 2000.0);
+        assert_eq!(rates.ierrors, 0.0);
+
+        let other = LinkStats {
+            link: "net1".to_string(),
+            ..cur
+        };
+        assert!(other.rates(&prev).is_none());
+        assert!(LinkStats::from_kstat(&kstat("net", 0, "net0", Vec::new())).is_none());
+    }
+
+    #[test]
+    fn per_zone() {
+        let vnic = |link: &str, zonename: &str, rbytes| {
+            let stats = vec![
+                ("zonename", DataString(zonename.to_string())),
+                ("rbytes64", DataUInt64(rbytes)),
+            ];
+            LinkStats::from_kstat(&kstat("link", 0, link, stats)).unwrap()
+        };
+        let prev = vec![
+            vnic("z1_net0", "web", 100),
+            vnic("z1_net1", "web", 200),
+            vnic("z2_net0", "db", 1000),
+            // not assigned to a zone
+            LinkStats::from_kstat(&kstat("link", 0, "net0", Vec::new())).unwrap(),
+        ];
+        let traffic = by_zone(&prev);
+        assert_eq!(traffic.len(), 2);
+        assert_eq!(traffic[0].zonename, "db");
+        assert_eq!(traffic[1].links, vec!["z1_net0", "z1_net1"]);
+        assert_eq!(traffic[1].rbytes, 300);
+
+        let later = |link: LinkStats, rbytes| LinkStats {
+            snaptime: 1_000_000_000,
+            rbytes: Counter {
+                value: rbytes,
+                ..link.rbytes
+            },
+            ..link
+        };
+        let cur = vec![
+            later(vnic("z1_net0", "web", 0), 150),
+            later(vnic("z1_net1", "web", 0), 250),
+            // created after the first snapshot
+            later(vnic("z1_net2", "web", 0), 5000),
+        ];
+        let rates = zone_rates(&prev, &cur);
+        assert_eq!(rates.len(), 1);
+        assert_eq!(rates[0].zonename, "web");
+        assert_eq!(rates[0].rbytes, 100.0);
+    }
+}
@@ -0,0 +1,146 @@
+//! Typed access to the per zone `memory_cap:<zoneid>:<zonename>` kstats, with the rss and swap
+//! breakdowns and the pageout rates that signal memory pressure.
+
+use super::{rate, Counter};
+use {KstatData, KstatReader};
+
+use std::io;
+
+/// The decoded `memory_cap` kstat of a single zone. Sizes are in bytes and a cap of 0 means the
+/// zone is uncapped.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MemoryCap {
+    /// the zone id, which is also the kstat instance
+    pub zoneid: i32,
+    /// the zone name
+    pub zonename: String,
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// resident set size
+    pub rss: u64,
+    /// the physical memory cap
+    pub physcap: u64,
+    /// swap reserved
+    pub swap: u64,
+    /// the swap cap
+    pub swapcap: u64,
+    /// times the zone went over its physical memory cap
+    pub nover: Counter,
+    /// bytes paged out to bring the zone back under its cap
+    pub pagedout: Counter,
+}
+
+/// The memory pressure of a zone between two `MemoryCap` snapshots
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MemoryCapRates {
+    /// rss as a percentage of the physical memory cap, or `None` if the zone is uncapped
+    pub rss_pct: Option<f64>,
+    /// swap as a percentage of the swap cap, or `None` if the zone is uncapped
+    pub swap_pct: Option<f64>,
+    /// times per second the zone went over its physical memory cap
+    pub nover: f64,
+    /// bytes paged out per second
+    pub pagedout: f64,
+}
+
+fn pct(used: u64, cap: u64) -> Option<f64> {
+    if cap == 0 {
+        None
+    } else {
+        Some(used as f64 * 100.0 / cap as f64)
+    }
+}
+
+impl MemoryCap {
+    /// Decode a `memory_cap` kstat, returning `None` if `data` is not one
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        if data.module != "memory_cap" {
+            return None;
+        }
+        let get = |key| data.get_u64(key).unwrap_or(0);
+        let counter = |key| Counter::of(data, key);
+        Some(MemoryCap {
+            zoneid: data.instance,
+            zonename: data.get_str("zonename").unwrap_or(&data.name).to_string(),
+            snaptime: data.snaptime,
+            rss: get("rss"),
+            physcap: get("physcap"),
+            swap: get("swap"),
+            swapcap: get("swapcap"),
+            nover: counter("nover"),
+            pagedout: counter("pagedout"),
+        })
+    }
+
+    /// Compute the memory pressure between `prev` and `self`, returning `None` if they are
+    /// snapshots of different zones
+    pub fn rates(&self, prev: &MemoryCap) -> Option<MemoryCapRates> {
+        if self.zoneid != prev.zoneid {
+            return None;
+        }
+
+        let interval = self.snaptime - prev.snaptime;
+        Some(MemoryCapRates {
+            rss_pct: pct(self.rss, self.physcap),
+            swap_pct: pct(self.swap, self.swapcap),
+            nover: rate(prev.nover, self.nover, interval),
+            pagedout: rate(prev.pagedout, self.pagedout, interval),
+        })
+    }
+}
+
+/// Read the memory cap of every zone, sorted by zone id
+pub fn read() -> io::Result<Vec<MemoryCap>> {
+    let reader = KstatReader::builder().module("memory_cap").build()?;
+    let mut ret: Vec<MemoryCap> = reader
+        .read()?
+        .iter()
+        .filter_map(MemoryCap::from_kstat)
+        .collect();
+    ret.sort_by_key(|c| c.zoneid);
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+    use kstat_named::KstatNamedData::DataUInt64;
+
+    #[test]
+    fn decode_and_pressure() {
+        let stats = vec![
+            ("rss", DataUInt64(256)),
+            ("physcap", DataUInt64(1024)),
+            ("swap", DataUInt64(10)),
+            ("pagedout", DataUInt64(4096)),
+        ];
+        let prev = MemoryCap::from_kstat(&kstat("memory_cap", 5, "web", stats)).unwrap();
+        assert_eq!(prev.zoneid, 5);
+        assert_eq!(prev.zonename, "web");
+        assert_eq!(prev.rss, 256);
+
+        let cur = MemoryCap {
+            snaptime: prev.snaptime + 2_000_000_000,
+            rss: 512,
+            nover: Counter {
+                value: 1,
+                ..prev.nover
+            },
+            pagedout: Counter {
+                value: 8192,
+                ..prev.pagedout
+            },
+            ..prev.clone()
+        };
+        let rates = cur.rates(&prev).unwrap();
+        assert_eq!(rates.rss_pct, Some(50.0));
+        assert_eq!(rates.swap_pct, None);
+        assert_eq!(rates.nover, 0.5);
+        assert_eq!(rates.pagedout, 2048.0);
+
+        let other = MemoryCap { zoneid: 6, ..cur };
+        assert!(other.rates(&prev).is_none());
+        assert!(MemoryCap::from_kstat(&kstat("caps", 5, "web", Vec::new())).is_none());
+    }
+}
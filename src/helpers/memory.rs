@@ -0,0 +1,112 @@
+//! A system wide memory summary, in bytes, built from the page counts in the
+//! `unix:0:system_pages` kstat.
+
+use {KstatData, KstatReader};
+
+use libc;
+
+use std::io;
+
+/// The decoded `unix:0:system_pages` kstat, converted from pages to bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemorySummary {
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// the page size the page counts were converted with
+    pub page_size: u64,
+    /// physical memory installed
+    pub physmem: u64,
+    /// memory managed by the page allocator
+    pub total: u64,
+    /// free memory
+    pub freemem: u64,
+    /// memory available to be locked or reserved by the kernel
+    pub availrmem: u64,
+    /// memory that is locked
+    pub locked: u64,
+    /// memory used by the kernel
+    pub kernel: u64,
+    /// the free memory below which the page scanner starts
+    pub lotsfree: u64,
+    /// the free memory below which swapping starts
+    pub desfree: u64,
+    /// the free memory below which only the kernel may allocate
+    pub minfree: u64,
+}
+
+impl MemorySummary {
+    /// Read `unix:0:system_pages` and convert it using the system page size
+    ///
+    /// # Example
+    /// ```
+    /// use kstat::helpers::memory::MemorySummary;
+    ///
+    /// let mem = MemorySummary::read().expect("failed to read system_pages");
+    /// println!("{} of {} MB free", mem.freemem >> 20, mem.physmem >> 20);
+    /// ```
+    pub fn read() -> io::Result<Self> {
+        let page_size = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            -1 => return Err(io::Error::last_os_error()),
+            n => n as u64,
+        };
+        let reader = KstatReader::new()?;
+        reader
+            .read_one("unix", 0, "system_pages")?
+            .and_then(|data| MemorySummary::from_kstat(&data, page_size))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unix:0:system_pages not found"))
+    }
+
+    /// Decode an already read `unix:0:system_pages` kstat, converting page counts to bytes with
+    /// `page_size`. Returns `None` if `data` is not a system_pages kstat.
+    pub fn from_kstat(data: &KstatData, page_size: u64) -> Option<Self> {
+        if data.module != "unix" || data.name != "system_pages" {
+            return None;
+        }
+        let get = |key| data.get_u64(key).unwrap_or(0).saturating_mul(page_size);
+        Some(MemorySummary {
+            snaptime: data.snaptime,
+            page_size,
+            physmem: get("physmem"),
+            total: get("pagestotal"),
+            freemem: get("freemem"),
+            availrmem: get("availrmem"),
+            locked: get("pageslocked"),
+            kernel: get("pp_kernel"),
+            lotsfree: get("lotsfree"),
+            desfree: get("desfree"),
+            minfree: get("minfree"),
+        })
+    }
+
+    /// Memory in use, i.e. managed by the page allocator but not free
+    pub fn used(&self) -> u64 {
+        self.total.saturating_sub(self.freemem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+    use kstat_named::KstatNamedData::{DataUInt32, DataUInt64};
+
+    #[test]
+    fn pages_to_bytes() {
+        let stats = vec![
+            ("physmem", DataUInt64(1024)),
+            ("pagestotal", DataUInt64(1000)),
+            ("freemem", DataUInt32(250)),
+            ("lotsfree", DataUInt64(u64::MAX)),
+        ];
+        let mem = MemorySummary::from_kstat(&kstat("unix", 0, "system_pages", stats), 4096);
+        let mem = mem.unwrap();
+        assert_eq!(mem.physmem, 4 << 20);
+        assert_eq!(mem.freemem, 250 * 4096);
+        assert_eq!(mem.used(), 750 * 4096);
+        assert_eq!(mem.lotsfree, u64::MAX);
+        assert_eq!(mem.kernel, 0);
+
+        let other = kstat("unix", 0, "vminfo", Vec::new());
+        assert!(MemorySummary::from_kstat(&other, 4096).is_none());
+    }
+}
@@ -0,0 +1,172 @@
+//! Typed helpers for commonly used illumos kstats.
+
+use accum::{self, Width};
+use kstat_named::{ConversionError, KstatNamedData};
+use {Data, KstatData, KstatReader};
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+
+pub mod caps;
+pub mod cpu;
+pub mod deverr;
+pub mod device;
+pub mod icmp;
+pub mod identity;
+pub mod iostat;
+pub mod ipsec;
+pub mod link;
+pub mod memcap;
+pub mod memory;
+pub mod mpstat;
+pub mod sctp;
+pub mod segmap;
+pub mod tcp;
+pub mod zfs;
+pub mod zones;
+
+/// How the helpers generated by `kstat_struct!` handle statistics that are missing or have an
+/// unexpected type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeMode {
+    /// fail to decode the kstat
+    Strict,
+    /// leave the field at its default value
+    Lenient,
+}
+
+/// Decode the statistic `key` of `data` for a struct generated by `kstat_struct!`
+#[doc(hidden)]
+pub fn decode_field<'a, T>(data: &'a KstatData, key: &str, mode: DecodeMode) -> io::Result<T>
+where
+    T: TryFrom<&'a KstatNamedData, Error = ConversionError> + Default,
+{
+    let ret = match data.get(key) {
+        Some(value) => T::try_from(value).map_err(|e| {
+            let msg = format!(
+                "{}:{}:{}:{}: {}",
+                data.module, data.instance, data.name, key, e
+            );
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        }),
+        None => {
+            let msg = format!(
+                "{}:{}:{}: missing {}",
+                data.module, data.instance, data.name, key
+            );
+            Err(io::Error::new(io::ErrorKind::NotFound, msg))
+        }
+    };
+
+    match mode {
+        DecodeMode::Strict => ret,
+        DecodeMode::Lenient => Ok(ret.unwrap_or_default()),
+    }
+}
+
+/// Look up a named statistic in `data`, widening any integer type to i64
+fn named_i64(data: &KstatData, key: &str) -> Option<i64> {
+    match data.data {
+        Data::Named(ref named) => match named.get(key) {
+            Some(&KstatNamedData::DataInt32(i)) => Some(i64::from(i)),
+            Some(&KstatNamedData::DataUInt32(u)) => Some(i64::from(u)),
+            Some(&KstatNamedData::DataInt64(i)) => Some(i),
+            Some(&KstatNamedData::DataUInt64(u)) => Some(u as i64),
+            _ => None,
+        },
+        Data::Io(_) => None,
+    }
+}
+
+/// A counter decoded by one of the helpers, along with its width so that its rate can tell a
+/// 32-bit counter that wrapped from a 64-bit counter that was reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Counter {
+    /// the value of the counter
+    pub value: u64,
+    /// the width of the counter's statistic
+    pub width: Width,
+}
+
+impl Counter {
+    /// The change of the counter since `prev`, or `None` if it went backwards because it was
+    /// reset. See `accum::counter_delta`.
+    pub fn delta(self, prev: Counter) -> Option<u64> {
+        accum::counter_delta(prev.value, self.value, self.width)
+    }
+
+    /// Look up the counter `key` in `data`. A missing counter is 0, and one that is not unsigned
+    /// is taken to be 64 bits wide.
+    fn of(data: &KstatData, key: &str) -> Counter {
+        Counter {
+            value: data.get_u64(key).unwrap_or(0),
+            width: data.get(key).and_then(Width::of).unwrap_or(Width::Bits64),
+        }
+    }
+
+    /// The sum of two counters, e.g. of the read and write failures of reader/writer locks. The
+    /// sum of two 32-bit counters wraps at 2^32 like they do, so that its change is still right
+    /// after either of them wrapped.
+    fn sum(self, other: Counter) -> Counter {
+        match (self.width, other.width) {
+            (Width::Bits32, Width::Bits32) => Counter {
+                value: (self.value + other.value) & u64::from(u32::MAX),
+                width: Width::Bits32,
+            },
+            _ => Counter {
+                value: self.value.wrapping_add(other.value),
+                width: Width::Bits64,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Counter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+/// The per second rate of a counter between two snapshots taken `interval` nanoseconds apart.
+/// Wrapped 32-bit counters are handled as described in `accum::counter_delta`, and the rate of
+/// a 64-bit counter that went backwards because it was reset is 0.
+fn rate(prev: Counter, cur: Counter, interval: i64) -> f64 {
+    if interval <= 0 {
+        return 0.0;
+    }
+    cur.delta(prev).map_or(0.0, |delta| {
+        delta as f64 / (interval as f64 / 1_000_000_000.0)
+    })
+}
+
+/// Read the MIB kstat `module:stack:name` of the IP stack `stack` and decode it with `decode`.
+/// The global zone and the zones sharing its IP stack use stack 0, and every exclusive-IP zone
+/// has a stack of its own whose ID is its zone ID.
+fn read_mib<T, F>(module: &str, stack: i32, name: &str, decode: F) -> io::Result<T>
+where
+    F: Fn(&KstatData) -> Option<T>,
+{
+    KstatReader::new()?
+        .read_one(module, stack, name)?
+        .as_ref()
+        .and_then(decode)
+        .ok_or_else(|| {
+            let msg = format!("{}:{}:{} not found", module, stack, name);
+            io::Error::new(io::ErrorKind::NotFound, msg)
+        })
+}
+
+/// Read the MIB kstat `module:*:name` of every IP stack and decode it with `decode`, sorted by
+/// stack ID
+fn read_mibs<T, F>(module: &str, name: &str, decode: F) -> io::Result<Vec<T>>
+where
+    F: Fn(&KstatData) -> Option<T>,
+{
+    let reader = KstatReader::builder()
+        .module(module)
+        .name(name)
+        .sorted(true)
+        .build()?;
+    Ok(reader.read()?.iter().filter_map(decode).collect())
+}
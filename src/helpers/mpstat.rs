@@ -0,0 +1,198 @@
+//! The per CPU event counters reported by mpstat(1M), combined from the `cpu:<cpu>:sys` and
+//! `cpu:<cpu>:vm` kstats.
+
+use super::{rate, Counter};
+use {KstatData, KstatReader};
+
+use std::collections::BTreeMap;
+use std::io;
+
+/// The cumulative event counters of a single CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuEvents {
+    /// the CPU id, which is also the kstat instance
+    pub cpu_id: i32,
+    /// nanoseconds since boot of the `sys` snapshot
+    pub snaptime: i64,
+    /// minor faults (`hat_fault` + `as_fault`)
+    pub minf: Counter,
+    /// major faults
+    pub mjf: Counter,
+    /// cross calls
+    pub xcal: Counter,
+    /// interrupts
+    pub intr: Counter,
+    /// interrupts handled as threads
+    pub ithr: Counter,
+    /// context switches
+    pub csw: Counter,
+    /// involuntary context switches
+    pub icsw: Counter,
+    /// thread migrations to this CPU
+    pub migr: Counter,
+    /// spins on mutexes
+    pub smtx: Counter,
+    /// spins on reader/writer locks
+    pub srw: Counter,
+    /// system calls
+    pub syscl: Counter,
+}
+
+/// The per second rates of a CPU's events between two `CpuEvents` snapshots, i.e. one line of
+/// mpstat output without the time columns
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CpuEventRates {
+    /// the CPU id
+    pub cpu_id: i32,
+    /// minor faults per second
+    pub minf: f64,
+    /// major faults per second
+    pub mjf: f64,
+    /// cross calls per second
+    pub xcal: f64,
+    /// interrupts per second
+    pub intr: f64,
+    /// interrupts handled as threads per second
+    pub ithr: f64,
+    /// context switches per second
+    pub csw: f64,
+    /// involuntary context switches per second
+    pub icsw: f64,
+    /// thread migrations per second
+    pub migr: f64,
+    /// spins on mutexes per second
+    pub smtx: f64,
+    /// spins on reader/writer locks per second
+    pub srw: f64,
+    /// system calls per second
+    pub syscl: f64,
+}
+
+impl CpuEvents {
+    /// Combine the `cpu:<cpu>:sys` and `cpu:<cpu>:vm` kstats of a single CPU, returning `None`
+    /// if they are not those kstats or belong to different CPUs
+    pub fn from_kstats(sys: &KstatData, vm: &KstatData) -> Option<Self> {
+        if sys.module != "cpu" || sys.name != "sys" || vm.module != "cpu" || vm.name != "vm" {
+            return None;
+        }
+        if sys.instance != vm.instance {
+            return None;
+        }
+        let sys_get = |key| Counter::of(sys, key);
+        let vm_get = |key| Counter::of(vm, key);
+        Some(CpuEvents {
+            cpu_id: sys.instance,
+            snaptime: sys.snaptime,
+            minf: vm_get("hat_fault").sum(vm_get("as_fault")),
+            mjf: vm_get("maj_fault"),
+            xcal: sys_get("xcalls"),
+            intr: sys_get("intr"),
+            ithr: sys_get("intrthread"),
+            csw: sys_get("pswitch"),
+            icsw: sys_get("inv_swtch"),
+            migr: sys_get("cpumigrate"),
+            smtx: sys_get("mutex_adenters"),
+            srw: sys_get("rw_rdfails").sum(sys_get("rw_wrfails")),
+            syscl: sys_get("syscall"),
+        })
+    }
+
+    /// Compute the per second rates between `prev` and `self`, returning `None` if they are
+    /// snapshots of different CPUs
+    pub fn rates(&self, prev: &CpuEvents) -> Option<CpuEventRates> {
+        if self.cpu_id != prev.cpu_id {
+            return None;
+        }
+        let interval = self.snaptime - prev.snaptime;
+        Some(CpuEventRates {
+            cpu_id: self.cpu_id,
+            minf: rate(prev.minf, self.minf, interval),
+            mjf: rate(prev.mjf, self.mjf, interval),
+            xcal: rate(prev.xcal, self.xcal, interval),
+            intr: rate(prev.intr, self.intr, interval),
+            ithr: rate(prev.ithr, self.ithr, interval),
+            csw: rate(prev.csw, self.csw, interval),
+            icsw: rate(prev.icsw, self.icsw, interval),
+            migr: rate(prev.migr, self.migr, interval),
+            smtx: rate(prev.smtx, self.smtx, interval),
+            srw: rate(prev.srw, self.srw, interval),
+            syscl: rate(prev.syscl, self.syscl, interval),
+        })
+    }
+}
+
+/// Read the event counters of every CPU, sorted by CPU id. CPUs missing either kstat, e.g.
+/// because they went offline between the two, are left out.
+///
+/// # Example
+/// ```
+/// # use std::thread;
+/// # use std::time::Duration;
+/// let prev = kstat::helpers::mpstat::read().expect("failed to read cpu kstats");
+/// thread::sleep(Duration::from_secs(1));
+/// let cur = kstat::helpers::mpstat::read().expect("failed to read cpu kstats");
+/// for (p, c) in prev.iter().zip(cur.iter()) {
+///     if let Some(r) = c.rates(p) {
+///         println!("{} {:.0} {:.0} {:.0}", r.cpu_id, r.xcal, r.csw, r.syscl);
+///     }
+/// }
+/// ```
+pub fn read() -> io::Result<Vec<CpuEvents>> {
+    let reader = KstatReader::builder().module("cpu").build()?;
+    let mut sys = BTreeMap::new();
+    let mut vm = BTreeMap::new();
+    for data in reader.read()? {
+        match data.name.as_str() {
+            "sys" => {
+                sys.insert(data.instance, data);
+            }
+            "vm" => {
+                vm.insert(data.instance, data);
+            }
+            _ => {}
+        }
+    }
+    Ok(sys
+        .iter()
+        .filter_map(|(cpu, s)| vm.get(cpu).and_then(|v| CpuEvents::from_kstats(s, v)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+    use kstat_named::KstatNamedData::DataUInt64;
+
+    fn events(cpu: i32, snaptime: i64, xcalls: u64, as_fault: u64) -> CpuEvents {
+        let sys = KstatData {
+            snaptime,
+            ..kstat("cpu", cpu, "sys", vec![("xcalls", DataUInt64(xcalls))])
+        };
+        let vm = vec![
+            ("hat_fault", DataUInt64(3)),
+            ("as_fault", DataUInt64(as_fault)),
+        ];
+        CpuEvents::from_kstats(&sys, &kstat("cpu", cpu, "vm", vm)).unwrap()
+    }
+
+    #[test]
+    fn combine_and_rates() {
+        let prev = events(1, 1_000_000_000, 100, 4);
+        assert_eq!(prev.cpu_id, 1);
+        assert_eq!(prev.snaptime, 1_000_000_000);
+        assert_eq!(prev.minf.value, 7);
+        assert_eq!(prev.xcal.value, 100);
+        assert_eq!(prev.csw.value, 0);
+
+        let rates = events(1, 3_000_000_000, 300, 14).rates(&prev).unwrap();
+        assert_eq!(rates.xcal, 100.0);
+        assert_eq!(rates.minf, 5.0);
+        assert_eq!(rates.syscl, 0.0);
+
+        assert!(events(2, 3_000_000_000, 300, 14).rates(&prev).is_none());
+        let sys = kstat("cpu", 0, "sys", Vec::new());
+        assert!(CpuEvents::from_kstats(&sys, &kstat("cpu", 1, "vm", Vec::new())).is_none());
+        assert!(CpuEvents::from_kstats(&sys, &sys).is_none());
+    }
+}
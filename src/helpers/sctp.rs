@@ -0,0 +1,144 @@
+//! Typed access to the `sctp:<stack>:sctp` MIB kstats, with the association gauge and rates
+//! reported by `netstat -s -P sctp`. There is one per IP stack, see `helpers::tcp`.
+
+use super::{rate, read_mib, read_mibs, Counter};
+use KstatData;
+
+use std::io;
+
+/// A decoded `sctp:<stack>:sctp` kstat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SctpStats {
+    /// the IP stack these counters belong to
+    pub stack: i32,
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// associations currently in ESTABLISHED, SHUTDOWN-PENDING or SHUTDOWN-RECEIVED
+    pub curr_estab: u64,
+    /// associations established from COOKIE-ECHOED
+    pub active_estab: Counter,
+    /// associations established from CLOSED
+    pub passive_estab: Counter,
+    /// associations that went to CLOSED via ABORT
+    pub aborted: Counter,
+    /// associations that went to CLOSED via a graceful shutdown
+    pub shutdowns: Counter,
+    /// out of the blue packets received
+    pub out_of_blue: Counter,
+    /// packets received with an invalid checksum
+    pub checksum_errors: Counter,
+    /// SCTP packets received
+    pub in_pkts: Counter,
+    /// SCTP packets sent
+    pub out_pkts: Counter,
+    /// data chunks retransmitted
+    pub retrans_chunks: Counter,
+}
+
+/// Per second rates between two `SctpStats` snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SctpRates {
+    /// active associations established per second
+    pub active_estab: f64,
+    /// passive associations established per second
+    pub passive_estab: f64,
+    /// associations aborted per second
+    pub aborted: f64,
+    /// associations shut down per second
+    pub shutdowns: f64,
+    /// packets received per second
+    pub in_pkts: f64,
+    /// packets sent per second
+    pub out_pkts: f64,
+    /// data chunks retransmitted per second
+    pub retrans_chunks: f64,
+}
+
+impl SctpStats {
+    /// Read the `sctp:<stack>:sctp` kstat of IP stack `stack`
+    pub fn read(stack: i32) -> io::Result<Self> {
+        read_mib("sctp", stack, "sctp", SctpStats::from_kstat)
+    }
+
+    /// Read the `sctp:<stack>:sctp` kstat of every IP stack, sorted by stack
+    pub fn read_all() -> io::Result<Vec<Self>> {
+        read_mibs("sctp", "sctp", SctpStats::from_kstat)
+    }
+
+    /// Decode an already read `sctp:<stack>:sctp` kstat, returning `None` if `data` is not one
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        if data.module != "sctp" || data.name != "sctp" {
+            return None;
+        }
+        let get = |key| data.get_u64(key).unwrap_or(0);
+        let counter = |key| Counter::of(data, key);
+        Some(SctpStats {
+            stack: data.instance,
+            snaptime: data.snaptime,
+            curr_estab: get("sctpCurrEstab"),
+            active_estab: counter("sctpActiveEstab"),
+            passive_estab: counter("sctpPassiveEstab"),
+            aborted: counter("sctpAborted"),
+            shutdowns: counter("sctpShutdowns"),
+            out_of_blue: counter("sctpOutOfBlue"),
+            checksum_errors: counter("sctpChecksumError"),
+            in_pkts: counter("sctpInSCTPPkts"),
+            out_pkts: counter("sctpOutSCTPPkts"),
+            retrans_chunks: counter("sctpRetransChunks"),
+        })
+    }
+
+    /// Compute the per second rates between `prev` and `self`
+    pub fn rates(&self, prev: &SctpStats) -> SctpRates {
+        let interval = self.snaptime - prev.snaptime;
+        SctpRates {
+            active_estab: rate(prev.active_estab, self.active_estab, interval),
+            passive_estab: rate(prev.passive_estab, self.passive_estab, interval),
+            aborted: rate(prev.aborted, self.aborted, interval),
+            shutdowns: rate(prev.shutdowns, self.shutdowns, interval),
+            in_pkts: rate(prev.in_pkts, self.in_pkts, interval),
+            out_pkts: rate(prev.out_pkts, self.out_pkts, interval),
+            retrans_chunks: rate(prev.retrans_chunks, self.retrans_chunks, interval),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+    use kstat_named::KstatNamedData::{DataUInt32, DataUInt64};
+
+    #[test]
+    fn decode_and_rates() {
+        let stats = vec![
+            ("sctpCurrEstab", DataUInt32(2)),
+            ("sctpAborted", DataUInt64(5)),
+            ("sctpInSCTPPkts", DataUInt64(100)),
+        ];
+        let prev = SctpStats::from_kstat(&kstat("sctp", 0, "sctp", stats)).unwrap();
+        assert_eq!(prev.stack, 0);
+        assert_eq!(prev.curr_estab, 2);
+        assert_eq!(prev.in_pkts.value, 100);
+        assert_eq!(prev.out_pkts.value, 0);
+
+        let cur = SctpStats {
+            snaptime: prev.snaptime + 500_000_000,
+            aborted: Counter {
+                value: 6,
+                ..prev.aborted
+            },
+            in_pkts: Counter {
+                value: 150,
+                ..prev.in_pkts
+            },
+            ..prev
+        };
+        let rates = cur.rates(&prev);
+        assert_eq!(rates.aborted, 2.0);
+        assert_eq!(rates.in_pkts, 100.0);
+        assert_eq!(rates.shutdowns, 0.0);
+
+        assert!(SctpStats::from_kstat(&kstat("tcp", 0, "sctp", Vec::new())).is_none());
+    }
+}
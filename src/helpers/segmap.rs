@@ -0,0 +1,131 @@
+//! Typed access to the `unix:0:segmap` kstat, which tracks the kernel's file system page cache
+//! mappings.
+
+use super::Counter;
+use {KstatData, KstatReader};
+
+use std::io;
+
+/// The decoded `unix:0:segmap` kstat. All values are counters since boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SegmapStats {
+    /// number of segmap faults
+    pub fault: Counter,
+    /// number of segmap fault-aheads
+    pub faulta: Counter,
+    /// number of segmap_getmap calls
+    pub getmap: Counter,
+    /// getmaps that found the page already mapped and in use
+    pub get_use: Counter,
+    /// getmaps that reclaimed a cached mapping
+    pub get_reclaim: Counter,
+    /// getmaps that reused a free slot
+    pub get_reuse: Counter,
+    /// getmaps that found an unused slot
+    pub get_unused: Counter,
+    /// getmaps that found no free slot
+    pub get_nofree: Counter,
+    /// number of segmap_release calls
+    pub release: Counter,
+    /// releases with SM_ASYNC
+    pub rel_async: Counter,
+    /// releases with SM_WRITE
+    pub rel_write: Counter,
+    /// releases with SM_FREE
+    pub rel_free: Counter,
+    /// releases with SM_ABORT
+    pub rel_abort: Counter,
+    /// releases with SM_DONTNEED
+    pub rel_dontneed: Counter,
+    /// number of segmap_pagecreate calls
+    pub pagecreate: Counter,
+    /// pages stolen from the segmap cache
+    pub stolen_pages: Counter,
+}
+
+impl SegmapStats {
+    /// Read the `unix:0:segmap` kstat
+    pub fn read() -> io::Result<Self> {
+        let reader = KstatReader::builder()
+            .module("unix")
+            .name("segmap")
+            .build()?;
+        reader
+            .read()?
+            .iter()
+            .filter_map(SegmapStats::from_kstat)
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unix:0:segmap not found"))
+    }
+
+    /// Decode an already read `unix:0:segmap` kstat. Statistics missing on this platform are
+    /// left as 0; returns `None` if `data` is not a segmap kstat.
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        if data.module != "unix" || data.name != "segmap" {
+            return None;
+        }
+        let counter = |key| Counter::of(data, key);
+        Some(SegmapStats {
+            fault: counter("fault"),
+            faulta: counter("faulta"),
+            getmap: counter("getmap"),
+            get_use: counter("get_use"),
+            get_reclaim: counter("get_reclaim"),
+            get_reuse: counter("get_reuse"),
+            get_unused: counter("get_unused"),
+            get_nofree: counter("get_nofree"),
+            release: counter("release"),
+            rel_async: counter("rel_async"),
+            rel_write: counter("rel_write"),
+            rel_free: counter("rel_free"),
+            rel_abort: counter("rel_abort"),
+            rel_dontneed: counter("rel_dontneed"),
+            pagecreate: counter("pagecreate"),
+            stolen_pages: counter("stolen_pages"),
+        })
+    }
+
+    /// The fraction of getmap calls between `prev` and `self` that were satisfied by a mapping
+    /// already in the cache, or `None` if there were no getmap calls or a counter was reset
+    pub fn hit_ratio(&self, prev: &SegmapStats) -> Option<f64> {
+        let getmap = self.getmap.delta(prev.getmap)?;
+        if getmap == 0 {
+            return None;
+        }
+        let hits = self.get_reclaim.delta(prev.get_reclaim)? + self.get_use.delta(prev.get_use)?;
+        Some(hits as f64 / getmap as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+    use kstat_named::KstatNamedData::{DataUInt32, DataUInt64};
+
+    #[test]
+    fn decode_and_hit_ratio() {
+        let stats = vec![
+            ("getmap", DataUInt64(100)),
+            ("get_use", DataUInt32(10)),
+            ("get_reclaim", DataUInt64(20)),
+        ];
+        let prev = SegmapStats::from_kstat(&kstat("unix", 0, "segmap", stats)).unwrap();
+        assert_eq!(prev.getmap.value, 100);
+        assert_eq!(prev.get_use.value, 10);
+        assert_eq!(prev.stolen_pages.value, 0);
+        assert_eq!(prev.hit_ratio(&prev), None);
+
+        let mut cur = prev;
+        cur.getmap.value = 200;
+        cur.get_use.value = 40;
+        cur.get_reclaim.value = 70;
+        assert_eq!(cur.hit_ratio(&prev), Some(0.8));
+
+        // The 64-bit getmap counter went backwards, so it was reset
+        let mut reset = cur;
+        reset.getmap.value = 50;
+        assert_eq!(reset.hit_ratio(&prev), None);
+        assert!(SegmapStats::from_kstat(&kstat("unix", 0, "vminfo", Vec::new())).is_none());
+    }
+}
@@ -0,0 +1,142 @@
+//! Typed access to the `tcp:<stack>:tcp` MIB kstats, with the connection gauges and rates
+//! reported by `netstat -s`. There is one per IP stack: stack 0 is shared by the global zone and
+//! its shared-IP zones, and every exclusive-IP zone has its own, numbered by its zone ID.
+
+use super::{rate, read_mib, read_mibs, Counter};
+use KstatData;
+
+use std::io;
+
+/// A decoded `tcp:<stack>:tcp` kstat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TcpStats {
+    /// the IP stack these counters belong to
+    pub stack: i32,
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// connections currently in ESTABLISHED or CLOSE_WAIT
+    pub curr_estab: u64,
+    /// active opens (SYN_SENT from CLOSED)
+    pub active_opens: Counter,
+    /// passive opens (SYN_RCVD from LISTEN)
+    pub passive_opens: Counter,
+    /// failed connection attempts
+    pub attempt_fails: Counter,
+    /// resets of ESTABLISHED or CLOSE_WAIT connections
+    pub estab_resets: Counter,
+    /// segments sent with RST set
+    pub out_rsts: Counter,
+    /// segments received
+    pub in_segs: Counter,
+    /// segments sent
+    pub out_segs: Counter,
+    /// segments retransmitted
+    pub retrans_segs: Counter,
+}
+
+/// Per second rates between two `TcpStats` snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TcpRates {
+    /// active opens per second
+    pub active_opens: f64,
+    /// passive opens per second
+    pub passive_opens: f64,
+    /// failed connection attempts per second
+    pub attempt_fails: f64,
+    /// connection resets per second
+    pub estab_resets: f64,
+    /// RST segments sent per second
+    pub out_rsts: f64,
+    /// segments received per second
+    pub in_segs: f64,
+    /// segments sent per second
+    pub out_segs: f64,
+    /// segments retransmitted per second
+    pub retrans_segs: f64,
+}
+
+impl TcpStats {
+    /// Read the `tcp:<stack>:tcp` kstat of IP stack `stack`
+    pub fn read(stack: i32) -> io::Result<Self> {
+        read_mib("tcp", stack, "tcp", TcpStats::from_kstat)
+    }
+
+    /// Read the `tcp:<stack>:tcp` kstat of every IP stack, sorted by stack
+    pub fn read_all() -> io::Result<Vec<Self>> {
+        read_mibs("tcp", "tcp", TcpStats::from_kstat)
+    }
+
+    /// Decode an already read `tcp:<stack>:tcp` kstat, returning `None` if `data` is not one
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        if data.module != "tcp" || data.name != "tcp" {
+            return None;
+        }
+        let get = |key| data.get_u64(key).unwrap_or(0);
+        let counter = |key| Counter::of(data, key);
+        Some(TcpStats {
+            stack: data.instance,
+            snaptime: data.snaptime,
+            curr_estab: get("currEstab"),
+            active_opens: counter("activeOpens"),
+            passive_opens: counter("passiveOpens"),
+            attempt_fails: counter("attemptFails"),
+            estab_resets: counter("estabResets"),
+            out_rsts: counter("outRsts"),
+            in_segs: counter("inSegs"),
+            out_segs: counter("outSegs"),
+            retrans_segs: counter("retransSegs"),
+        })
+    }
+
+    /// Compute the per second rates between `prev` and `self`
+    pub fn rates(&self, prev: &TcpStats) -> TcpRates {
+        let interval = self.snaptime - prev.snaptime;
+        TcpRates {
+            active_opens: rate(prev.active_opens, self.active_opens, interval),
+            passive_opens: rate(prev.passive_opens, self.passive_opens, interval),
+            attempt_fails: rate(prev.attempt_fails, self.attempt_fails, interval),
+            estab_resets: rate(prev.estab_resets, self.estab_resets, interval),
+            out_rsts: rate(prev.out_rsts, self.out_rsts, interval),
+            in_segs: rate(prev.in_segs, self.in_segs, interval),
+            out_segs: rate(prev.out_segs, self.out_segs, interval),
+            retrans_segs: rate(prev.retrans_segs, self.retrans_segs, interval),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+    use kstat_named::KstatNamedData::{self, DataUInt32, DataUInt64};
+
+    fn tcp(stack: i32, snaptime: i64, estab: u64, in_segs: KstatNamedData) -> KstatData {
+        let stats = vec![("currEstab", DataUInt64(estab)), ("inSegs", in_segs)];
+        KstatData {
+            snaptime,
+            ..kstat("tcp", stack, "tcp", stats)
+        }
+    }
+
+    #[test]
+    fn decode_and_rates() {
+        let prev = tcp(3, 1_000_000_000, 7, DataUInt32(u32::MAX - 9));
+        let prev = TcpStats::from_kstat(&prev).unwrap();
+        assert_eq!(prev.stack, 3);
+        assert_eq!(prev.curr_estab, 7);
+        assert_eq!(prev.out_segs.value, 0);
+
+        // The 32-bit counter wrapped
+        let cur = TcpStats::from_kstat(&tcp(3, 3_000_000_000, 5, DataUInt32(10))).unwrap();
+        let rates = cur.rates(&prev);
+        assert_eq!(rates.in_segs, 10.0);
+        assert_eq!(rates.out_segs, 0.0);
+
+        // A 64-bit counter that went backwards was reset rather than wrapped
+        let prev = TcpStats::from_kstat(&tcp(3, 1_000_000_000, 7, DataUInt64(1000))).unwrap();
+        let cur = TcpStats::from_kstat(&tcp(3, 3_000_000_000, 5, DataUInt64(10))).unwrap();
+        assert_eq!(cur.rates(&prev).in_segs, 0.0);
+
+        assert!(TcpStats::from_kstat(&kstat("tcp", 0, "tcpstat", Vec::new())).is_none());
+    }
+}
@@ -0,0 +1,193 @@
+//! Typed access to the ZFS ARC statistics, `zfs:0:arcstats`.
+//!
+//! The set of statistics in arcstats changes between releases, so `ArcStats` is decoded
+//! leniently: statistics the running kernel does not have are left at 0 and statistics this
+//! crate does not know about are ignored. Code that only needs hit rates should use the
+//! `CacheStats` trait, which does not depend on the exact fields of `ArcStats`.
+
+use super::DecodeMode;
+use accum::{self, Width};
+use {KstatData, KstatReader};
+
+use std::io;
+
+kstat_struct! {
+    /// The decoded `zfs:0:arcstats` kstat. Sizes are in bytes.
+    pub struct ArcStats("zfs", "arcstats") {
+        /// current size of the ARC
+        size: u64 = "size",
+        /// target size of the ARC
+        c: u64 = "c",
+        /// minimum target size of the ARC
+        c_min: u64 = "c_min",
+        /// maximum target size of the ARC
+        c_max: u64 = "c_max",
+        /// target size of the MRU list
+        p: u64 = "p",
+        /// hits
+        hits: u64 = "hits",
+        /// misses
+        misses: u64 = "misses",
+        /// hits on demand reads
+        demand_data_hits: u64 = "demand_data_hits",
+        /// misses on demand reads
+        demand_data_misses: u64 = "demand_data_misses",
+        /// hits on demand metadata reads
+        demand_metadata_hits: u64 = "demand_metadata_hits",
+        /// misses on demand metadata reads
+        demand_metadata_misses: u64 = "demand_metadata_misses",
+        /// hits on prefetch reads
+        prefetch_data_hits: u64 = "prefetch_data_hits",
+        /// misses on prefetch reads
+        prefetch_data_misses: u64 = "prefetch_data_misses",
+        /// hits in the most recently used list
+        mru_hits: u64 = "mru_hits",
+        /// hits in the ghost list of the most recently used list
+        mru_ghost_hits: u64 = "mru_ghost_hits",
+        /// hits in the most frequently used list
+        mfu_hits: u64 = "mfu_hits",
+        /// hits in the ghost list of the most frequently used list
+        mfu_ghost_hits: u64 = "mfu_ghost_hits",
+        /// size of the most recently used list
+        mru_size: u64 = "mru_size",
+        /// size of the most frequently used list
+        mfu_size: u64 = "mfu_size",
+        /// size of the L2ARC
+        l2_size: u64 = "l2_size",
+        /// L2ARC hits
+        l2_hits: u64 = "l2_hits",
+        /// L2ARC misses
+        l2_misses: u64 = "l2_misses",
+    }
+}
+
+/// The hit and miss counters of a cache, and the hit rates derived from them
+pub trait CacheStats {
+    /// cumulative hits
+    fn hits(&self) -> u64;
+
+    /// cumulative misses
+    fn misses(&self) -> u64;
+
+    /// The fraction of lookups that hit since the counters started, or `None` if there were no
+    /// lookups
+    fn hit_ratio(&self) -> Option<f64> {
+        ratio(self.hits(), self.misses())
+    }
+
+    /// The fraction of lookups between `prev` and `self` that hit, or `None` if there were no
+    /// lookups in between or the counters were reset
+    fn hit_ratio_since(&self, prev: &Self) -> Option<f64>
+    where
+        Self: Sized,
+    {
+        ratio(
+            since(prev.hits(), self.hits())?,
+            since(prev.misses(), self.misses())?,
+        )
+    }
+}
+
+/// The change of a 64-bit counter, or `None` if it went backwards because it was reset
+fn since(prev: u64, cur: u64) -> Option<u64> {
+    accum::counter_delta(prev, cur, Width::Bits64)
+}
+
+fn ratio(hits: u64, misses: u64) -> Option<f64> {
+    let total = hits.saturating_add(misses);
+    if total == 0 {
+        None
+    } else {
+        Some(hits as f64 / total as f64)
+    }
+}
+
+impl CacheStats for ArcStats {
+    fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+/// The L2ARC counters of an `ArcStats`, as a cache of their own
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct L2ArcStats<'a>(pub &'a ArcStats);
+
+// The L2ARC counters are the hits and misses of this cache, not the ARC's
+#[allow(clippy::misnamed_getters)]
+impl<'a> CacheStats for L2ArcStats<'a> {
+    fn hits(&self) -> u64 {
+        self.0.l2_hits
+    }
+
+    fn misses(&self) -> u64 {
+        self.0.l2_misses
+    }
+}
+
+impl ArcStats {
+    /// Read `zfs:0:arcstats`
+    ///
+    /// # Example
+    /// ```
+    /// use kstat::helpers::zfs::{ArcStats, CacheStats};
+    ///
+    /// let arc = ArcStats::read_arc().expect("failed to read arcstats");
+    /// println!("{} of {} bytes, {:?} hit ratio", arc.size, arc.c_max, arc.hit_ratio());
+    /// ```
+    pub fn read_arc() -> io::Result<Self> {
+        let reader = KstatReader::new()?;
+        match reader.read_one("zfs", 0, "arcstats")? {
+            Some(data) => ArcStats::decode(&data),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "zfs:0:arcstats not found",
+            )),
+        }
+    }
+
+    /// Decode an already read `zfs:0:arcstats` kstat, tolerating missing statistics
+    pub fn decode(data: &KstatData) -> io::Result<Self> {
+        ArcStats::from_kstat(data, DecodeMode::Lenient)
+    }
+
+    /// The fraction of hits between `prev` and `self` that were served from the most frequently
+    /// used list, or `None` if there were no MRU or MFU hits in between or the counters were reset
+    pub fn mfu_share_since(&self, prev: &ArcStats) -> Option<f64> {
+        let mru = since(prev.mru_hits, self.mru_hits)?;
+        let mfu = since(prev.mfu_hits, self.mfu_hits)?;
+        ratio(mfu, mru)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hit_ratios() {
+        let prev = ArcStats {
+            hits: 100,
+            misses: 100,
+            mru_hits: 50,
+            mfu_hits: 50,
+            ..ArcStats::default()
+        };
+        let cur = ArcStats {
+            hits: 190,
+            misses: 110,
+            mru_hits: 70,
+            mfu_hits: 120,
+            ..ArcStats::default()
+        };
+        assert_eq!(prev.hit_ratio(), Some(0.5));
+        assert_eq!(cur.hit_ratio_since(&prev), Some(0.9));
+        assert_eq!(cur.mfu_share_since(&prev), Some(70.0 / 90.0));
+        // the counters went backwards, so the ARC statistics were reset
+        assert_eq!(prev.hit_ratio_since(&cur), None);
+        assert_eq!(L2ArcStats(&cur).hit_ratio(), None);
+    }
+}
@@ -0,0 +1,269 @@
+//! Per zone collection of the kstats that illumos keeps for every zone: `zone_vfs` (file system
+//! I/O), `zones` of class `zone_misc` (CPU time, load and fork failures), the CPU cap from `caps`
+//! and the memory cap from `memory_cap`. On SmartOS the network traffic of each zone's VNICs is
+//! added from the `link` kstats.
+
+use super::caps::CpuCap;
+use super::link::{self, LinkStats, ZoneTraffic};
+use super::memcap::MemoryCap;
+use {KstatData, KstatReader, KstatSelector, Matcher};
+
+use std::collections::BTreeMap;
+use std::io;
+
+/// The decoded `zone_vfs:<zoneid>:<zonename>` kstat. Times are in nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ZoneVfs {
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// bytes read
+    pub nread: u64,
+    /// read operations
+    pub reads: u64,
+    /// cumulative run (service) time of reads
+    pub rtime: u64,
+    /// cumulative run length * time product of reads
+    pub rlentime: u64,
+    /// bytes written
+    pub nwritten: u64,
+    /// write operations
+    pub writes: u64,
+    /// cumulative wait time of writes
+    pub wtime: u64,
+    /// cumulative wait length * time product of writes
+    pub wlentime: u64,
+    /// operations that took longer than 10ms
+    pub ops_10ms: u64,
+    /// operations that took longer than 100ms
+    pub ops_100ms: u64,
+    /// operations that took longer than 1s
+    pub ops_1s: u64,
+    /// operations that took longer than 10s
+    pub ops_10s: u64,
+    /// operations delayed by I/O throttling
+    pub delay_cnt: u64,
+    /// cumulative time operations were delayed by I/O throttling
+    pub delay_time: u64,
+}
+
+impl ZoneVfs {
+    /// Decode a `zone_vfs` kstat, returning `None` if `data` is not one
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        if data.module != "zone_vfs" {
+            return None;
+        }
+        let get = |key| data.get_u64(key).unwrap_or(0);
+        Some(ZoneVfs {
+            snaptime: data.snaptime,
+            nread: get("nread"),
+            reads: get("reads"),
+            rtime: get("rtime"),
+            rlentime: get("rlentime"),
+            nwritten: get("nwritten"),
+            writes: get("writes"),
+            wtime: get("wtime"),
+            wlentime: get("wlentime"),
+            ops_10ms: get("10ms_ops"),
+            ops_100ms: get("100ms_ops"),
+            ops_1s: get("1s_ops"),
+            ops_10s: get("10s_ops"),
+            delay_cnt: get("delay_cnt"),
+            delay_time: get("delay_time"),
+        })
+    }
+}
+
+/// The decoded `zones:<zoneid>:<zonename>` kstat of class `zone_misc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ZoneMisc {
+    /// nanoseconds since boot of this snapshot
+    pub snaptime: i64,
+    /// nanoseconds of user CPU time
+    pub nsec_user: u64,
+    /// nanoseconds of system CPU time
+    pub nsec_sys: u64,
+    /// nanoseconds threads spent waiting on a run queue
+    pub nsec_waitrq: u64,
+    /// the 1 minute load average, scaled by 256
+    pub avenrun_1min: u64,
+    /// the 5 minute load average, scaled by 256
+    pub avenrun_5min: u64,
+    /// the 15 minute load average, scaled by 256
+    pub avenrun_15min: u64,
+    /// forks that failed because of the zone's process cap
+    pub forkfail_cap: u64,
+    /// forks that failed because the process table was full
+    pub forkfail_noproc: u64,
+    /// forks that failed because of a lack of memory
+    pub forkfail_nomem: u64,
+    /// forks that failed for any other reason
+    pub forkfail_misc: u64,
+    /// the pid of the zone's init
+    pub init_pid: u64,
+    /// the wall clock time the zone booted, in seconds since the epoch
+    pub boot_time: u64,
+}
+
+impl ZoneMisc {
+    /// Decode a `zone_misc` kstat, returning `None` if `data` is not one
+    pub fn from_kstat(data: &KstatData) -> Option<Self> {
+        if data.module != "zones" || data.class != "zone_misc" {
+            return None;
+        }
+        let get = |key| data.get_u64(key).unwrap_or(0);
+        Some(ZoneMisc {
+            snaptime: data.snaptime,
+            nsec_user: get("nsec_user"),
+            nsec_sys: get("nsec_sys"),
+            nsec_waitrq: get("nsec_waitrq"),
+            avenrun_1min: get("avenrun_1min"),
+            avenrun_5min: get("avenrun_5min"),
+            avenrun_15min: get("avenrun_15min"),
+            forkfail_cap: get("forkfail_cap"),
+            forkfail_noproc: get("forkfail_noproc"),
+            forkfail_nomem: get("forkfail_nomem"),
+            forkfail_misc: get("forkfail_misc"),
+            init_pid: get("init_pid"),
+            boot_time: get("boot_time"),
+        })
+    }
+}
+
+/// Everything known about a single zone. A kstat the zone does not have, e.g. the CPU cap of an
+/// uncapped zone, is `None`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ZoneStats {
+    /// the zone id
+    pub zoneid: i32,
+    /// the zone name
+    pub zonename: String,
+    /// file system I/O
+    pub vfs: Option<ZoneVfs>,
+    /// CPU time, load and fork failures
+    pub misc: Option<ZoneMisc>,
+    /// the CPU cap
+    pub cpu_cap: Option<CpuCap>,
+    /// the memory cap
+    pub memory_cap: Option<MemoryCap>,
+    /// the combined traffic of the zone's VNICs
+    pub network: Option<ZoneTraffic>,
+}
+
+/// Group already read kstats by zone, sorted by zone id. Kstats that are not one of the per zone
+/// kstats are ignored. Link kstats are matched to their zone by name, as they are not keyed by
+/// zone id.
+pub fn from_kstats(data: &[KstatData]) -> Vec<ZoneStats> {
+    let mut zones: BTreeMap<i32, ZoneStats> = BTreeMap::new();
+    let mut links = Vec::new();
+    for kstat in data {
+        if let Some(link) = LinkStats::from_kstat(kstat) {
+            links.push(link);
+            continue;
+        }
+        let zone = zones.entry(kstat.instance).or_insert_with(|| ZoneStats {
+            zoneid: kstat.instance,
+            ..ZoneStats::default()
+        });
+        if zone.zonename.is_empty() {
+            // The kstat name is the zone name truncated to fit, so prefer the zonename statistic
+            if let Some(zonename) = kstat.get_str("zonename") {
+                zone.zonename = zonename.to_string();
+            }
+        }
+
+        if let Some(vfs) = ZoneVfs::from_kstat(kstat) {
+            zone.vfs = Some(vfs);
+        } else if let Some(misc) = ZoneMisc::from_kstat(kstat) {
+            zone.misc = Some(misc);
+        } else if let Some(cap) = CpuCap::from_kstat(kstat) {
+            zone.cpu_cap = Some(cap);
+        } else if let Some(cap) = MemoryCap::from_kstat(kstat) {
+            zone.memory_cap = Some(cap);
+        }
+    }
+
+    let mut traffic = link::by_zone(&links);
+    zones
+        .into_values()
+        .filter(|zone| {
+            zone.vfs.is_some()
+                || zone.misc.is_some()
+                || zone.cpu_cap.is_some()
+                || zone.memory_cap.is_some()
+        })
+        .map(|mut zone| {
+            if let Some(i) = traffic.iter().position(|t| t.zonename == zone.zonename) {
+                zone.network = Some(traffic.swap_remove(i));
+            }
+            zone
+        })
+        .collect()
+}
+
+/// Read the per zone kstats of every zone visible from this one, sorted by zone id
+///
+/// # Example
+/// ```
+/// for zone in kstat::helpers::zones::read().expect("failed to read zone kstats") {
+///     if let Some(misc) = zone.misc {
+///         println!("{}: {} ns of user time", zone.zonename, misc.nsec_user);
+///     }
+/// }
+/// ```
+pub fn read() -> io::Result<Vec<ZoneStats>> {
+    let reader = KstatReader::builder()
+        .add_selector(KstatSelector::new().module("zone_vfs"))
+        .add_selector(KstatSelector::new().module("zones").class("zone_misc"))
+        .add_selector(
+            KstatSelector::new()
+                .module("caps")
+                .name(Matcher::glob("cpucaps_zone_*")),
+        )
+        .add_selector(KstatSelector::new().module("memory_cap"))
+        .add_selector(KstatSelector::new().module("link").instance(0))
+        .build()?;
+    Ok(from_kstats(&reader.read()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+    use kstat_named::KstatNamedData::{DataString, DataUInt64};
+
+    #[test]
+    fn grouped_by_zone() {
+        let zonename = |name: &str| ("zonename", DataString(name.to_string()));
+        let misc = KstatData {
+            class: "zone_misc".to_string(),
+            ..kstat(
+                "zones",
+                3,
+                "web",
+                vec![zonename("webserver"), ("nsec_user", DataUInt64(9))],
+            )
+        };
+        let data = vec![
+            kstat("zone_vfs", 3, "web", vec![("reads", DataUInt64(5))]),
+            misc,
+            kstat("zone_vfs", 0, "global", vec![zonename("global")]),
+            kstat("memory_cap", 0, "global", vec![("rss", DataUInt64(100))]),
+            kstat("unix", 7, "system_pages", Vec::new()),
+            kstat("link", 0, "z3_net0", vec![zonename("webserver")]),
+        ];
+
+        let zones = from_kstats(&data);
+        assert_eq!(zones.len(), 2);
+        assert_eq!(zones[0].zoneid, 0);
+        assert_eq!(zones[0].zonename, "global");
+        assert_eq!(zones[0].memory_cap.as_ref().map(|c| c.rss), Some(100));
+        assert!(zones[0].misc.is_none());
+        assert_eq!(zones[1].zonename, "webserver");
+        assert_eq!(zones[1].vfs.map(|v| v.reads), Some(5));
+        assert_eq!(zones[1].misc.map(|m| m.nsec_user), Some(9));
+        assert!(zones[1].cpu_cap.is_none());
+        assert!(zones[0].network.is_none());
+        let network = zones[1].network.as_ref().map(|n| n.links.clone());
+        assert_eq!(network, Some(vec!["z3_net0".to_string()]));
+    }
+}
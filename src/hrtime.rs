@@ -0,0 +1,50 @@
+//! Conversion of hrtime values, such as a kstat's `snaptime` and `crtime`, to wall clock time.
+//!
+//! hrtime counts nanoseconds since boot and does not advance while the wall clock is adjusted, so
+//! the crate establishes the wall clock time of boot once, the first time it is needed, and
+//! converts every hrtime relative to that anchor. Timestamps therefore stay consistent with each
+//! other for the life of the process even if the system clock is stepped.
+
+use ffi;
+
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+static BOOT: OnceLock<i64> = OnceLock::new();
+
+/// The wall clock time of boot in nanoseconds since the epoch
+pub(crate) fn boot_time_nanos() -> i64 {
+    *BOOT.get_or_init(|| {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64 * 1_000_000_000 + i64::from(d.subsec_nanos()))
+            .unwrap_or(0);
+        now - unsafe { ffi::gethrtime() }
+    })
+}
+
+/// Returns the wall clock time the system booted, as anchored by this process
+pub fn boot_time() -> SystemTime {
+    to_system_time(0)
+}
+
+/// Convert an hrtime in nanoseconds since boot to wall clock time
+///
+/// # Example
+/// ```
+/// let reader = kstat::KstatReader::new().expect("failed to create kstat reader");
+/// for stat in reader.read().expect("failed to read kstats") {
+///     println!("{:?}", kstat::hrtime::to_system_time(stat.snaptime));
+/// }
+/// ```
+pub fn to_system_time(hrtime: i64) -> SystemTime {
+    from_epoch_nanos(boot_time_nanos() + hrtime)
+}
+
+fn from_epoch_nanos(nanos: i64) -> SystemTime {
+    if nanos >= 0 {
+        UNIX_EPOCH + Duration::from_nanos(nanos as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_nanos(nanos.unsigned_abs())
+    }
+}
@@ -0,0 +1,315 @@
+use super::error::{self, Error};
+use super::ffi;
+use super::kstat_ctl::{ChainKey, CtlGuard, Kstat};
+use super::triplet::KstatTriplet;
+use {KstatData, KstatReader};
+
+use libc;
+
+use std::io;
+use std::marker::PhantomData;
+use std::ptr;
+use std::time::Instant;
+use std::vec;
+
+/// The kstats a reader matched on its last complete walk of the chain
+#[derive(Debug)]
+pub(crate) struct MatchCache {
+    key: ChainKey,
+    kstats: Vec<*const ffi::kstat_t>,
+}
+
+// The pointers are only dereferenced by a `KstatIter` that holds the handle's lock and has checked
+// that the chain they were taken from is still the current one.
+unsafe impl Send for MatchCache {}
+
+/// How far a walk of the kstat chain has got, passed to the callback set with
+/// `KstatReader::on_progress`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReadProgress {
+    /// kstats visited so far
+    pub processed: usize,
+    /// kstats visited so far that matched the reader's filters
+    pub matched: usize,
+}
+
+/// The result of `KstatReader::read_outcome`: the kstats that were read along with the errors
+/// of the kstats the reader's `ErrorPolicy` skipped
+#[derive(Debug)]
+pub struct ReadOutcome {
+    /// the kstats that were read
+    pub data: Vec<KstatData>,
+    /// why each skipped kstat could not be read. Every error carries a `kstat::Error` naming the
+    /// kstat, which can be recovered with `Error::from_io`.
+    pub skipped: Vec<io::Error>,
+}
+
+/// What a `KstatReader` does when reading a single kstat fails
+#[derive(Debug, Clone, Copy, Default)]
+pub enum ErrorPolicy {
+    /// return every error
+    Strict,
+    /// skip kstats that went away or could not be read (ENXIO and EIO) or whose data is
+    /// malformed, and return any other error. This is the default.
+    #[default]
+    IgnoreTransient,
+    /// skip every kstat that fails to read, like kstat(1M)
+    IgnoreAll,
+    /// skip the kstat if the function returns true
+    Custom(fn(&io::Error) -> bool),
+}
+
+impl ErrorPolicy {
+    fn ignores(&self, e: &io::Error) -> bool {
+        match *self {
+            ErrorPolicy::Strict => false,
+            ErrorPolicy::IgnoreTransient => is_transient(e) || is_malformed(e),
+            ErrorPolicy::IgnoreAll => true,
+            ErrorPolicy::Custom(f) => f(e),
+        }
+    }
+}
+
+fn is_transient(e: &io::Error) -> bool {
+    match error::raw_os_error(e) {
+        // the kstat went away by the time we call read, so forget it and move on
+        // example: a zone is no longer running
+        Some(libc::ENXIO) => true,
+        // I don't know why EIO seems to be common here. The kstat cmd on illumos
+        // seems to ignore all errors and continue while only reporting the errors
+        // when REPORT_UNKNOWN is set
+        Some(libc::EIO) => true,
+        _ => false,
+    }
+}
+
+/// Whether the kstat itself is broken, which reopening the kstat handle cannot fix
+fn is_malformed(e: &io::Error) -> bool {
+    matches!(
+        Error::from_io(e),
+        Some(Error::MalformedData { .. }) | Some(Error::UnsupportedType { .. })
+    )
+}
+
+/// An iterator over the kstats matched by a `KstatReader`, created with `KstatReader::iter`.
+/// Each matching kstat is read only when the iterator reaches it.
+#[derive(Debug)]
+pub struct KstatIter<'a> {
+    reader: &'a KstatReader,
+    ctl: CtlGuard<'a>,
+    next: *const ffi::kstat_t,
+    /// the matches of a previous walk of this same chain, if there were any
+    cached: Option<vec::IntoIter<*const ffi::kstat_t>>,
+    /// the matches found so far while walking the chain
+    matched: Option<Vec<*const ffi::kstat_t>>,
+    /// the kstat found by `kstat_lookup()` instead of walking the chain
+    found: Option<*const ffi::kstat_t>,
+    progress: ReadProgress,
+    /// kstats left to visit until the progress callback is next due
+    progress_due: usize,
+    skipped: Vec<io::Error>,
+}
+
+impl<'a> KstatIter<'a> {
+    pub(crate) fn new(reader: &'a KstatReader, ctl: CtlGuard<'a>) -> Self {
+        let next = ctl.get_chain();
+        let key = ctl.chain_key();
+
+        // The chain has not changed since the last complete walk, so the filters do not need to
+        // be evaluated again
+        let cached = match *reader.match_cache.borrow() {
+            Some(ref cache) if cache.key == key => Some(cache.kstats.clone().into_iter()),
+            _ => None,
+        };
+        let matched = match cached {
+            Some(_) => None,
+            None => Some(Vec::new()),
+        };
+
+        KstatIter {
+            reader,
+            ctl,
+            next,
+            cached,
+            matched,
+            found: None,
+            progress: ReadProgress::default(),
+            progress_due: reader.on_progress.as_ref().map_or(0, |&(every, _)| every),
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Returns an iterator over the single kstat named by `triplet`, found with `kstat_lookup()`
+    /// rather than by walking the chain. The reader's filters only apply if `filtered` is set.
+    pub(crate) fn lookup(
+        reader: &'a KstatReader,
+        ctl: CtlGuard<'a>,
+        triplet: &KstatTriplet,
+        filtered: bool,
+    ) -> Self {
+        let found = ctl.lookup(triplet).filter(|&inner| {
+            let kstat = Kstat {
+                inner,
+                _marker: PhantomData,
+            };
+            let ks_type = kstat.get_type();
+            (ks_type == ffi::KSTAT_TYPE_NAMED || ks_type == ffi::KSTAT_TYPE_IO)
+                && (!filtered || reader.matches(&kstat))
+        });
+
+        KstatIter {
+            reader,
+            ctl,
+            next: ptr::null(),
+            cached: None,
+            matched: None,
+            found,
+            progress: ReadProgress::default(),
+            progress_due: reader.on_progress.as_ref().map_or(0, |&(every, _)| every),
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Returns the next kstat that matches the reader's filters
+    fn next_match(&mut self) -> Option<*const ffi::kstat_t> {
+        if let Some(found) = self.found.take() {
+            self.visited(true);
+            return Some(found);
+        }
+
+        if self.cached.is_some() {
+            let next = self.cached.as_mut().and_then(|c| c.next());
+            if next.is_some() {
+                self.visited(true);
+            }
+            return next;
+        }
+
+        while !self.next.is_null() {
+            let kstat = Kstat {
+                inner: self.next,
+                _marker: PhantomData,
+            };
+
+            // Loop until we reach the end of the chain
+            self.next = unsafe { (*self.next).ks_next };
+
+            // must be NAMED or IO
+            let ks_type = kstat.get_type();
+            if ks_type != ffi::KSTAT_TYPE_NAMED && ks_type != ffi::KSTAT_TYPE_IO {
+                self.visited(false);
+                continue;
+            }
+
+            if !self.reader.matches(&kstat) {
+                self.visited(false);
+                continue;
+            }
+
+            self.visited(true);
+
+            if let Some(ref mut matched) = self.matched {
+                matched.push(kstat.inner);
+            }
+            return Some(kstat.inner);
+        }
+
+        // Remember the matches of a complete walk for the next walk of the same chain
+        if let Some(kstats) = self.matched.take() {
+            *self.reader.match_cache.borrow_mut() = Some(MatchCache {
+                key: self.ctl.chain_key(),
+                kstats,
+            });
+        }
+
+        None
+    }
+
+    /// Returns the errors of the kstats skipped so far because of the reader's `ErrorPolicy`
+    pub fn skipped(&self) -> &[io::Error] {
+        &self.skipped
+    }
+
+    /// Consume the iterator, returning the errors of the kstats it skipped
+    pub fn into_skipped(self) -> Vec<io::Error> {
+        self.skipped
+    }
+
+    /// Count a visited kstat, calling the reader's progress callback if it is due
+    fn visited(&mut self, matched: bool) {
+        self.progress.processed += 1;
+        if matched {
+            self.progress.matched += 1;
+        }
+        if let Some((every, ref f)) = self.reader.on_progress {
+            self.progress_due -= 1;
+            if self.progress_due == 0 {
+                self.progress_due = every;
+                (f.0)(self.progress);
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for KstatIter<'a> {
+    type Item = io::Result<KstatData>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(inner) = self.next_match() {
+            let kstat = Kstat {
+                inner,
+                _marker: PhantomData,
+            };
+
+            let start = Instant::now();
+            let snapshot = kstat.snapshot(&self.ctl);
+            let read = Instant::now();
+            let result = snapshot.and_then(|_| kstat.decode(&self.reader.limits));
+            self.reader.record_timings(|t| {
+                t.read += read - start;
+                t.decode += read.elapsed();
+            });
+
+            match result {
+                Ok(k) => {
+                    self.reader.record_success();
+                    return Some(Ok(k));
+                }
+                Err(e) => {
+                    if self.reader.error_policy.ignores(&e) {
+                        self.skipped.push(e);
+                        continue;
+                    }
+                    // Only errors the caller gets to see count towards reopening the handle, and
+                    // a malformed kstat is still malformed after a reopen
+                    if !is_transient(&e) && !is_malformed(&e) {
+                        self.reader.record_failure();
+                    }
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_policy() {
+        let policy = ErrorPolicy::default();
+        assert!(policy.ignores(&io::Error::from_raw_os_error(libc::ENXIO)));
+        assert!(!policy.ignores(&io::Error::from_raw_os_error(libc::EACCES)));
+
+        let malformed = Error::MalformedData {
+            module: "unix".to_string(),
+            instance: 0,
+            name: "vminfo".to_string(),
+            reason: "I/O data section is 0 bytes".to_string(),
+        };
+        assert!(policy.ignores(&malformed.into()));
+    }
+}
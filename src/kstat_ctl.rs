@@ -1,43 +1,242 @@
+use super::error::Error;
 use super::ffi;
-use super::kstat_named::{KstatNamed, KstatNamedData};
-use KstatData;
+use super::kstat_io::KstatIoData;
+use super::kstat_named::KstatNamed;
+use super::matcher::Matcher;
+use super::meta::{KstatFlags, KstatMeta};
+use super::triplet::KstatTriplet;
+use {Data, KstatData, NamedMap};
 
 use libc;
 
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::cmp;
+use std::ffi::CStr;
 use std::io;
 use std::marker::PhantomData;
+use std::mem;
 use std::ptr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
 
-/// A wrapper around a `kstat_ctl_t` handle.
+/// How many times to retry `kstat_open()` when it fails with EAGAIN
+const OPEN_RETRIES: u32 = 5;
+/// The initial delay between `kstat_open()` retries, doubled after every attempt
+const OPEN_RETRY_DELAY_MS: u64 = 10;
+
+/// Identifies a snapshot of the kstat chain: the number of times the handle has been reopened
+/// and the chain ID libkstat reports. Kstat pointers taken from the chain stay valid for as long
+/// as the key does not change.
+pub(crate) type ChainKey = (u64, i32);
+
+/// A raw `kstat_ctl_t` pointer
+#[derive(Debug)]
+struct Handle {
+    ctl: *const ffi::kstat_ctl_t,
+    generation: u64,
+}
+
+// libkstat keeps no thread local state, so a handle may be used from any thread as long as it is
+// only used by one thread at a time. `KstatCtl` only hands out access through its `Mutex`, and
+// every `Kstat` borrowed from the chain is bound to the lifetime of that lock and is neither
+// `Send` nor `Sync`.
+unsafe impl Send for Handle {}
+
+/// A kstat handle opened with `kstat_open()`, along with the copy of the kstat chain it owns.
+///
+/// A `KstatCtl` can be shared between several differently filtered `KstatReader`s with
+/// `KstatReaderBuilder::ctl`, so that they share one handle and one chain. Readers sharing a
+/// handle take turns: each walk of the chain holds the handle until its `KstatIter` is dropped.
+///
+/// # Example
+/// ```
+/// # use std::sync::Arc;
+/// # use kstat::{KstatCtl, KstatReader};
+/// let ctl = Arc::new(KstatCtl::new().expect("failed to open kstat handle"));
+/// let cpus = KstatReader::builder()
+///     .module("cpu")
+///     .name("sys")
+///     .ctl(Arc::clone(&ctl))
+///     .build()
+///     .expect("failed to create kstat reader");
+/// let vfs = KstatReader::builder()
+///     .module("zone_vfs")
+///     .ctl(ctl)
+///     .build()
+///     .expect("failed to create kstat reader");
+/// ```
 #[derive(Debug)]
 pub struct KstatCtl {
-    inner: *const ffi::kstat_ctl_t,
+    inner: Mutex<Handle>,
 }
 
 impl KstatCtl {
+    /// Open a new kstat handle
     pub fn new() -> io::Result<Self> {
-        unsafe { ptr_or_err(ffi::kstat_open()).map(|c| KstatCtl { inner: c }) }
+        open().map(|c| KstatCtl {
+            inner: Mutex::new(Handle {
+                ctl: c,
+                generation: 0,
+            }),
+        })
+    }
+
+    /// Take exclusive access to the handle, blocking while another reader is walking the chain.
+    /// A poisoned lock is still usable since the handle holds no state a panic could corrupt.
+    pub(crate) fn lock(&self) -> CtlGuard<'_> {
+        CtlGuard {
+            inner: self.inner.lock().unwrap_or_else(PoisonError::into_inner),
+        }
+    }
+
+    /// Write the raw header of every kstat in the chain to `writer`, one line each, without
+    /// reading any data. Attaching this output to a bug report shows how the chain looks on the
+    /// platform where a kstat fails to decode.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io;
+    /// let ctl = kstat::KstatCtl::new().expect("failed to open kstat handle");
+    /// ctl.dump_chain_debug(io::stderr()).expect("failed to dump kstat chain");
+    /// ```
+    pub fn dump_chain_debug<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        let ctl = self.lock();
+        ctl.chain_update()?;
+        let (generation, chain_id) = ctl.chain_key();
+        writeln!(
+            writer,
+            "# kstat chain id {} (handle generation {}), kstat_t is {} bytes",
+            chain_id,
+            generation,
+            mem::size_of::<ffi::kstat_t>()
+        )?;
+
+        let mut ksp = ctl.get_chain();
+        while !ksp.is_null() {
+            let ks = unsafe { &*ksp };
+            writeln!(
+                writer,
+                "kid={} {}:{}:{} class={} type={} flags={:#04x} ndata={} data_size={} \
+                 crtime={} snaptime={} kstat={:p} data={:p} next={:p}",
+                ks.ks_kid,
+                ks.get_module(),
+                ks.ks_instance,
+                ks.get_name(),
+                ks.get_class(),
+                ks.ks_type,
+                ks.ks_flags,
+                ks.ks_ndata,
+                ks.ks_data_size,
+                ks.ks_crtime,
+                ks.ks_snaptime,
+                ksp,
+                ks.ks_data,
+                ks.ks_next
+            )?;
+            ksp = ks.ks_next;
+        }
+        writer.flush()
+    }
+}
+
+impl Drop for KstatCtl {
+    fn drop(&mut self) {
+        let handle = self.inner.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let _ = unsafe { ffi::kstat_close(handle.ctl) };
+    }
+}
+
+/// Exclusive access to the handle of a `KstatCtl` and the chain it owns
+#[derive(Debug)]
+pub(crate) struct CtlGuard<'a> {
+    inner: MutexGuard<'a, Handle>,
+}
+
+impl<'a> CtlGuard<'a> {
+    /// Close the current handle and replace it with a freshly opened one. Any `Kstat` obtained
+    /// from the old chain is no longer valid after this returns.
+    pub fn reopen(&mut self) -> io::Result<()> {
+        let old = mem::replace(&mut self.inner.ctl, open()?);
+        self.inner.generation += 1;
+        let _ = unsafe { ffi::kstat_close(old) };
+        Ok(())
     }
 
     pub fn get_chain(&self) -> *const ffi::kstat_t {
-        unsafe { (*self.inner).kc_chain }
+        unsafe { (*self.inner.ctl).kc_chain }
+    }
+
+    pub fn chain_key(&self) -> ChainKey {
+        (self.inner.generation, unsafe { (*self.inner.ctl).kc_chain_id })
     }
 
     pub fn chain_update(&self) -> io::Result<bool> {
-        let ret = unsafe { chain_updated(ret_or_err(ffi::kstat_chain_update(self.inner))?) };
-        Ok(ret)
+        let kid = unsafe { ret_or_err(ffi::kstat_chain_update(self.inner.ctl)) }
+            .map_err(|source| Error::ChainUpdateFailed { source })?;
+        Ok(chain_updated(kid))
+    }
+
+    /// Find the kstat named by `triplet` in the chain without walking it ourselves
+    pub fn lookup(&self, triplet: &KstatTriplet) -> Option<*const ffi::kstat_t> {
+        let ksp = unsafe {
+            ffi::kstat_lookup(
+                self.inner.ctl,
+                triplet.module().as_ptr(),
+                triplet.instance(),
+                triplet.name().as_ptr(),
+            )
+        };
+        if ksp.is_null() {
+            None
+        } else {
+            Some(ksp)
+        }
     }
 
     pub fn kstat_read(&self, kstat: &Kstat) -> io::Result<i32> {
-        unsafe { ret_or_err(ffi::kstat_read(self.inner, kstat.get_inner(), ptr::null())) }
+        unsafe { ret_or_err(ffi::kstat_read(self.inner.ctl, kstat.get_inner(), ptr::null())) }
     }
 }
 
-impl Drop for KstatCtl {
-    fn drop(&mut self) {
-        let _ = unsafe { ffi::kstat_close(self.inner) };
+/// Open a new kstat handle. kstat_open() can fail with EAGAIN when the kernel is unable to
+/// allocate the chain snapshot, so that case is retried a bounded number of times with a
+/// backoff before giving up.
+fn open() -> io::Result<*const ffi::kstat_ctl_t> {
+    let mut delay = Duration::from_millis(OPEN_RETRY_DELAY_MS);
+    let mut attempt = 0;
+    loop {
+        match unsafe { ptr_or_err(ffi::kstat_open()) } {
+            Ok(c) => return Ok(c),
+            Err(ref e) if e.raw_os_error() == Some(libc::EAGAIN) && attempt < OPEN_RETRIES => {
+                attempt += 1;
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            Err(e) => return Err(Error::open_failed(e, current_zone_name()).into()),
+        }
+    }
+}
+
+/// Bounds on what decoding a single kstat may allocate
+#[derive(Debug, Clone)]
+pub(crate) struct Limits {
+    /// the longest KSTAT_DATA_STRING value kept, in bytes; longer values are truncated
+    pub max_string_len: usize,
+    /// the most named statistics a single kstat may have before it is rejected as malformed
+    pub max_ndata: usize,
+    /// the named statistics that are decoded; the others are skipped without being copied
+    pub stats: Option<Matcher>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_string_len: usize::MAX,
+            // Far more than any provider has, e.g. arcstats has a few hundred
+            max_ndata: 1 << 16,
+            stats: None,
+        }
     }
 }
 
@@ -49,17 +248,28 @@ pub struct Kstat<'ksctl> {
 }
 
 impl<'ksctl> Kstat<'ksctl> {
-    /// Read this particular kstat and its corresponding data into a `KstatData`
-    pub fn read(&self, ctl: &KstatCtl) -> io::Result<KstatData> {
-        ctl.kstat_read(self)?;
+    /// Have libkstat copy the current data of this kstat out of the kernel
+    pub fn snapshot(&self, ctl: &CtlGuard) -> io::Result<()> {
+        ctl.kstat_read(self).map_err(|source| Error::ReadFailed {
+            module: self.get_module().into_owned(),
+            instance: self.get_instance(),
+            name: self.get_name().into_owned(),
+            source,
+        })?;
+        Ok(())
+    }
 
+    /// Decode the data copied out by the last `snapshot` into a `KstatData`
+    pub fn decode(&self, limits: &Limits) -> io::Result<KstatData> {
         let class = self.get_class().into_owned();
         let module = self.get_module().into_owned();
         let instance = self.get_instance();
         let name = self.get_name().into_owned();
         let snaptime = self.get_snaptime();
         let crtime = self.get_crtime();
-        let data = self.get_data();
+        let kid = self.get_kid();
+        let flags = self.get_flags();
+        let (data, truncated, unsupported) = self.get_data(limits)?;
         Ok(KstatData {
             class,
             module,
@@ -67,20 +277,109 @@ impl<'ksctl> Kstat<'ksctl> {
             name,
             snaptime,
             crtime,
+            kid,
+            flags,
             data,
+            truncated,
+            unsupported,
         })
     }
 
-    fn get_data(&self) -> HashMap<String, KstatNamedData> {
+    /// Returns the decoded data along with the names of the statistics that were truncated and
+    /// of those that were left out because their data type is unknown
+    fn get_data(&self, limits: &Limits) -> io::Result<(Data, Vec<String>, Vec<String>)> {
+        match self.get_type() {
+            ffi::KSTAT_TYPE_IO => self
+                .get_io_data()
+                .map(|io| (Data::Io(io), Vec::new(), Vec::new())),
+            _ => {
+                let (named, truncated, unsupported) = self.get_named_data(limits)?;
+                Ok((Data::Named(named), truncated, unsupported))
+            }
+        }
+    }
+
+    fn get_io_data(&self) -> io::Result<KstatIoData> {
+        let (data, size) = unsafe { ((*self.inner).ks_data, (*self.inner).ks_data_size) };
+        if data.is_null() || size < mem::size_of::<ffi::kstat_io_t>() {
+            return Err(Error::MalformedData {
+                module: self.get_module().into_owned(),
+                instance: self.get_instance(),
+                name: self.get_name().into_owned(),
+                reason: format!("I/O data section is {} bytes", size),
+            }
+            .into());
+        }
+        let io = unsafe { &*(data as *const ffi::kstat_io_t) };
+        Ok(KstatIoData::from(io))
+    }
+
+    fn get_named_data(
+        &self,
+        limits: &Limits,
+    ) -> io::Result<(NamedMap, Vec<String>, Vec<String>)> {
         let head = unsafe { (*self.inner).ks_data as *const ffi::kstat_named_t };
-        let ndata = unsafe { (*self.inner).ks_ndata };
-        let mut ret = HashMap::with_capacity(ndata as usize);
+        if head.is_null() {
+            return Ok((NamedMap::new(), Vec::new(), Vec::new()));
+        }
+
+        // Never walk past the end of the data section, whatever ks_ndata claims
+        let (ndata, size) = unsafe { ((*self.inner).ks_ndata, (*self.inner).ks_data_size) };
+        let ndata = cmp::min(ndata as usize, size / mem::size_of::<ffi::kstat_named_t>());
+        if ndata > limits.max_ndata {
+            return Err(Error::MalformedData {
+                module: self.get_module().into_owned(),
+                instance: self.get_instance(),
+                name: self.get_name().into_owned(),
+                reason: format!(
+                    "{} named statistics exceeds the limit of {}",
+                    ndata, limits.max_ndata
+                ),
+            }
+            .into());
+        }
+        let mut ret = match limits.stats {
+            Some(_) => NamedMap::new(),
+            None => NamedMap::with_capacity(ndata),
+        };
+        let mut truncated = Vec::new();
+        let mut unsupported = Vec::new();
         for i in 0..ndata {
-            let (key, value) = KstatNamed::new(unsafe { head.offset(i as isize) }).read();
+            let named = KstatNamed::new(unsafe { head.add(i) });
+            if let Some(ref stats) = limits.stats {
+                if !stats.matches(&named.name()) {
+                    continue;
+                }
+            }
+            let (key, value, was_truncated) = match named.read(limits) {
+                Some(read) => read,
+                // One statistic of a type we don't know must not hide the rest of the kstat
+                None => {
+                    unsupported.push(named.name().into_owned());
+                    continue;
+                }
+            };
+            if was_truncated {
+                truncated.push(key.clone());
+            }
             ret.insert(key, value);
         }
 
-        ret
+        Ok((ret, truncated, unsupported))
+    }
+
+    /// Collect the header fields of this kstat without reading its data
+    pub fn meta(&self) -> KstatMeta {
+        KstatMeta {
+            module: self.get_module().into_owned(),
+            instance: self.get_instance(),
+            name: self.get_name().into_owned(),
+            class: self.get_class().into_owned(),
+            kstat_type: self.get_type().into(),
+            crtime: self.get_crtime(),
+            kid: self.get_kid(),
+            flags: self.get_flags(),
+        }
     }
 
     #[inline]
@@ -94,17 +393,17 @@ impl<'ksctl> Kstat<'ksctl> {
     }
 
     #[inline]
-    pub fn get_class(&self) -> Cow<str> {
+    pub fn get_class(&self) -> Cow<'_, str> {
         unsafe { (*self.inner).get_class() }
     }
 
     #[inline]
-    pub fn get_module(&self) -> Cow<str> {
+    pub fn get_module(&self) -> Cow<'_, str> {
         unsafe { (*self.inner).get_module() }
     }
 
     #[inline]
-    pub fn get_name(&self) -> Cow<str> {
+    pub fn get_name(&self) -> Cow<'_, str> {
         unsafe { (*self.inner).get_name() }
     }
 
@@ -122,10 +421,30 @@ impl<'ksctl> Kstat<'ksctl> {
     pub fn get_crtime(&self) -> i64 {
         unsafe { (*self.inner).ks_crtime }
     }
+
+    #[inline]
+    pub fn get_kid(&self) -> i32 {
+        unsafe { (*self.inner).ks_kid }
+    }
+
+    #[inline]
+    pub fn get_flags(&self) -> KstatFlags {
+        KstatFlags::from_bits(unsafe { (*self.inner).ks_flags })
+    }
 }
 
 // ============ Helpers ============
 
+fn current_zone_name() -> String {
+    let mut buf = [0 as libc::c_char; ffi::ZONENAME_MAX];
+    let ret = unsafe { ffi::getzonenamebyid(ffi::getzoneid(), buf.as_mut_ptr(), buf.len()) };
+    if ret < 0 {
+        return String::from("<unknown>");
+    }
+    let cstr = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    cstr.to_string_lossy().into_owned()
+}
+
 fn ptr_or_err<T>(ptr: *const T) -> io::Result<*const T> {
     if ptr.is_null() {
         Err(io::Error::last_os_error())
@@ -142,8 +461,157 @@ fn ret_or_err(ret: i32) -> io::Result<i32> {
 }
 
 fn chain_updated(kid: i32) -> bool {
-    match kid {
-        0 => false,
-        _ => true,
+    kid != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use kstat_named::KstatNamedData;
+    use libc::c_char;
+
+    fn kstat_t(ks_type: u8, data: &[ffi::kstat_named_t], ndata: u32) -> ffi::kstat_t {
+        let mut ks: ffi::kstat_t = unsafe { mem::zeroed() };
+        // None of the strings are NUL terminated
+        ks.ks_module = [b'm' as c_char; ffi::KSTAT_STRLEN];
+        ks.ks_name = [b'n' as c_char; ffi::KSTAT_STRLEN];
+        ks.ks_class = [0xff_u8 as c_char; ffi::KSTAT_STRLEN];
+        ks.ks_type = ks_type;
+        ks.ks_data = data.as_ptr() as *const _;
+        ks.ks_ndata = ndata;
+        ks.ks_data_size = mem::size_of_val(data);
+        ks
+    }
+
+    fn named(name: u8, data_type: u8, value: [u8; 16]) -> ffi::kstat_named_t {
+        ffi::kstat_named_t {
+            name: [name as c_char; ffi::KSTAT_STRLEN],
+            data_type,
+            value,
+        }
+    }
+
+    #[test]
+    fn adversarial_kstats() {
+        let data = [
+            named(b'a', ffi::KSTAT_DATA_UINT64, [1; 16]),
+            // a string with a NULL pointer
+            named(b'c', ffi::KSTAT_DATA_STRING, [0; 16]),
+            // an unknown data type
+            named(b'b', 42, [0; 16]),
+        ];
+
+        // ks_ndata claims far more entries than the data section holds
+        let ks = kstat_t(ffi::KSTAT_TYPE_NAMED, &data[..2], 1_000_000);
+        let kstat = Kstat {
+            inner: &ks,
+            _marker: PhantomData,
+        };
+        let meta = kstat.meta();
+        assert_eq!(meta.module.len(), ffi::KSTAT_STRLEN);
+        assert_eq!(meta.name.len(), ffi::KSTAT_STRLEN);
+        let key = |c: &str| c.repeat(ffi::KSTAT_STRLEN);
+        match kstat.get_data(&Limits::default()).expect("named data should decode").0 {
+            Data::Named(named) => {
+                assert_eq!(named.len(), 2);
+                assert!(named.contains_key(&key("a")));
+                match named.get(&key("c")) {
+                    Some(KstatNamedData::DataString(s)) => assert!(s.is_empty()),
+                    other => panic!("unexpected value {:?}", other),
+                }
+            }
+            Data::Io(_) => panic!("expected named data"),
+        }
+
+        let ks = kstat_t(ffi::KSTAT_TYPE_NAMED, &data, 3);
+        let kstat = Kstat {
+            inner: &ks,
+            _marker: PhantomData,
+        };
+        let (decoded, _, unsupported) = kstat
+            .get_data(&Limits::default())
+            .expect("an unknown data type should be skipped");
+        assert_eq!(unsupported, vec![key("b")]);
+        match decoded {
+            Data::Named(named) => assert_eq!(named.len(), 2),
+            Data::Io(_) => panic!("expected named data"),
+        }
+
+        // An I/O kstat whose data section is too small, then one without a data section
+        let mut ks = kstat_t(ffi::KSTAT_TYPE_IO, &data[..1], 1);
+        let kstat = Kstat {
+            inner: &ks,
+            _marker: PhantomData,
+        };
+        assert!(kstat.get_data(&Limits::default()).is_err());
+        ks.ks_data = ptr::null();
+        let kstat = Kstat {
+            inner: &ks,
+            _marker: PhantomData,
+        };
+        assert!(kstat.get_data(&Limits::default()).is_err());
+    }
+
+    #[test]
+    fn string_limit() {
+        let s = b"hello\0";
+        let mut value = [0; 16];
+        value[..8].copy_from_slice(&(s.as_ptr() as u64).to_ne_bytes());
+        value[8..12].copy_from_slice(&(s.len() as u32).to_ne_bytes());
+        let data = [named(b's', ffi::KSTAT_DATA_STRING, value)];
+        let ks = kstat_t(ffi::KSTAT_TYPE_NAMED, &data, 1);
+        let kstat = Kstat {
+            inner: &ks,
+            _marker: PhantomData,
+        };
+
+        let mut limits = Limits {
+            max_string_len: 3,
+            ..Limits::default()
+        };
+        let (data, truncated, _) = kstat.get_data(&limits).expect("named data should decode");
+        assert_eq!(truncated, vec!["s".repeat(ffi::KSTAT_STRLEN)]);
+        match data {
+            Data::Named(named) => match named.values().next() {
+                Some(KstatNamedData::DataString(s)) => assert_eq!(s, "hel"),
+                other => panic!("unexpected value {:?}", other),
+            },
+            Data::Io(_) => panic!("expected named data"),
+        }
+
+        limits.max_string_len = 5;
+        assert!(kstat.get_data(&limits).expect("named data should decode").1.is_empty());
+
+        limits.max_ndata = 0;
+        assert!(kstat.get_data(&limits).is_err());
+    }
+
+    #[test]
+    fn projection() {
+        let data = [
+            named(b'a', ffi::KSTAT_DATA_UINT64, [1; 16]),
+            named(b'b', ffi::KSTAT_DATA_UINT64, [2; 16]),
+            named(b'c', ffi::KSTAT_DATA_UINT64, [3; 16]),
+        ];
+        let ks = kstat_t(ffi::KSTAT_TYPE_NAMED, &data, 3);
+        let kstat = Kstat {
+            inner: &ks,
+            _marker: PhantomData,
+        };
+
+        let key = |c: &str| c.repeat(ffi::KSTAT_STRLEN);
+        let limits = Limits {
+            stats: Some(Matcher::one_of(vec![key("a"), key("c"), key("d")])),
+            ..Limits::default()
+        };
+        match kstat.get_data(&limits).expect("named data should decode").0 {
+            Data::Named(named) => {
+                let mut keys: Vec<_> = named.keys().cloned().collect();
+                keys.sort();
+                assert_eq!(keys, vec![key("a"), key("c")]);
+            }
+            Data::Io(_) => panic!("expected named data"),
+        }
     }
 }
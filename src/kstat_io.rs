@@ -0,0 +1,50 @@
+use super::ffi;
+
+/// The data found in a kstat of type KSTAT_TYPE_IO
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KstatIoData {
+    /// number of bytes read
+    pub nread: u64,
+    /// number of bytes written
+    pub nwritten: u64,
+    /// number of read operations
+    pub reads: u32,
+    /// number of write operations
+    pub writes: u32,
+    /// cumulative wait (pre-service) time in nanoseconds
+    pub wtime: i64,
+    /// cumulative wait length*time product
+    pub wlentime: i64,
+    /// last time the wait queue changed
+    pub wlastupdate: i64,
+    /// cumulative run (service) time in nanoseconds
+    pub rtime: i64,
+    /// cumulative run length*time product
+    pub rlentime: i64,
+    /// last time the run queue changed
+    pub rlastupdate: i64,
+    /// count of elements in wait state
+    pub wcnt: u32,
+    /// count of elements in run state
+    pub rcnt: u32,
+}
+
+impl<'a> From<&'a ffi::kstat_io_t> for KstatIoData {
+    fn from(io: &'a ffi::kstat_io_t) -> Self {
+        KstatIoData {
+            nread: io.nread,
+            nwritten: io.nwritten,
+            reads: io.reads,
+            writes: io.writes,
+            wtime: io.wtime,
+            wlentime: io.wlentime,
+            wlastupdate: io.wlastupdate,
+            rtime: io.rtime,
+            rlentime: io.rlentime,
+            rlastupdate: io.rlastupdate,
+            wcnt: io.wcnt,
+            rcnt: io.rcnt,
+        }
+    }
+}
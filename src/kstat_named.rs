@@ -1,11 +1,17 @@
 use super::ffi;
+use super::kstat_ctl::Limits;
 use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
 
 /// The types of data a kstat named/value pair can contain
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum KstatNamedData {
-    /// KSTAT_DATA_CHAR
-    DataChar(i8),
+    /// KSTAT_DATA_CHAR, the whole 16 byte field. Providers often store a short NUL terminated
+    /// string here, see `char_str`.
+    DataChar([u8; 16]),
     /// KSTAT_DATA_INT32
     DataInt32(i32),
     /// KSTAT_DATA_UINT32
@@ -18,6 +24,124 @@ pub enum KstatNamedData {
     DataString(String),
 }
 
+impl KstatNamedData {
+    /// A short name for the type of this value, used in error messages
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            KstatNamedData::DataChar(_) => "char",
+            KstatNamedData::DataInt32(_) => "int32",
+            KstatNamedData::DataUInt32(_) => "uint32",
+            KstatNamedData::DataInt64(_) => "int64",
+            KstatNamedData::DataUInt64(_) => "uint64",
+            KstatNamedData::DataString(_) => "string",
+        }
+    }
+
+    /// For a KSTAT_DATA_CHAR value, the bytes up to the first NUL as a string, which is how
+    /// kstat(1M) prints them
+    pub fn char_str(&self) -> Option<Cow<'_, str>> {
+        match *self {
+            KstatNamedData::DataChar(ref c) => {
+                let end = c.iter().position(|&b| b == 0).unwrap_or(c.len());
+                Some(String::from_utf8_lossy(&c[..end]))
+            }
+            _ => None,
+        }
+    }
+
+    fn as_i128(&self) -> Option<i128> {
+        match *self {
+            KstatNamedData::DataInt32(i) => Some(i128::from(i)),
+            KstatNamedData::DataUInt32(u) => Some(i128::from(u)),
+            KstatNamedData::DataInt64(i) => Some(i128::from(i)),
+            KstatNamedData::DataUInt64(u) => Some(i128::from(u)),
+            KstatNamedData::DataChar(_) | KstatNamedData::DataString(_) => None,
+        }
+    }
+}
+
+/// The error returned when a `KstatNamedData` cannot be converted to the requested type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversionError {
+    /// The value is not of a compatible type, e.g. a string converted to an integer
+    TypeMismatch {
+        /// the requested type
+        expected: &'static str,
+        /// the type of the value
+        found: &'static str,
+    },
+    /// The value is an integer that does not fit in the requested type
+    OutOfRange {
+        /// the requested type
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConversionError::TypeMismatch { expected, found } => {
+                write!(f, "expected a kstat value convertible to {}, found {}", expected, found)
+            }
+            ConversionError::OutOfRange { expected } => {
+                write!(f, "kstat value out of range for {}", expected)
+            }
+        }
+    }
+}
+
+impl error::Error for ConversionError {}
+
+macro_rules! try_from_integer {
+    ($($t:ty),*) => {
+        $(
+            impl<'a> TryFrom<&'a KstatNamedData> for $t {
+                type Error = ConversionError;
+
+                fn try_from(v: &'a KstatNamedData) -> Result<Self, Self::Error> {
+                    let expected = stringify!($t);
+                    let i = v.as_i128().ok_or(ConversionError::TypeMismatch {
+                        expected,
+                        found: v.type_name(),
+                    })?;
+                    <$t>::try_from(i).map_err(|_| ConversionError::OutOfRange { expected })
+                }
+            }
+        )*
+    };
+}
+
+try_from_integer!(i32, u32, i64, u64);
+
+/// Integers above 2^53 lose precision in the conversion
+impl<'a> TryFrom<&'a KstatNamedData> for f64 {
+    type Error = ConversionError;
+
+    fn try_from(v: &'a KstatNamedData) -> Result<Self, Self::Error> {
+        v.as_i128()
+            .map(|i| i as f64)
+            .ok_or(ConversionError::TypeMismatch {
+                expected: "f64",
+                found: v.type_name(),
+            })
+    }
+}
+
+impl<'a> TryFrom<&'a KstatNamedData> for String {
+    type Error = ConversionError;
+
+    fn try_from(v: &'a KstatNamedData) -> Result<Self, Self::Error> {
+        match *v {
+            KstatNamedData::DataString(ref s) => Ok(s.clone()),
+            KstatNamedData::DataChar(_) => Ok(v.char_str().unwrap_or_default().into_owned()),
+            _ => Err(ConversionError::TypeMismatch {
+                expected: "String",
+                found: v.type_name(),
+            }),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct KstatNamed {
     inner: *const ffi::kstat_named_t,
@@ -28,39 +152,78 @@ impl KstatNamed {
         KstatNamed { inner: ptr }
     }
 
-    pub fn name(&self) -> Cow<str> {
+    pub fn name(&self) -> Cow<'_, str> {
         unsafe { (*self.inner).get_name() }
     }
 
-    fn get_data_type(&self) -> u8 {
+    pub fn data_type(&self) -> u8 {
         unsafe { (*self.inner).data_type }
     }
 
-    pub fn read(&self) -> (String, KstatNamedData) {
-        (self.name().into_owned(), self.into())
+    /// Returns the name and value of the statistic, or `None` if its data type is unknown. The
+    /// flag is set if the value was truncated to fit `limits`.
+    pub fn read(&self, limits: &Limits) -> Option<(String, KstatNamedData, bool)> {
+        self.value(limits)
+            .map(|(v, truncated)| (self.name().into_owned(), v, truncated))
     }
-}
 
-impl<'a> From<&'a KstatNamed> for KstatNamedData {
-    fn from(t: &'a KstatNamed) -> Self {
-        match t.get_data_type() {
-            ffi::KSTAT_DATA_CHAR => KstatNamedData::DataChar(unsafe { (*t.inner).value_as_char() }),
-            ffi::KSTAT_DATA_INT32 => {
-                KstatNamedData::DataInt32(unsafe { (*t.inner).value_as_i32() })
-            }
-            ffi::KSTAT_DATA_UINT32 => {
-                KstatNamedData::DataUInt32(unsafe { (*t.inner).value_as_u32() })
-            }
-            ffi::KSTAT_DATA_INT64 => {
-                KstatNamedData::DataInt64(unsafe { (*t.inner).value_as_i64() })
-            }
-            ffi::KSTAT_DATA_UINT64 => {
-                KstatNamedData::DataUInt64(unsafe { (*t.inner).value_as_u64() })
-            }
-            ffi::KSTAT_DATA_STRING => {
-                KstatNamedData::DataString(unsafe { (*t.inner).value_as_string() })
-            }
-            _ => panic!("unknown kstat data type"),
+    fn value(&self, limits: &Limits) -> Option<(KstatNamedData, bool)> {
+        let named = unsafe { &*self.inner };
+        if self.data_type() == ffi::KSTAT_DATA_STRING {
+            let (s, truncated) = named.value_as_string(limits.max_string_len);
+            return Some((KstatNamedData::DataString(s), truncated));
         }
+        let value = match self.data_type() {
+            ffi::KSTAT_DATA_CHAR => KstatNamedData::DataChar(named.value),
+            ffi::KSTAT_DATA_INT32 => KstatNamedData::DataInt32(named.value_as_i32()),
+            ffi::KSTAT_DATA_UINT32 => KstatNamedData::DataUInt32(named.value_as_u32()),
+            ffi::KSTAT_DATA_INT64 => KstatNamedData::DataInt64(named.value_as_i64()),
+            ffi::KSTAT_DATA_UINT64 => KstatNamedData::DataUInt64(named.value_as_u64()),
+            _ => return None,
+        };
+        Some((value, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_conversions() {
+        assert_eq!(u64::try_from(&KstatNamedData::DataUInt32(7)), Ok(7));
+        assert_eq!(i32::try_from(&KstatNamedData::DataInt64(-7)), Ok(-7));
+        assert_eq!(
+            u32::try_from(&KstatNamedData::DataInt32(-1)),
+            Err(ConversionError::OutOfRange { expected: "u32" })
+        );
+        assert_eq!(
+            i64::try_from(&KstatNamedData::DataUInt64(u64::MAX)),
+            Err(ConversionError::OutOfRange { expected: "i64" })
+        );
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let s = KstatNamedData::DataString("on-line".to_string());
+        assert_eq!(String::try_from(&s), Ok("on-line".to_string()));
+        assert_eq!(
+            u64::try_from(&s),
+            Err(ConversionError::TypeMismatch {
+                expected: "u64",
+                found: "string"
+            })
+        );
+        assert!(String::try_from(&KstatNamedData::DataUInt64(1)).is_err());
+    }
+
+    #[test]
+    fn char_array() {
+        let mut c = [0; 16];
+        c[..7].copy_from_slice(b"on-line");
+        let v = KstatNamedData::DataChar(c);
+        assert_eq!(v.char_str().as_ref().map(|s| s.as_ref()), Some("on-line"));
+        assert_eq!(String::try_from(&v), Ok("on-line".to_string()));
+        assert_eq!(KstatNamedData::DataChar([b'x'; 16]).char_str().unwrap().len(), 16);
     }
 }
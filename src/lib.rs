@@ -9,6 +9,10 @@
 //! the named-value pairs associated with those particular kstats. This means that the crate only
 //! allows the consumer to track/read kstats that are of type KSTAT_TYPE_NAMED or KSTAT_TYPE_IO.
 //!
+//! Nothing read from the kernel is trusted: malformed kstats (unknown data types, counts that
+//! overrun the data section, strings without a terminating NUL) are skipped, truncated, or
+//! reported as errors, and never cause a panic.
+//!
 //! # Example:
 //! ```
 //! extern crate kstat;
@@ -16,7 +20,9 @@
 //! use kstat::KstatReader;
 //!
 //! fn main() {
-//!     let reader = KstatReader::new(None, None, None, Some("zone_vfs"))
+//!     let reader = KstatReader::builder()
+//!         .class("zone_vfs")
+//!         .build()
 //!         .expect("failed to create kstat reader");
 //!     let stats = reader.read().expect("failed to read kstats");
 //!     println!("{:#?}", stats);
@@ -24,22 +30,116 @@
 //! ```
 
 extern crate byteorder;
+#[cfg(feature = "stream")]
+extern crate futures_core;
+#[cfg(feature = "ordered")]
+extern crate indexmap;
 extern crate libc;
+extern crate regex;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(feature = "async")]
+extern crate tokio;
 
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::fmt;
 use std::io;
 use std::marker::PhantomData;
+use std::str;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+#[macro_use]
+mod macros;
 
+pub mod accum;
+pub mod analyze;
+mod builder;
+mod burst;
+pub mod compat;
+mod coordinator;
+mod error;
+pub mod exporters;
 mod ffi;
+#[cfg(test)]
+mod fixtures;
+pub mod helpers;
+pub mod hrtime;
+mod iter;
 mod kstat_ctl;
+mod matcher;
+mod meta;
+mod sampler;
+mod selector;
+#[cfg(feature = "serde")]
+mod serialize;
+mod triplet;
+mod watcher;
+#[cfg(feature = "async")]
+mod stream;
+#[cfg(feature = "stream")]
+mod thread_stream;
+/// The data found in a kstat of type KSTAT_TYPE_IO
+pub mod kstat_io;
 /// The type of data found in named-value pairs of a kstat
 pub mod kstat_named;
 
-use kstat_ctl::{Kstat, KstatCtl};
+pub use builder::KstatReaderBuilder;
+pub use burst::BurstCapture;
+pub use coordinator::{CoordinatorGuard, SampleCoordinator};
+pub use error::{Error, OpenFailure};
+pub use helpers::cpu;
+pub use iter::{ErrorPolicy, KstatIter, ReadOutcome, ReadProgress};
+use iter::MatchCache;
+use triplet::{KstatTriplet, TripletCache};
+use kstat_ctl::{CtlGuard, Kstat, Limits};
+pub use kstat_ctl::KstatCtl;
+use kstat_io::KstatIoData;
 use kstat_named::KstatNamedData;
+pub use matcher::Matcher;
+pub use meta::{KstatFlags, KstatId, KstatMeta, KstatType};
+pub use sampler::{
+    KstatDelta, KstatPairs, KstatSampler, SampleTimings, StatDelta, StatPair, WarmUp,
+};
+pub use selector::KstatSelector;
+#[cfg(feature = "serde")]
+pub use serialize::serialize_into;
+pub use watcher::{ChainChanges, KstatWatcher};
+#[cfg(feature = "async")]
+pub use stream::KstatStream;
+#[cfg(feature = "stream")]
+pub use thread_stream::KstatThreadStream;
+
+/// The named-value pairs of a KSTAT_TYPE_NAMED kstat. With the `ordered` feature this is an
+/// `IndexMap` that keeps the statistics in the order the kernel lists them, which is the order
+/// kstat(1M) prints them in, so that output diffs cleanly between runs.
+#[cfg(feature = "ordered")]
+pub type NamedMap = indexmap::IndexMap<String, KstatNamedData>;
+
+/// The named-value pairs of a KSTAT_TYPE_NAMED kstat. Enable the `ordered` feature to keep them
+/// in the order the kernel lists them.
+#[cfg(not(feature = "ordered"))]
+pub type NamedMap = std::collections::HashMap<String, KstatNamedData>;
+
+/// The data section of a kstat, decoded according to its type
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Data {
+    /// A hashmap of the named-value pairs of a KSTAT_TYPE_NAMED kstat
+    Named(NamedMap),
+    /// The I/O statistics of a KSTAT_TYPE_IO kstat
+    Io(KstatIoData),
+}
 
 /// The corresponding data read in from a kstat
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct KstatData {
     /// string denoting class of kstat
     pub class: String,
@@ -53,154 +153,465 @@ pub struct KstatData {
     pub snaptime: i64,
     /// creation time of this kstat in nanoseconds since boot
     pub crtime: i64,
-    /// A hashmap of the named-value pairs for the kstat
-    pub data: HashMap<String, KstatNamedData>,
+    /// the unique kstat ID, which is never reused while the system is up, unlike the
+    /// module/instance/name of a kstat that was deleted and recreated
+    pub kid: i32,
+    /// the kstat's flags
+    pub flags: KstatFlags,
+    /// The named-value pairs or I/O statistics of the kstat
+    pub data: Data,
+    /// the names of the string statistics that were cut short by
+    /// `KstatReader::max_string_len`
+    pub truncated: Vec<String>,
+    /// the names of the statistics that were left out because this crate cannot decode their
+    /// data type
+    pub unsupported: Vec<String>,
+}
+
+impl KstatData {
+    /// Returns the module/instance/name identity of this kstat
+    pub fn id(&self) -> KstatId {
+        KstatId {
+            module: self.module.clone(),
+            instance: self.instance,
+            name: self.name.clone(),
+        }
+    }
+
+    /// Returns the wall clock time this snapshot was taken. See the `hrtime` module for how
+    /// hrtime is converted.
+    pub fn snaptime_as_systemtime(&self) -> SystemTime {
+        hrtime::to_system_time(self.snaptime)
+    }
+
+    /// Returns the wall clock time this kstat was created
+    pub fn crtime_as_systemtime(&self) -> SystemTime {
+        hrtime::to_system_time(self.crtime)
+    }
+
+    /// Returns the named statistic `key`, or `None` if it does not exist or this is not a
+    /// KSTAT_TYPE_NAMED kstat
+    pub fn get(&self, key: &str) -> Option<&KstatNamedData> {
+        match self.data {
+            Data::Named(ref named) => named.get(key),
+            Data::Io(_) => None,
+        }
+    }
+
+    /// Returns the statistic `key` as a u64 if it is an unsigned integer. For KSTAT_TYPE_IO
+    /// kstats `key` is the name of a `KstatIoData` field.
+    ///
+    /// # Example
+    /// ```
+    /// let reader = kstat::KstatReader::builder()
+    ///     .module("zone_vfs")
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// for stat in reader.read().expect("failed to read kstat(s)") {
+    ///     println!("{}: {:?}", stat.name, stat.get_u64("nread"));
+    /// }
+    /// ```
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        match self.data {
+            Data::Named(ref named) => match named.get(key) {
+                Some(&KstatNamedData::DataUInt32(u)) => Some(u64::from(u)),
+                Some(&KstatNamedData::DataUInt64(u)) => Some(u),
+                _ => None,
+            },
+            Data::Io(ref io) => match key {
+                "nread" => Some(io.nread),
+                "nwritten" => Some(io.nwritten),
+                "reads" => Some(u64::from(io.reads)),
+                "writes" => Some(u64::from(io.writes)),
+                "wcnt" => Some(u64::from(io.wcnt)),
+                "rcnt" => Some(u64::from(io.rcnt)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Returns the statistic `key` as an i64 if it is a signed integer or an unsigned integer
+    /// that always fits. For KSTAT_TYPE_IO kstats `key` is the name of a `KstatIoData` field.
+    pub fn get_i64(&self, key: &str) -> Option<i64> {
+        match self.data {
+            Data::Named(ref named) => match named.get(key) {
+                Some(&KstatNamedData::DataInt32(i)) => Some(i64::from(i)),
+                Some(&KstatNamedData::DataUInt32(u)) => Some(i64::from(u)),
+                Some(&KstatNamedData::DataInt64(i)) => Some(i),
+                _ => None,
+            },
+            Data::Io(ref io) => match key {
+                "reads" => Some(i64::from(io.reads)),
+                "writes" => Some(i64::from(io.writes)),
+                "wtime" => Some(io.wtime),
+                "wlentime" => Some(io.wlentime),
+                "wlastupdate" => Some(io.wlastupdate),
+                "rtime" => Some(io.rtime),
+                "rlentime" => Some(io.rlentime),
+                "rlastupdate" => Some(io.rlastupdate),
+                "wcnt" => Some(i64::from(io.wcnt)),
+                "rcnt" => Some(i64::from(io.rcnt)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Returns the statistic `key` if it is a string, or a KSTAT_DATA_CHAR holding a NUL
+    /// terminated UTF-8 string
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        match self.get(key) {
+            Some(KstatNamedData::DataString(s)) => Some(s),
+            Some(KstatNamedData::DataChar(c)) => {
+                let end = c.iter().position(|&b| b == 0).unwrap_or(c.len());
+                str::from_utf8(&c[..end]).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A boxed user supplied closure, wrapped so that it can live in types that derive `Debug`
+struct Callback<F: ?Sized>(Box<F>);
+
+impl<F: ?Sized> fmt::Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Callback")
+    }
 }
 
-/// `KstatReader` represents all of the kstats that matched the fields of interest when created
-/// with `KstatCtl.reader(...)`
+/// The predicate set with `KstatReaderBuilder::filter_fn`
+type FilterFn = Callback<dyn Fn(&KstatMeta) -> bool + Send>;
+
+/// The callback set with `KstatReaderBuilder::on_reopen`
+type ReopenFn = Callback<dyn Fn(u32) + Send>;
+
+/// The callback set with `KstatReaderBuilder::on_progress`
+type ProgressFn = Callback<dyn Fn(ReadProgress) + Send>;
+
+/// `KstatReader` represents all of the kstats that match the fields of interest. It is created
+/// with a `KstatReaderBuilder`, and each reader opens its own kstat handle unless one is shared
+/// with `KstatReaderBuilder::ctl`.
+///
+/// A `KstatReader` is `Send`, so it can be moved into a worker thread or task, but it is not
+/// `Sync`: libkstat handles must not be used by more than one thread at a time.
 #[derive(Debug)]
 pub struct KstatReader {
-    module: Option<String>,
-    instance: Option<i32>,
-    name: Option<String>,
-    class: Option<String>,
-    ctl: KstatCtl,
+    filter: KstatSelector,
+    selectors: Vec<KstatSelector>,
+    kstat_type: Option<KstatType>,
+    filter_fn: Option<FilterFn>,
+    reopen_after: Option<u32>,
+    on_reopen: Option<ReopenFn>,
+    on_progress: Option<(usize, ProgressFn)>,
+    error_policy: ErrorPolicy,
+    limits: Limits,
+    sorted: bool,
+    timings: Cell<SampleTimings>,
+    failures: Cell<u32>,
+    match_cache: RefCell<Option<MatchCache>>,
+    triplet: Option<KstatTriplet>,
+    triplet_cache: RefCell<TripletCache>,
+    ctl: Arc<KstatCtl>,
 }
 
 impl KstatReader {
-    /// Returns a `KstatReader` that tracks the kstats of interest.
+    /// Returns a `KstatReader` that reads every kstat. Use `builder` to narrow down the kstats
+    /// of interest.
     ///
     /// # Example
     /// ```
-    /// let reader = kstat::KstatReader::new()
-    /// .expect("failed to create kstat reader");
-    ///
+    /// let reader = kstat::KstatReader::new().expect("failed to create kstat reader");
+    /// let stats = reader.read().expect("failed to read kstat(s)");
     /// ```
     pub fn new() -> io::Result<Self> {
-        let ctl = KstatCtl::new()?;
-
-        Ok(KstatReader {
-            module: None,
-            instance: None,
-            name: None,
-            class: None,
-            ctl,
-        })
+        KstatReaderBuilder::new().build()
+    }
+
+    /// Returns a `KstatReaderBuilder` to configure the filters and behaviour of a `KstatReader`.
+    /// This is the way to configure a reader; the setters on `KstatReader` itself are deprecated
+    /// shims kept for existing code, see the `compat` module.
+    pub fn builder() -> KstatReaderBuilder {
+        KstatReaderBuilder::new()
+    }
+
+    /// Returns the kstat handle this Reader reads through, so that it can be shared with other
+    /// readers
+    pub fn ctl(&self) -> &Arc<KstatCtl> {
+        &self.ctl
+    }
+
+    /// Returns where the time of the most recent walk of the chain went. Only the
+    /// `chain_update`, `read` and `decode` phases are filled in.
+    pub fn last_timings(&self) -> SampleTimings {
+        self.timings.get()
+    }
+
+    fn record_timings<F: FnOnce(&mut SampleTimings)>(&self, f: F) {
+        let mut timings = self.timings.get();
+        f(&mut timings);
+        self.timings.set(timings);
+    }
+
+    fn record_failure(&self) {
+        self.failures.set(self.failures.get() + 1);
+    }
+
+    fn record_success(&self) {
+        self.failures.set(0);
+    }
+
+    fn needs_reopen(&self) -> bool {
+        match self.reopen_after {
+            Some(n) => self.failures.get() >= n,
+            None => false,
+        }
+    }
+
+    fn reopen(&self, ctl: &mut CtlGuard) -> io::Result<()> {
+        let failures = self.failures.get();
+        ctl.reopen()?;
+        self.record_success();
+        if let Some(ref f) = self.on_reopen {
+            (f.0)(failures);
+        }
+        Ok(())
     }
 
-    // XXX update
-    /// Calling module on the Reader will set the module filter.
+    fn matches(&self, kstat: &Kstat) -> bool {
+        if !self.filter.matches(kstat) {
+            return false;
+        }
+
+        if !self.selectors.is_empty() && !self.selectors.iter().any(|s| s.matches(kstat)) {
+            return false;
+        }
+
+        if let Some(t) = self.kstat_type {
+            if KstatType::from(kstat.get_type()) != t {
+                return false;
+            }
+        }
+
+        match self.filter_fn {
+            Some(ref f) => (f.0)(&kstat.meta()),
+            None => true,
+        }
+    }
+
+    /// Calling read on the Reader will update the kstat chain and proceed to walk the chain
+    /// reading the corresponding data of a kstat that matches the search criteria. The kstats
+    /// that matched are remembered, so as long as the chain does not change later reads skip
+    /// evaluating the filters.
     ///
     /// # Example
     /// ```
-    /// # let reader = kstat::KstatReader::new(None, None, None, Some("zone_vfs")).unwrap();
+    /// let reader = kstat::KstatReader::builder()
+    ///     .class("zone_vfs")
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
     /// let stats = reader.read().expect("failed to read kstat(s)");
     /// ```
-    pub fn module<S>(&mut self, m: S) -> &mut Self
-    where
-        S: Into<String>,
-    {
-       self.module = Some(m.into());
-       self
+    pub fn read(&self) -> io::Result<Vec<KstatData>> {
+        let mut data = self.iter()?.collect::<io::Result<Vec<_>>>()?;
+        self.sort(&mut data);
+        Ok(data)
     }
 
-    // XXX update
-    /// Calling module on the Reader will set the name filter.
+    /// Like `read`, but returns the kstats keyed by their module, instance, and name, so that two
+    /// samples can be correlated without indexing them by hand. Should the chain hold two kstats
+    /// with the same identity, the one found last is kept.
     ///
     /// # Example
     /// ```
-    /// # let reader = kstat::KstatReader::new(None, None, None, Some("zone_vfs")).unwrap();
-    /// let stats = reader.read().expect("failed to read kstat(s)");
+    /// # use std::thread;
+    /// # use std::time::Duration;
+    /// let reader = kstat::KstatReader::builder()
+    ///     .module("cpu")
+    ///     .name("sys")
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// let before = reader.read_map().expect("failed to read kstat(s)");
+    /// thread::sleep(Duration::from_secs(1));
+    /// for (id, after) in reader.read_map().expect("failed to read kstat(s)") {
+    ///     if let Some(prev) = before.get(&id) {
+    ///         println!("cpu {}: {:?} -> {:?}", id.instance, prev.get("intr"), after.get("intr"));
+    ///     }
+    /// }
     /// ```
-    pub fn name<S>(&mut self, n: S) -> &mut Self
-    where
-        S: Into<String>,
-    {
-       self.name = Some(n.into());
-       self
+    pub fn read_map(&self) -> io::Result<HashMap<KstatId, KstatData>> {
+        self.iter()?.map(|r| r.map(|data| (data.id(), data))).collect()
     }
 
-    // XXX update
-    /// Calling module on the Reader will set the class filter.
+    /// Like `read`, but also returns the errors of the kstats that were skipped because of the
+    /// Reader's `ErrorPolicy`, e.g. kstats that went away mid read, so that they can be counted
+    /// or logged.
     ///
     /// # Example
     /// ```
-    /// # let reader = kstat::KstatReader::new(None, None, None, Some("zone_vfs")).unwrap();
-    /// let stats = reader.read().expect("failed to read kstat(s)");
+    /// let reader = kstat::KstatReader::builder()
+    ///     .module("zone_vfs")
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// let outcome = reader.read_outcome().expect("failed to read kstat(s)");
+    /// for e in &outcome.skipped {
+    ///     eprintln!("skipped: {}", e);
+    /// }
+    /// ```
+    pub fn read_outcome(&self) -> io::Result<ReadOutcome> {
+        let mut iter = self.iter()?;
+        let mut data = iter.by_ref().collect::<io::Result<Vec<_>>>()?;
+        self.sort(&mut data);
+        Ok(ReadOutcome {
+            data,
+            skipped: iter.into_skipped(),
+        })
+    }
+
+    /// Drive a collection loop on the current thread, calling `read` once every `interval` and
+    /// handing each result to `f`. The loop stops as soon as `f` returns false. Ticks are
+    /// scheduled relative to when the loop started, so a slow read or callback does not cause the
+    /// schedule to drift; ticks that are missed entirely are skipped. Returns an `InvalidInput`
+    /// error without reading if `interval` is zero.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::time::Duration;
+    /// let reader = kstat::KstatReader::builder()
+    ///     .module("link")
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// let mut count = 0;
+    /// reader.every(Duration::from_secs(1), |stats| {
+    ///     println!("{:?}", stats);
+    ///     count += 1;
+    ///     count < 10
+    /// })
+    /// .expect("interval is zero");
     /// ```
-    pub fn class<S>(&mut self, c: S) -> &mut Self
+    pub fn every<F>(&self, interval: Duration, mut f: F) -> io::Result<()>
     where
-        S: Into<String>,
+        F: FnMut(io::Result<Vec<KstatData>>) -> bool,
     {
-       self.class = Some(c.into());
-       self
+        sampler::run_every(interval, || f(self.read()))
     }
 
-    /// Calling read on the Reader will update the kstat chain and proceed to walk the chain
-    /// reading the corresponding data of a kstat that matches the search criteria.
+    /// Calling iter on the Reader will update the kstat chain and return an iterator that walks
+    /// the chain, reading each kstat that matches the search criteria as it is reached. The
+    /// Reader's kstat handle is held until the iterator is dropped, so starting another walk
+    /// through the same handle on the same thread while the iterator is alive will deadlock.
     ///
     /// # Example
     /// ```
-    /// # let reader = kstat::KstatReader::new(None, None, None, Some("zone_vfs")).unwrap();
-    /// let stats = reader.read().expect("failed to read kstat(s)");
+    /// let reader = kstat::KstatReader::builder()
+    ///     .module("cpu")
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// let first = reader
+    ///     .iter()
+    ///     .expect("failed to update kstat chain")
+    ///     .next();
     /// ```
-    pub fn read(&self) -> io::Result<Vec<KstatData>> {
-        // First update the chain
-        self.ctl.chain_update()?;
+    pub fn iter(&self) -> io::Result<KstatIter<'_>> {
+        self.timings.set(SampleTimings::default());
+        let ctl = self.update_chain()?;
+
+        Ok(match self.triplet {
+            Some(ref triplet) => KstatIter::lookup(self, ctl, triplet, true),
+            None => KstatIter::new(self, ctl),
+        })
+    }
 
+    /// Read exactly the kstat `module:instance:name`, found with `kstat_lookup()`, regardless of
+    /// the Reader's filters. Returns `None` if there is no such kstat, it is not of type
+    /// KSTAT_TYPE_NAMED or KSTAT_TYPE_IO, or reading it failed with an error the Reader's
+    /// `ErrorPolicy` ignores.
+    ///
+    /// # Example
+    /// ```
+    /// let reader = kstat::KstatReader::new().expect("failed to create kstat reader");
+    /// match reader.read_one("zfs", 0, "arcstats").expect("failed to read arcstats") {
+    ///     Some(arcstats) => println!("ARC size: {:?}", arcstats.get_u64("size")),
+    ///     None => println!("no ARC"),
+    /// }
+    /// ```
+    pub fn read_one(
+        &self,
+        module: &str,
+        instance: i32,
+        name: &str,
+    ) -> io::Result<Option<KstatData>> {
+        // No kstat has a NUL in its module or name
+        let triplet = match self.triplet_cache.borrow_mut().triplet(module, instance, name) {
+            Some(triplet) => triplet,
+            None => return Ok(None),
+        };
+        self.timings.set(SampleTimings::default());
+        let ctl = self.update_chain()?;
+        KstatIter::lookup(self, ctl, &triplet, false).next().transpose()
+    }
+
+    /// Walk the chain and return the header of every kstat that matches the search criteria,
+    /// without reading any of their data. Unlike `read` this includes kstats of every type, which
+    /// makes it cheap to discover what exists right now.
+    ///
+    /// # Example
+    /// ```
+    /// let reader = kstat::KstatReader::builder()
+    ///     .module("zone_vfs")
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// for meta in reader.list().expect("failed to list kstat(s)") {
+    ///     println!("{}:{}:{}", meta.module, meta.instance, meta.name);
+    /// }
+    /// ```
+    pub fn list(&self) -> io::Result<Vec<KstatMeta>> {
+        let ctl = self.update_chain()?;
         let mut ret = Vec::new();
-        let mut kstat_ptr = self.ctl.get_chain();
-        while !kstat_ptr.is_null() {
+        let mut next = ctl.get_chain();
+        while !next.is_null() {
             let kstat = Kstat {
-                inner: kstat_ptr,
+                inner: next,
                 _marker: PhantomData,
             };
-
-            // Loop until we reach the end of the chain
-            kstat_ptr = unsafe { (*kstat_ptr).ks_next };
-
-            // must be NAMED or IO
-            let ks_type = kstat.get_type();
-            if ks_type != ffi::KSTAT_TYPE_NAMED && ks_type != ffi::KSTAT_TYPE_IO {
-                continue;
-            }
-
-
-            if self.module.is_some() && kstat.get_module() != *self.module.as_ref().unwrap() {
-                continue;
+            next = unsafe { (*next).ks_next };
+            if self.matches(&kstat) {
+                ret.push(kstat.meta());
             }
+        }
+        Ok(ret)
+    }
 
-            if self.instance.is_some() && kstat.get_instance() != *self.instance.as_ref().unwrap() {
-                continue;
-            }
+    /// Sort `data` by module, instance, and name if the Reader was built with `sorted`
+    fn sort(&self, data: &mut [KstatData]) {
+        if self.sorted {
+            data.sort_by(|a, b| {
+                (&a.module, a.instance, &a.name).cmp(&(&b.module, b.instance, &b.name))
+            });
+        }
+    }
 
-            if self.name.is_some() && kstat.get_name() != *self.name.as_ref().unwrap() {
-                continue;
-            }
+    /// Lock the kstat handle and bring its chain up to date, reopening the handle if it has been
+    /// failing persistently
+    fn update_chain(&self) -> io::Result<CtlGuard<'_>> {
+        let mut ctl = self.ctl.lock();
+        let start = Instant::now();
 
-            if self.class.is_some() && kstat.get_class() != *self.class.as_ref().unwrap() {
-                continue;
-            }
+        // Reads from the previous walk may have left us failing persistently
+        if self.needs_reopen() {
+            self.reopen(&mut ctl)?;
+        }
 
-            match kstat.read(&self.ctl) {
-                Ok(k) => ret.push(k),
-                Err(e) => {
-                    match e.raw_os_error().unwrap() {
-                        // the kstat went away by the time we call read, so forget it and move on
-                        // example: a zone is no longer running
-                        libc::ENXIO => continue,
-                        // I don't know why EIO seems to be common here. The kstat cmd on illumos
-                        // seems to ignore all errors and continue while only reporting the errors
-                        // when REPORT_UNKNOWN is set
-                        libc::EIO => continue,
-                        _ => return Err(e),
-                    }
-                }
+        // First update the chain
+        if let Err(e) = ctl.chain_update() {
+            self.record_failure();
+            if !self.needs_reopen() {
+                return Err(e);
             }
+            // A freshly opened handle already has an up to date chain
+            self.reopen(&mut ctl)?;
         }
-
-        Ok(ret)
+        self.record_timings(|t| t.chain_update = start.elapsed());
+        Ok(ctl)
     }
 }
 
@@ -208,19 +619,28 @@ impl KstatReader {
 mod tests {
     use super::*;
 
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn reader_is_send() {
+        assert_send::<KstatReader>();
+        assert_send::<KstatSampler>();
+    }
+
     #[test]
     fn all_reader() {
-        let reader =
-            KstatReader::new::<String>(None, None, None, None).expect("failed to create reader");
+        let reader = KstatReader::new().expect("failed to create reader");
         let stats = reader.read().expect("failed to read kstat(s)");
-        assert!(stats.len() > 0);
+        assert!(!stats.is_empty());
     }
 
     #[test]
     fn module_reader() {
         let module = "cpu";
-        let reader =
-            KstatReader::new(Some(module), None, None, None).expect("failed to create reader");
+        let reader = KstatReader::builder()
+            .module(module)
+            .build()
+            .expect("failed to create reader");
         let stats = reader.read().expect("failed to read kstat(s)");
         for stat in stats {
             assert_eq!(stat.module, module);
@@ -230,7 +650,9 @@ mod tests {
     #[test]
     fn instance_reader() {
         let instance: i32 = 0;
-        let reader = KstatReader::new::<String>(None, Some(instance), None, None)
+        let reader = KstatReader::builder()
+            .instance(instance)
+            .build()
             .expect("failed to create reader");
         let stats = reader.read().expect("failed to read kstat(s)");
         for stat in stats {
@@ -241,8 +663,10 @@ mod tests {
     #[test]
     fn name_reader() {
         let name = "vm";
-        let reader =
-            KstatReader::new(None, None, Some(name), None).expect("failed to create reader");
+        let reader = KstatReader::builder()
+            .name(name)
+            .build()
+            .expect("failed to create reader");
         let stats = reader.read().expect("failed to read kstat(s)");
         for stat in stats {
             assert_eq!(stat.name, name);
@@ -252,8 +676,10 @@ mod tests {
     #[test]
     fn class_reader() {
         let class = "misc";
-        let reader =
-            KstatReader::new(None, None, None, Some(class)).expect("failed to create reader");
+        let reader = KstatReader::builder()
+            .class(class)
+            .build()
+            .expect("failed to create reader");
         let stats = reader.read().expect("failed to read kstat(s)");
         for stat in stats {
             assert_eq!(stat.class, class);
@@ -266,7 +692,12 @@ mod tests {
         let instance = 1;
         let name = "kmem_alloc_16";
         let class = "keme_cache";
-        let reader = KstatReader::new(Some(module), Some(instance), Some(name), Some(class))
+        let reader = KstatReader::builder()
+            .module(module)
+            .instance(instance)
+            .name(name)
+            .class(class)
+            .build()
             .expect("failed to create reader");
         let stats = reader.read().expect("failed to read kstat(s)");
         for stat in stats {
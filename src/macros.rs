@@ -0,0 +1,130 @@
+/// Define a typed helper for a kstat, in the same shape as the built-in helpers.
+///
+/// Given the module and name of a kstat and a list of fields, each with a type and the name of
+/// the statistic it is decoded from, this generates a struct with those fields (plus the kstat
+/// `instance` and `snaptime`) and two functions:
+///
+/// - `from_kstat(&KstatData, DecodeMode) -> io::Result<Self>` decodes an already read kstat
+/// - `read(DecodeMode) -> io::Result<Vec<Self>>` reads and decodes every instance of the kstat
+///
+/// Field types must implement `TryFrom<&KstatNamedData>` and `Default`: the integer types,
+/// `f64` and `String`. With `DecodeMode::Strict` a missing or mistyped statistic is an error;
+/// with `DecodeMode::Lenient` the field is left at its default value instead.
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate kstat;
+///
+/// use kstat::helpers::DecodeMode;
+///
+/// kstat_struct! {
+///     /// The ZFS ARC statistics
+///     pub struct ArcStats("zfs", "arcstats") {
+///         /// current size of the ARC in bytes
+///         size: u64 = "size",
+///         /// ARC hits
+///         hits: u64 = "hits",
+///         /// ARC misses
+///         misses: u64 = "misses",
+///     }
+/// }
+///
+/// fn main() {
+///     for arc in ArcStats::read(DecodeMode::Strict).expect("failed to read arcstats") {
+///         println!("{} bytes, {} hits", arc.size, arc.hits);
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! kstat_struct {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident($module:expr, $kname:expr) {
+            $(
+                $(#[$fattr:meta])*
+                $field:ident: $ty:ty = $key:expr
+            ),* $(,)*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, PartialEq, Default)]
+        pub struct $name {
+            /// the kstat instance
+            pub instance: i32,
+            /// nanoseconds since boot of this snapshot
+            pub snaptime: i64,
+            $(
+                $(#[$fattr])*
+                pub $field: $ty,
+            )*
+        }
+
+        impl $name {
+            /// Decode an already read kstat
+            pub fn from_kstat(
+                data: &$crate::KstatData,
+                mode: $crate::helpers::DecodeMode,
+            ) -> ::std::io::Result<Self> {
+                Ok($name {
+                    instance: data.instance,
+                    snaptime: data.snaptime,
+                    $(
+                        $field: $crate::helpers::decode_field(data, $key, mode)?,
+                    )*
+                })
+            }
+
+            /// Read and decode every instance of the kstat
+            pub fn read(mode: $crate::helpers::DecodeMode) -> ::std::io::Result<Vec<Self>> {
+                let reader = $crate::KstatReader::builder()
+                    .module($module)
+                    .name($kname)
+                    .build()?;
+                reader
+                    .read()?
+                    .iter()
+                    .map(|data| $name::from_kstat(data, mode))
+                    .collect()
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use fixtures::kstat;
+    use helpers::DecodeMode;
+    use kstat_named::KstatNamedData;
+
+    kstat_struct! {
+        /// A test kstat
+        pub struct Test("unix", "test") {
+            /// a counter
+            hits: u64 = "hits",
+            /// a string
+            label: String = "label",
+        }
+    }
+
+    #[test]
+    fn strict_and_lenient() {
+        let data = kstat(
+            "unix",
+            1,
+            "test",
+            vec![("hits", KstatNamedData::DataUInt64(7))],
+        );
+
+        assert!(Test::from_kstat(&data, DecodeMode::Strict).is_err());
+        let test = Test::from_kstat(&data, DecodeMode::Lenient).unwrap();
+        assert_eq!((test.instance, test.hits, test.label.as_str()), (1, 7, ""));
+    }
+
+    #[test]
+    fn generates_read() {
+        // Reading needs a live kstat device, so only check that `read` is generated
+        let read: fn(DecodeMode) -> ::std::io::Result<Vec<Test>> = Test::read;
+        let _ = read;
+    }
+}
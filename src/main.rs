@@ -1,10 +1,331 @@
 extern crate kstat;
 
-use kstat::KstatReader;
+use kstat::kstat_named::KstatNamedData;
+use kstat::{Data, KstatData, KstatReader, KstatSelector, Matcher};
+#[cfg(test)]
+use kstat::kstat_io::KstatIoData;
+#[cfg(test)]
+use kstat::KstatFlags;
+
+use std::env;
+use std::io::{self, Write};
+use std::process;
+use std::time::Duration;
+
+const USAGE: &str = "Usage: kstat [-p] [-m module] [-i instance] [-n name] [-c class]
+             [module:instance:name:statistic ...] [interval [count]]";
+
+/// Which kstats and statistics to print, from the -m/-i/-n/-c flags or from a
+/// module:instance:name:statistic operand. Unset fields match anything.
+#[derive(Debug, Default)]
+struct Operand {
+    module: Option<Matcher>,
+    instance: Option<i32>,
+    name: Option<Matcher>,
+    class: Option<Matcher>,
+    statistic: Option<Matcher>,
+}
+
+impl Operand {
+    fn selector(&self) -> KstatSelector {
+        let mut selector = KstatSelector::new();
+        if let Some(ref m) = self.module {
+            selector = selector.module(m.clone());
+        }
+        if let Some(i) = self.instance {
+            selector = selector.instance(i);
+        }
+        if let Some(ref n) = self.name {
+            selector = selector.name(n.clone());
+        }
+        if let Some(ref c) = self.class {
+            selector = selector.class(c.clone());
+        }
+        selector
+    }
+
+    fn matches(&self, kstat: &KstatData) -> bool {
+        self.module.as_ref().map_or(true, |m| m.matches(&kstat.module))
+            && self.instance.map_or(true, |i| i == kstat.instance)
+            && self.name.as_ref().map_or(true, |n| n.matches(&kstat.name))
+            && self.class.as_ref().map_or(true, |c| c.matches(&kstat.class))
+    }
+}
+
+/// Command line options, mirroring a subset of kstat(1M)
+#[derive(Debug, Default)]
+struct Opts {
+    parseable: bool,
+    /// one operand per module:instance:name:statistic argument, preceded by the operand built
+    /// from the -m/-i/-n/-c flags if any were given or if there are no other operands
+    operands: Vec<Operand>,
+    interval: Option<Duration>,
+    count: Option<u64>,
+}
+
+fn usage(msg: &str) -> ! {
+    eprintln!("kstat: {}", msg);
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+/// Like kstat(1M), a value wrapped in slashes is treated as a regular expression
+fn matcher(s: &str) -> Matcher {
+    if s.len() > 1 && s.starts_with('/') && s.ends_with('/') {
+        Matcher::regex(&s[1..s.len() - 1])
+            .unwrap_or_else(|e| usage(&format!("invalid regular expression {}: {}", s, e)))
+    } else {
+        Matcher::exact(s)
+    }
+}
+
+fn instance(s: &str) -> i32 {
+    s.parse().unwrap_or_else(|_| usage(&format!("invalid instance {}", s)))
+}
+
+/// Parse a `module:instance:name:statistic` operand, where empty or missing fields match
+/// anything
+fn parse_operand(opts: &mut Opts, arg: &str) {
+    let fields: Vec<&str> = arg.split(':').collect();
+    if fields.len() > 4 {
+        usage(&format!("invalid operand {}", arg));
+    }
+
+    let mut operand = Operand::default();
+    for (i, field) in fields.iter().enumerate().filter(|&(_, f)| !f.is_empty()) {
+        match i {
+            0 => operand.module = Some(matcher(field)),
+            1 => operand.instance = Some(instance(field)),
+            2 => operand.name = Some(matcher(field)),
+            _ => operand.statistic = Some(matcher(field)),
+        }
+    }
+    opts.operands.push(operand);
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Opts {
+    let mut opts = Opts::default();
+    let mut flags: Option<Operand> = None;
+    while let Some(arg) = args.next() {
+        if arg == "-p" {
+            opts.parseable = true;
+            continue;
+        }
+
+        // Trailing numbers are the interval and count
+        if let Ok(n) = arg.parse::<u64>() {
+            if opts.interval.is_none() {
+                if n == 0 {
+                    usage("interval must be greater than 0");
+                }
+                opts.interval = Some(Duration::from_secs(n));
+            } else if opts.count.is_none() {
+                opts.count = Some(n);
+            } else {
+                usage(&format!("unexpected argument {}", arg));
+            }
+            continue;
+        }
+
+        if opts.interval.is_some() {
+            usage(&format!("unexpected argument {}", arg));
+        }
+
+        if !arg.starts_with('-') {
+            parse_operand(&mut opts, &arg);
+            continue;
+        }
+
+        let value = match arg.as_str() {
+            "-m" | "-i" | "-n" | "-c" => args
+                .next()
+                .unwrap_or_else(|| usage(&format!("option {} requires an argument", arg))),
+            _ => usage(&format!("unrecognized argument {}", arg)),
+        };
+
+        let operand = flags.get_or_insert_with(Operand::default);
+        match arg.as_str() {
+            "-m" => operand.module = Some(matcher(&value)),
+            "-n" => operand.name = Some(matcher(&value)),
+            "-c" => operand.class = Some(matcher(&value)),
+            _ => operand.instance = Some(instance(&value)),
+        }
+    }
+
+    if flags.is_some() || opts.operands.is_empty() {
+        opts.operands.insert(0, flags.unwrap_or_default());
+    }
+    opts
+}
+
+/// The statistics of a kstat, in the order kstat(1M) prints them
+fn stats(kstat: &KstatData) -> Vec<(String, String)> {
+    let mut ret: Vec<(String, String)> = match kstat.data {
+        Data::Named(ref named) => named
+            .iter()
+            .map(|(k, v)| {
+                let v = match *v {
+                    KstatNamedData::DataChar(_) => v.char_str().unwrap_or_default().into_owned(),
+                    KstatNamedData::DataInt32(i) => i.to_string(),
+                    KstatNamedData::DataUInt32(u) => u.to_string(),
+                    KstatNamedData::DataInt64(i) => i.to_string(),
+                    KstatNamedData::DataUInt64(u) => u.to_string(),
+                    KstatNamedData::DataString(ref s) => s.clone(),
+                };
+                (k.clone(), v)
+            })
+            .collect(),
+        Data::Io(ref io) => vec![
+            ("nread".to_string(), io.nread.to_string()),
+            ("nwritten".to_string(), io.nwritten.to_string()),
+            ("reads".to_string(), io.reads.to_string()),
+            ("writes".to_string(), io.writes.to_string()),
+            ("wtime".to_string(), io.wtime.to_string()),
+            ("wlentime".to_string(), io.wlentime.to_string()),
+            ("wlastupdate".to_string(), io.wlastupdate.to_string()),
+            ("rtime".to_string(), io.rtime.to_string()),
+            ("rlentime".to_string(), io.rlentime.to_string()),
+            ("rlastupdate".to_string(), io.rlastupdate.to_string()),
+            ("wcnt".to_string(), io.wcnt.to_string()),
+            ("rcnt".to_string(), io.rcnt.to_string()),
+        ],
+    };
+    ret.push(("class".to_string(), kstat.class.clone()));
+    ret.push(("crtime".to_string(), hrtime(kstat.crtime)));
+    ret.push(("snaptime".to_string(), hrtime(kstat.snaptime)));
+    ret.sort();
+    ret
+}
+
+/// Format nanoseconds as seconds the way kstat(1M) does
+fn hrtime(ns: i64) -> String {
+    format!("{}.{:09}", ns / 1_000_000_000, ns % 1_000_000_000)
+}
+
+/// Returns true if `stat` of `kstat` should be printed, i.e. an operand that matches `kstat`
+/// has no statistic field or one that matches `stat`
+fn wanted(opts: &Opts, kstat: &KstatData, stat: &str) -> bool {
+    opts.operands.iter().any(|op| {
+        op.matches(kstat) && op.statistic.as_ref().map_or(true, |m| m.matches(stat))
+    })
+}
+
+fn print_parseable<W: Write>(w: &mut W, opts: &Opts, kstat: &KstatData) -> io::Result<()> {
+    for (k, v) in stats(kstat).into_iter().filter(|(k, _)| wanted(opts, kstat, k)) {
+        writeln!(w, "{}:{}:{}:{}\t{}", kstat.module, kstat.instance, kstat.name, k, v)?;
+    }
+    Ok(())
+}
+
+fn print_human<W: Write>(w: &mut W, opts: &Opts, kstat: &KstatData) -> io::Result<()> {
+    writeln!(w, "module: {:<30}instance: {}", kstat.module, kstat.instance)?;
+    writeln!(w, "name:   {:<30}class:    {}", kstat.name, kstat.class)?;
+    for (k, v) in stats(kstat) {
+        if k != "class" && wanted(opts, kstat, &k) {
+            writeln!(w, "\t{:<30} {}", k, v)?;
+        }
+    }
+    writeln!(w)
+}
+
+fn print<W: Write>(w: &mut W, opts: &Opts, stats: Vec<KstatData>) -> io::Result<()> {
+    for kstat in &stats {
+        if opts.parseable {
+            print_parseable(w, opts, kstat)?;
+        } else {
+            print_human(w, opts, kstat)?;
+        }
+    }
+    w.flush()
+}
 
 fn main() {
-    let mut reader = KstatReader::new().expect("failed to create kstat reader");
-    reader.module("zone_vfs").class("zone_vfs");
-    let stats = reader.read().expect("failed to read kstats");
-    println!("{:#?}", stats);
+    let opts = parse_args(env::args().skip(1));
+
+    let mut builder = KstatReader::builder().sorted(true);
+    for operand in &opts.operands {
+        builder = builder.add_selector(operand.selector());
+    }
+    let reader = builder.build().unwrap_or_else(|e| {
+        eprintln!("kstat: failed to open kstat: {}", e);
+        process::exit(1);
+    });
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut emit = |stats: io::Result<Vec<KstatData>>| {
+        let stats = stats.unwrap_or_else(|e| {
+            eprintln!("kstat: failed to read kstats: {}", e);
+            process::exit(1);
+        });
+        if print(&mut out, &opts, stats).is_err() {
+            // Most likely a closed pipe, e.g. `kstat -p | head`
+            process::exit(1);
+        }
+    };
+
+    let interval = match opts.interval {
+        Some(i) => i,
+        None => return emit(reader.read()),
+    };
+
+    let mut remaining = opts.count;
+    let looped = reader.every(interval, |stats| {
+        emit(stats);
+        remaining = remaining.map(|r| r.saturating_sub(1));
+        remaining != Some(0)
+    });
+    if let Err(e) = looped {
+        eprintln!("kstat: {}", e);
+        process::exit(1);
+    }
+}
+
+// The library's fixtures are not compiled into its non-test build, so include them directly
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "fixtures.rs"]
+mod fixtures;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures;
+
+    fn kstat(module: &str, instance: i32, name: &str) -> KstatData {
+        fixtures::kstat(module, instance, name, Vec::new())
+    }
+
+    fn parse(args: &[&str]) -> Opts {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn statistics_stay_with_their_operand() {
+        let opts = parse(&["cpu:0:sys:intr", "zfs:0:arcstats:size"]);
+        assert_eq!(opts.operands.len(), 2);
+
+        let sys = kstat("cpu", 0, "sys");
+        assert!(wanted(&opts, &sys, "intr"));
+        assert!(!wanted(&opts, &sys, "size"));
+
+        let arcstats = kstat("zfs", 0, "arcstats");
+        assert!(wanted(&opts, &arcstats, "size"));
+        assert!(!wanted(&opts, &arcstats, "intr"));
+
+        assert!(!wanted(&opts, &kstat("cpu", 1, "sys"), "intr"));
+    }
+
+    #[test]
+    fn flags_print_every_statistic() {
+        let opts = parse(&["-m", "cpu", "zfs:0:arcstats:size"]);
+        assert_eq!(opts.operands.len(), 2);
+        assert!(wanted(&opts, &kstat("cpu", 3, "sys"), "intr"));
+        assert!(!wanted(&opts, &kstat("zfs", 0, "arcstats"), "hits"));
+
+        let opts = parse(&["1", "5"]);
+        assert!(wanted(&opts, &kstat("zfs", 0, "arcstats"), "hits"));
+        assert_eq!(opts.interval, Some(Duration::from_secs(1)));
+        assert_eq!(opts.count, Some(5));
+    }
 }
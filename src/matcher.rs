@@ -0,0 +1,134 @@
+use super::error::Error;
+
+use regex::Regex;
+
+use std::io;
+
+/// A pattern used to match the module, name, or class of a kstat
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    /// Matches the string exactly
+    Exact(String),
+    /// Matches a shell style glob where `*` matches any run of characters and `?` matches a
+    /// single character
+    Glob(String),
+    /// Matches a regular expression, similar to the `/regex/` form accepted by kstat(1M)
+    Regex(Regex),
+    /// Matches any of the strings exactly
+    OneOf(Vec<String>),
+}
+
+impl Matcher {
+    /// Returns a `Matcher` that matches `s` exactly
+    pub fn exact<S: Into<String>>(s: S) -> Self {
+        Matcher::Exact(s.into())
+    }
+
+    /// Returns a `Matcher` that matches the glob `pattern`
+    pub fn glob<S: Into<String>>(pattern: S) -> Self {
+        Matcher::Glob(pattern.into())
+    }
+
+    /// Returns a `Matcher` that matches the regular expression `re`, or an `Error::InvalidFilter`
+    /// if `re` fails to compile
+    pub fn regex(re: &str) -> io::Result<Self> {
+        Regex::new(re).map(Matcher::Regex).map_err(|source| {
+            Error::InvalidFilter {
+                pattern: re.to_string(),
+                source,
+            }
+            .into()
+        })
+    }
+
+    /// Returns a `Matcher` that matches any of `values` exactly
+    pub fn one_of<I, S>(values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Matcher::OneOf(values.into_iter().map(Into::into).collect())
+    }
+
+    /// Returns true if `s` is matched by this `Matcher`
+    pub fn matches(&self, s: &str) -> bool {
+        match *self {
+            Matcher::Exact(ref e) => e == s,
+            Matcher::Glob(ref g) => glob_match(g.as_bytes(), s.as_bytes()),
+            Matcher::Regex(ref r) => r.is_match(s),
+            Matcher::OneOf(ref v) => v.iter().any(|e| e == s),
+        }
+    }
+}
+
+impl From<String> for Matcher {
+    fn from(s: String) -> Self {
+        Matcher::Exact(s)
+    }
+}
+
+impl<'a> From<&'a str> for Matcher {
+    fn from(s: &'a str) -> Self {
+        Matcher::Exact(s.to_string())
+    }
+}
+
+/// Iterative glob matcher that backtracks to the most recent `*` on a mismatch.
+fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while i < s.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == s[i]) {
+            p += 1;
+            i += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, i));
+            p += 1;
+        } else if let Some((sp, si)) = star {
+            p = sp + 1;
+            i = si + 1;
+            star = Some((sp, si + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(Matcher::glob("zone_vfs*").matches("zone_vfs"));
+        assert!(Matcher::glob("kmem_alloc_*").matches("kmem_alloc_16"));
+        assert!(Matcher::glob("e1000g?").matches("e1000g0"));
+        assert!(Matcher::glob("*_*_*").matches("kmem_alloc_16"));
+        assert!(!Matcher::glob("kmem_alloc_*").matches("kmem_cache"));
+        assert!(!Matcher::glob("e1000g?").matches("e1000g10"));
+    }
+
+    #[test]
+    fn regex() {
+        let m = Matcher::regex("^e1000g").expect("failed to compile regex");
+        assert!(m.matches("e1000g0"));
+        assert!(!m.matches("igb0"));
+        let e = Matcher::regex("(").expect_err("compiled an invalid regex");
+        match Error::from_io(&e) {
+            Some(Error::InvalidFilter { pattern, .. }) => assert_eq!(pattern, "("),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn one_of() {
+        let m = Matcher::one_of(["sd", "ssd", "blkdev"]);
+        assert!(m.matches("ssd"));
+        assert!(m.matches("blkdev"));
+        assert!(!m.matches("s"));
+        assert!(!Matcher::one_of(Vec::<String>::new()).matches(""));
+    }
+}
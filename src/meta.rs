@@ -0,0 +1,110 @@
+use super::ffi;
+
+use std::ops;
+
+/// The type of a kstat's data section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KstatType {
+    /// KSTAT_TYPE_RAW
+    Raw,
+    /// KSTAT_TYPE_NAMED
+    Named,
+    /// KSTAT_TYPE_INTR
+    Intr,
+    /// KSTAT_TYPE_IO
+    Io,
+    /// KSTAT_TYPE_TIMER
+    Timer,
+    /// A type this crate does not know about
+    Unknown(u8),
+}
+
+impl From<u8> for KstatType {
+    fn from(t: u8) -> Self {
+        match t {
+            ffi::KSTAT_TYPE_RAW => KstatType::Raw,
+            ffi::KSTAT_TYPE_NAMED => KstatType::Named,
+            ffi::KSTAT_TYPE_INTR => KstatType::Intr,
+            ffi::KSTAT_TYPE_IO => KstatType::Io,
+            ffi::KSTAT_TYPE_TIMER => KstatType::Timer,
+            _ => KstatType::Unknown(t),
+        }
+    }
+}
+
+/// The `ks_flags` of a kstat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KstatFlags(u8);
+
+impl KstatFlags {
+    /// KSTAT_FLAG_VIRTUAL: the data section is owned by the provider
+    pub const VIRTUAL: KstatFlags = KstatFlags(ffi::KSTAT_FLAG_VIRTUAL);
+    /// KSTAT_FLAG_VAR_SIZE: the size of the data section may change
+    pub const VAR_SIZE: KstatFlags = KstatFlags(ffi::KSTAT_FLAG_VAR_SIZE);
+    /// KSTAT_FLAG_WRITABLE: the kstat can be written to
+    pub const WRITABLE: KstatFlags = KstatFlags(ffi::KSTAT_FLAG_WRITABLE);
+    /// KSTAT_FLAG_PERSISTENT: the kstat survives its provider going away
+    pub const PERSISTENT: KstatFlags = KstatFlags(ffi::KSTAT_FLAG_PERSISTENT);
+    /// KSTAT_FLAG_DORMANT: a persistent kstat whose provider has gone away
+    pub const DORMANT: KstatFlags = KstatFlags(ffi::KSTAT_FLAG_DORMANT);
+    /// KSTAT_FLAG_INVALID: the kstat is still being initialized
+    pub const INVALID: KstatFlags = KstatFlags(ffi::KSTAT_FLAG_INVALID);
+    /// KSTAT_FLAG_LONGSTRINGS: the kstat contains KSTAT_DATA_STRING values
+    pub const LONGSTRINGS: KstatFlags = KstatFlags(ffi::KSTAT_FLAG_LONGSTRINGS);
+
+    /// Wrap the raw `ks_flags` value
+    pub fn from_bits(bits: u8) -> Self {
+        KstatFlags(bits)
+    }
+
+    /// Returns the raw `ks_flags` value
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns true if every flag set in `other` is also set in `self`
+    pub fn contains(self, other: KstatFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl ops::BitOr for KstatFlags {
+    type Output = KstatFlags;
+
+    fn bitor(self, rhs: KstatFlags) -> KstatFlags {
+        KstatFlags(self.0 | rhs.0)
+    }
+}
+
+/// The header fields of a kstat, available without reading its data
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KstatMeta {
+    /// string denoting module of kstat
+    pub module: String,
+    /// int denoting instance of kstat
+    pub instance: i32,
+    /// string denoting name of kstat
+    pub name: String,
+    /// string denoting class of kstat
+    pub class: String,
+    /// the type of the kstat's data section
+    pub kstat_type: KstatType,
+    /// creation time of this kstat in nanoseconds since boot
+    pub crtime: i64,
+    /// the unique kstat ID
+    pub kid: i32,
+    /// the kstat's flags
+    pub flags: KstatFlags,
+}
+
+/// The identity of a kstat: its module, instance, and name
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KstatId {
+    /// string denoting module of kstat
+    pub module: String,
+    /// int denoting instance of kstat
+    pub instance: i32,
+    /// string denoting name of kstat
+    pub name: String,
+}
@@ -0,0 +1,725 @@
+use super::accum::{self, StatKind, Width};
+use super::error::Error;
+use super::kstat_named::KstatNamedData;
+use {Data, KstatData, KstatId, KstatReader};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The change in a single statistic between two snapshots of a kstat
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatDelta {
+    /// the difference between the current and previous value. 32-bit unsigned statistics that
+    /// went backwards are treated as counters that wrapped, while 64-bit ones that went backwards
+    /// were reset and give a negative delta, see `accum::counter_delta`.
+    pub delta: i64,
+    /// `delta` divided by the time between the two snapshots, in units per second. For
+    /// statistics `accum::classify` knows to be accumulators this is instead their average value
+    /// over the interval, see `accum::average`, or 0 if their update counter did not advance.
+    pub rate: f64,
+}
+
+/// The change in every numeric statistic of a kstat between two snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub struct KstatDelta {
+    /// string denoting class of kstat
+    pub class: String,
+    /// string denoting module of kstat
+    pub module: String,
+    /// int denoting instance of kstat
+    pub instance: i32,
+    /// string denoting name of kstat
+    pub name: String,
+    /// nanoseconds between the two snapshots
+    pub interval: i64,
+    /// A hashmap of statistic name to its change over `interval`
+    pub stats: HashMap<String, StatDelta>,
+    /// true if this is the first time the kstat was sampled, in which case `interval` and
+    /// `stats` cover the whole life of the kstat since it was created rather than the time since
+    /// the previous sample. Only produced when the sampler's `WarmUp` is `PassThrough`.
+    pub warm_up: bool,
+    /// true if `interval` is longer than the sampler's gap threshold, e.g. because the collector
+    /// stalled or the host was suspended, so that `rate` is an average over a period that was
+    /// not observed
+    pub gap: bool,
+}
+
+/// A single statistic's value in two snapshots of a kstat along with its change between them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatPair {
+    /// the value in the previous snapshot, or 0 for a warm-up pair. Values are widened so that
+    /// signed and unsigned statistics fit.
+    pub previous: i128,
+    /// the value in the current snapshot
+    pub current: i128,
+    /// the difference between the current and previous value, see `StatDelta::delta`
+    pub delta: i64,
+    /// the rate or average of the statistic over the interval, see `StatDelta::rate`
+    pub rate: f64,
+}
+
+/// The previous and current value of every numeric statistic of a kstat along with their change,
+/// returned by `KstatSampler::sample_pairs`
+#[derive(Debug, Clone, PartialEq)]
+pub struct KstatPairs {
+    /// string denoting class of kstat
+    pub class: String,
+    /// string denoting module of kstat
+    pub module: String,
+    /// int denoting instance of kstat
+    pub instance: i32,
+    /// string denoting name of kstat
+    pub name: String,
+    /// nanoseconds since boot of the current snapshot
+    pub snaptime: i64,
+    /// nanoseconds between the two snapshots
+    pub interval: i64,
+    /// A hashmap of statistic name to its values and change over `interval`
+    pub stats: HashMap<String, StatPair>,
+    /// see `KstatDelta::warm_up`
+    pub warm_up: bool,
+    /// see `KstatDelta::gap`
+    pub gap: bool,
+}
+
+impl KstatPairs {
+    /// Pair up the values of `prev` and `cur` with their `delta`. `prev` is `None` for warm-up
+    /// deltas, whose previous values are all 0.
+    fn new(delta: KstatDelta, prev: Option<&KstatData>, cur: &KstatData) -> Self {
+        let prev_values = prev.map(|p| numeric_values(&p.data)).unwrap_or_default();
+        let cur_values = numeric_values(&cur.data);
+        let stats = delta
+            .stats
+            .into_iter()
+            .map(|(key, d)| {
+                let pair = StatPair {
+                    previous: prev_values.get(&key).cloned().unwrap_or(0),
+                    current: cur_values.get(&key).cloned().unwrap_or(0),
+                    delta: d.delta,
+                    rate: d.rate,
+                };
+                (key, pair)
+            })
+            .collect();
+
+        KstatPairs {
+            class: delta.class,
+            module: delta.module,
+            instance: delta.instance,
+            name: delta.name,
+            snaptime: cur.snaptime,
+            interval: delta.interval,
+            stats,
+            warm_up: delta.warm_up,
+            gap: delta.gap,
+        }
+    }
+}
+
+/// What a `KstatSampler` does with a kstat the first time it sees it, when there is no previous
+/// snapshot to compute a delta against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WarmUp {
+    /// only record the snapshot as the baseline for the next sample. This is the default.
+    #[default]
+    Suppress,
+    /// also return a delta covering the time since the kstat was created, with `warm_up` set
+    PassThrough,
+}
+
+impl KstatDelta {
+    /// Compute the change between two snapshots of the same kstat. Returns `None` if the
+    /// snapshots are of different kstats, the kstat was recreated in between (its kid or crtime
+    /// changed), or no time passed between them.
+    pub fn between(prev: &KstatData, cur: &KstatData) -> Option<KstatDelta> {
+        if prev.id() != cur.id() || prev.kid != cur.kid || prev.crtime != cur.crtime {
+            return None;
+        }
+
+        let prev_values = numeric_values(&prev.data);
+        KstatDelta::compute(cur, cur.snaptime - prev.snaptime, false, |key| {
+            prev_values.get(key).cloned()
+        })
+    }
+
+    /// Compute the change of a kstat since it was created, i.e. treat every counter as having
+    /// started at 0 at `crtime`. The result has `warm_up` set. Returns `None` if no time passed
+    /// since the kstat was created.
+    pub fn since_creation(cur: &KstatData) -> Option<KstatDelta> {
+        KstatDelta::compute(cur, cur.snaptime - cur.crtime, true, |_| Some(0))
+    }
+
+    fn compute<F>(cur: &KstatData, interval: i64, warm_up: bool, prev: F) -> Option<KstatDelta>
+    where
+        F: Fn(&str) -> Option<i128>,
+    {
+        if interval <= 0 {
+            return None;
+        }
+
+        let secs = interval as f64 / 1_000_000_000.0;
+        let values = numeric_values(&cur.data);
+        let mut stats = HashMap::new();
+        for (key, &value) in &values {
+            let old = match prev(key) {
+                Some(old) => old,
+                None => continue,
+            };
+            let width = width(&cur.data, key);
+            let wrapped = unsigned(old, value)
+                .zip(width)
+                .and_then(|((old, value), width)| accum::counter_delta(old, value, width));
+            let delta = match wrapped {
+                Some(delta) => delta as i64,
+                // Signed statistics and reset 64-bit counters that went backwards come out as
+                // a negative delta
+                None => (value - old) as i64,
+            };
+            let rate = match accum::classify(&cur.module, &cur.name, key) {
+                StatKind::Counter => delta as f64 / secs,
+                StatKind::Accumulator { updates } => {
+                    let updates = values.get(updates).and_then(|&u| Some((prev(updates)?, u)));
+                    updates
+                        .and_then(|(old_updates, updates)| {
+                            let (old, value) = unsigned(old, value)?;
+                            let (old_updates, updates) = unsigned(old_updates, updates)?;
+                            accum::average(old, value, old_updates, updates, width?)
+                        })
+                        .unwrap_or(0.0)
+                }
+            };
+            stats.insert(key.clone(), StatDelta { delta, rate });
+        }
+
+        Some(KstatDelta {
+            class: cur.class.clone(),
+            module: cur.module.clone(),
+            instance: cur.instance,
+            name: cur.name.clone(),
+            interval,
+            stats,
+            warm_up,
+            gap: false,
+        })
+    }
+}
+
+/// Where the time of a single sample went, so that slow collection can be diagnosed in the field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SampleTimings {
+    /// time spent in `kstat_chain_update()`, including reopening the handle if that was needed
+    pub chain_update: Duration,
+    /// time spent in `kstat_read()` copying kstats out of the kernel
+    pub read: Duration,
+    /// time spent decoding the copied data
+    pub decode: Duration,
+    /// time spent computing deltas against the previous sample
+    pub delta: Duration,
+    /// time the caller reported spending on exporting the sample with
+    /// `KstatSampler::record_export`
+    pub export: Duration,
+}
+
+impl SampleTimings {
+    /// The total of all the phases
+    pub fn total(&self) -> Duration {
+        self.chain_update + self.read + self.decode + self.delta + self.export
+    }
+}
+
+/// `KstatSampler` wraps a `KstatReader` and remembers the previous snapshot of every kstat it
+/// reads, so that each call to `sample` returns the change since the last call.
+///
+/// # Example
+/// ```
+/// # use std::thread;
+/// # use std::time::Duration;
+/// let reader = kstat::KstatReader::builder()
+///     .module("cpu")
+///     .name("sys")
+///     .build()
+///     .expect("failed to create kstat reader");
+/// let mut sampler = kstat::KstatSampler::new(reader);
+/// sampler.sample().expect("failed to sample kstat(s)");
+/// thread::sleep(Duration::from_secs(1));
+/// for delta in sampler.sample().expect("failed to sample kstat(s)") {
+///     println!("{:?}", delta.stats.get("cpu_ticks_idle"));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct KstatSampler {
+    reader: KstatReader,
+    previous: HashMap<KstatId, KstatData>,
+    timings: Option<SampleTimings>,
+    warm_up: WarmUp,
+    gap_threshold: Option<i64>,
+}
+
+impl KstatSampler {
+    /// Returns a `KstatSampler` that samples the kstats tracked by `reader`
+    pub fn new(reader: KstatReader) -> Self {
+        KstatSampler {
+            reader,
+            previous: HashMap::new(),
+            timings: None,
+            warm_up: WarmUp::default(),
+            gap_threshold: None,
+        }
+    }
+
+    /// Mark deltas whose interval is longer than `threshold` as a `gap`. A sensible threshold is
+    /// a small multiple of the sampling interval. Warm-up deltas are never marked.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::time::Duration;
+    /// let reader = kstat::KstatReader::builder()
+    ///     .module("cpu")
+    ///     .name("sys")
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// let mut sampler = kstat::KstatSampler::new(reader);
+    /// sampler.gap_threshold(Duration::from_secs(3));
+    /// sampler.every(Duration::from_secs(1), |deltas| {
+    ///     for delta in deltas.expect("failed to sample kstat(s)") {
+    ///         if delta.gap {
+    ///             eprintln!("missed samples of {}:{}", delta.module, delta.instance);
+    ///         }
+    ///     }
+    ///     false
+    /// })
+    /// .expect("interval is zero");
+    /// ```
+    pub fn gap_threshold(&mut self, threshold: Duration) -> &mut Self {
+        self.gap_threshold = Some(i64::try_from(threshold.as_nanos()).unwrap_or(i64::MAX));
+        self
+    }
+
+    /// Set what happens to kstats seen for the first time, or recreated since the previous
+    /// sample. By default they only establish a baseline.
+    pub fn warm_up(&mut self, warm_up: WarmUp) -> &mut Self {
+        self.warm_up = warm_up;
+        self
+    }
+
+    /// Returns a reference to the underlying `KstatReader`
+    pub fn reader(&self) -> &KstatReader {
+        &self.reader
+    }
+
+    /// Read the tracked kstats and return the change of each one since the previous call. Kstats
+    /// seen for the first time, or recreated since the previous call, are handled according to
+    /// the sampler's `WarmUp`.
+    pub fn sample(&mut self) -> io::Result<Vec<KstatDelta>> {
+        self.advance(|delta, _, _| delta)
+    }
+
+    /// Like `sample`, but also return the previous and current value of every statistic next
+    /// to its change, for consumers that need both the raw counters and their rates
+    ///
+    /// # Example
+    /// ```
+    /// # use std::thread;
+    /// # use std::time::Duration;
+    /// let reader = kstat::KstatReader::builder()
+    ///     .module("link")
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// let mut sampler = kstat::KstatSampler::new(reader);
+    /// sampler.sample_pairs().expect("failed to sample kstat(s)");
+    /// thread::sleep(Duration::from_secs(1));
+    /// for link in sampler.sample_pairs().expect("failed to sample kstat(s)") {
+    ///     if let Some(rbytes) = link.stats.get("rbytes64") {
+    ///         println!("{}: {} bytes, {:.0} bytes/s", link.name, rbytes.current, rbytes.rate);
+    ///     }
+    /// }
+    /// ```
+    pub fn sample_pairs(&mut self) -> io::Result<Vec<KstatPairs>> {
+        self.advance(KstatPairs::new)
+    }
+
+    /// Read the tracked kstats, hand every delta to `f` along with the snapshots it was computed
+    /// from, and remember the current snapshots for the next call
+    fn advance<T, F>(&mut self, mut f: F) -> io::Result<Vec<T>>
+    where
+        F: FnMut(KstatDelta, Option<&KstatData>, &KstatData) -> T,
+    {
+        let current = self.reader.read()?;
+        let start = Instant::now();
+
+        let mut ret = Vec::new();
+        let mut next = HashMap::with_capacity(current.len());
+        for cur in current {
+            let (delta, prev) = match self.previous.get(&cur.id()) {
+                Some(prev) if prev.kid == cur.kid && prev.crtime == cur.crtime => {
+                    (KstatDelta::between(prev, &cur), Some(prev))
+                }
+                _ => match self.warm_up {
+                    WarmUp::Suppress => (None, None),
+                    WarmUp::PassThrough => (KstatDelta::since_creation(&cur), None),
+                },
+            };
+            if let Some(mut d) = delta {
+                d.gap = !d.warm_up && self.gap_threshold.is_some_and(|t| d.interval > t);
+                ret.push(f(d, prev, &cur));
+            }
+            next.insert(cur.id(), cur);
+        }
+
+        // Forget kstats that have gone away
+        self.previous = next;
+
+        let mut timings = self.reader.last_timings();
+        timings.delta = start.elapsed();
+        self.timings = Some(timings);
+
+        Ok(ret)
+    }
+
+    /// Returns where the time of the last successful `sample` went
+    pub fn last_timings(&self) -> Option<SampleTimings> {
+        self.timings
+    }
+
+    /// Add the time spent exporting the last sample to its timings
+    ///
+    /// # Example
+    /// ```
+    /// # use std::time::Instant;
+    /// let reader = kstat::KstatReader::builder()
+    ///     .module("zone_vfs")
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// let mut sampler = kstat::KstatSampler::new(reader);
+    /// let deltas = sampler.sample().expect("failed to sample kstat(s)");
+    /// let start = Instant::now();
+    /// println!("{:?}", deltas);
+    /// sampler.record_export(start.elapsed());
+    /// println!("{:?}", sampler.last_timings());
+    /// ```
+    pub fn record_export(&mut self, elapsed: Duration) {
+        if let Some(ref mut timings) = self.timings {
+            timings.export += elapsed;
+        }
+    }
+
+    /// Drive a collection loop on the current thread, calling `sample` once every `interval` and
+    /// handing each result to `f`. The loop stops as soon as `f` returns false. Ticks are
+    /// scheduled relative to when the loop started, so a slow sample or callback does not cause
+    /// the schedule to drift; ticks that are missed entirely are skipped.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::sync::mpsc;
+    /// # use std::time::Duration;
+    /// let reader = kstat::KstatReader::builder()
+    ///     .module("zone_vfs")
+    ///     .build()
+    ///     .expect("failed to create kstat reader");
+    /// let mut sampler = kstat::KstatSampler::new(reader);
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let mut remaining = 3;
+    /// sampler.every(Duration::from_millis(100), |deltas| {
+    ///     remaining -= 1;
+    ///     tx.send(deltas).is_ok() && remaining > 0
+    /// })
+    /// .expect("interval is zero");
+    /// assert_eq!(rx.try_iter().count(), 3);
+    /// ```
+    pub fn every<F>(&mut self, interval: Duration, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(io::Result<Vec<KstatDelta>>) -> bool,
+    {
+        run_every(interval, || f(self.sample()))
+    }
+}
+
+/// Returns the width of the statistic `key` of `data`, or `None` if it is not an unsigned counter
+fn width(data: &Data, key: &str) -> Option<Width> {
+    match *data {
+        Data::Named(ref named) => named.get(key).and_then(Width::of),
+        Data::Io(_) => match key {
+            "nread" | "nwritten" => Some(Width::Bits64),
+            "reads" | "writes" => Some(Width::Bits32),
+            _ => None,
+        },
+    }
+}
+
+/// Returns `prev` and `cur` as unsigned counter values, if both are
+fn unsigned(prev: i128, cur: i128) -> Option<(u64, u64)> {
+    Some((u64::try_from(prev).ok()?, u64::try_from(cur).ok()?))
+}
+
+/// Call `f` once every `interval` until it returns false. Ticks are scheduled relative to the
+/// first call so the schedule does not drift, and ticks that are missed entirely are skipped.
+/// Fails without calling `f` if `interval` is zero, which would never yield a next tick.
+pub(crate) fn run_every<F: FnMut() -> bool>(interval: Duration, mut f: F) -> io::Result<()> {
+    if interval == Duration::from_secs(0) {
+        return Err(Error::ZeroInterval.into());
+    }
+
+    let mut next = Instant::now();
+    loop {
+        if !f() {
+            return Ok(());
+        }
+
+        next += interval;
+        let now = Instant::now();
+        while next < now {
+            next += interval;
+        }
+        thread::sleep(next - now);
+    }
+}
+
+/// Collect the numeric statistics of a kstat, widened so that any two can be subtracted
+pub(crate) fn numeric_values(data: &Data) -> HashMap<String, i128> {
+    match *data {
+        Data::Named(ref named) => named
+            .iter()
+            .filter_map(|(k, v)| {
+                let value = match *v {
+                    KstatNamedData::DataInt32(i) => i as i128,
+                    KstatNamedData::DataUInt32(u) => u as i128,
+                    KstatNamedData::DataInt64(i) => i as i128,
+                    KstatNamedData::DataUInt64(u) => u as i128,
+                    _ => return None,
+                };
+                Some((k.clone(), value))
+            })
+            .collect(),
+        Data::Io(ref io) => [
+            ("nread", io.nread as i128),
+            ("nwritten", io.nwritten as i128),
+            ("reads", io.reads as i128),
+            ("writes", io.writes as i128),
+            ("wtime", io.wtime as i128),
+            ("wlentime", io.wlentime as i128),
+            ("rtime", io.rtime as i128),
+            ("rlentime", io.rlentime as i128),
+        ]
+        .iter()
+        .map(|&(k, v)| (k.to_string(), v))
+        .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::kstat;
+
+    fn named(snaptime: i64, crtime: i64, stats: Vec<(&str, KstatNamedData)>) -> KstatData {
+        KstatData {
+            snaptime,
+            crtime,
+            ..kstat("unix", 0, "test", stats)
+        }
+    }
+
+    #[test]
+    fn delta_and_rate() {
+        let prev = named(
+            1_000_000_000,
+            0,
+            vec![("hits", KstatNamedData::DataUInt64(100))],
+        );
+        let cur = named(
+            3_000_000_000,
+            0,
+            vec![("hits", KstatNamedData::DataUInt64(300))],
+        );
+        let delta = KstatDelta::between(&prev, &cur).expect("expected a delta");
+        assert_eq!(delta.interval, 2_000_000_000);
+        assert_eq!(
+            delta.stats["hits"],
+            StatDelta {
+                delta: 200,
+                rate: 100.0
+            }
+        );
+    }
+
+    #[test]
+    fn strings_are_skipped() {
+        let prev = named(0, 0, vec![("s", KstatNamedData::DataString(String::new()))]);
+        let cur = named(1, 0, vec![("s", KstatNamedData::DataString(String::new()))]);
+        let delta = KstatDelta::between(&prev, &cur).expect("expected a delta");
+        assert!(delta.stats.is_empty());
+    }
+
+    #[test]
+    fn recreated_kstat() {
+        let prev = named(1, 0, vec![("hits", KstatNamedData::DataUInt64(100))]);
+        let cur = named(2, 1, vec![("hits", KstatNamedData::DataUInt64(1))]);
+        assert!(KstatDelta::between(&prev, &cur).is_none());
+    }
+
+    #[test]
+    fn warm_up_since_creation() {
+        let stats = vec![("hits", KstatNamedData::DataUInt64(50))];
+        let cur = named(4_000_000_000, 2_000_000_000, stats);
+        let delta = KstatDelta::since_creation(&cur).expect("expected a delta");
+        assert!(delta.warm_up);
+        assert_eq!(delta.interval, 2_000_000_000);
+        assert_eq!(delta.stats["hits"].rate, 25.0);
+    }
+
+    #[test]
+    fn pairs() {
+        let prev = named(
+            1_000_000_000,
+            0,
+            vec![("hits", KstatNamedData::DataUInt64(100))],
+        );
+        let cur = named(
+            3_000_000_000,
+            0,
+            vec![("hits", KstatNamedData::DataUInt64(300))],
+        );
+        let delta = KstatDelta::between(&prev, &cur).expect("expected a delta");
+        let pairs = KstatPairs::new(delta, Some(&prev), &cur);
+        assert_eq!(pairs.snaptime, 3_000_000_000);
+        assert_eq!(
+            pairs.stats["hits"],
+            StatPair {
+                previous: 100,
+                current: 300,
+                delta: 200,
+                rate: 100.0
+            }
+        );
+
+        let delta = KstatDelta::since_creation(&cur).expect("expected a delta");
+        assert_eq!(KstatPairs::new(delta, None, &cur).stats["hits"].previous, 0);
+    }
+
+    #[test]
+    fn accumulators() {
+        let sysinfo = |snaptime, runque, updates| {
+            let mut k = named(
+                snaptime,
+                0,
+                vec![
+                    ("runque", KstatNamedData::DataUInt32(runque)),
+                    ("updates", KstatNamedData::DataUInt32(updates)),
+                ],
+            );
+            k.name = "sysinfo".to_string();
+            k
+        };
+        // 4 updates in 5 seconds, e.g. because the snapshots fell between ticks
+        let prev = sysinfo(1_000_000_000, 100, 10);
+        let cur = sysinfo(6_000_000_000, 112, 14);
+        let delta = KstatDelta::between(&prev, &cur).expect("expected a delta");
+        assert_eq!(delta.stats["runque"].rate, 3.0);
+        assert_eq!(delta.stats["updates"].rate, 0.8);
+    }
+
+    #[test]
+    fn zero_interval() {
+        let mut calls = 0;
+        let e = run_every(Duration::from_secs(0), || {
+            calls += 1;
+            false
+        })
+        .expect_err("expected a zero interval to be rejected");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(matches!(Error::from_io(&e), Some(&Error::ZeroInterval)));
+        assert_eq!(calls, 0);
+
+        run_every(Duration::from_millis(1), || {
+            calls += 1;
+            calls < 3
+        })
+        .expect("expected a non-zero interval to run");
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn wrapped_counters() {
+        let max = u32::MAX;
+        let sysinfo = |snaptime, runque, updates| {
+            let mut k = named(
+                snaptime,
+                0,
+                vec![
+                    ("runque", KstatNamedData::DataUInt32(runque)),
+                    ("updates", KstatNamedData::DataUInt32(updates)),
+                    ("intr", KstatNamedData::DataInt64(i64::from(runque))),
+                ],
+            );
+            k.name = "sysinfo".to_string();
+            k
+        };
+        // Both the running total and the update counter wrapped at 2^32
+        let prev = sysinfo(1_000_000_000, max - 3, max - 1);
+        let cur = sysinfo(5_000_000_000, 8, 2);
+        let delta = KstatDelta::between(&prev, &cur).expect("expected a delta");
+        assert_eq!(
+            delta.stats["runque"],
+            StatDelta {
+                delta: 12,
+                rate: 3.0
+            }
+        );
+        assert_eq!(
+            delta.stats["updates"],
+            StatDelta {
+                delta: 4,
+                rate: 1.0
+            }
+        );
+        // Signed statistics are never treated as wrapped
+        assert_eq!(delta.stats["intr"].delta, 8 - i64::from(max - 3));
+
+        // An accumulator whose update counter did not advance has no average
+        let cur = sysinfo(5_000_000_000, max - 3, max - 1);
+        assert_eq!(
+            KstatDelta::between(&prev, &cur).unwrap().stats["runque"].rate,
+            0.0
+        );
+    }
+
+    #[test]
+    fn reset_counters() {
+        let prev = named(
+            1_000_000_000,
+            0,
+            vec![("hits", KstatNamedData::DataUInt64(1000))],
+        );
+        let cur = named(
+            2_000_000_000,
+            0,
+            vec![("hits", KstatNamedData::DataUInt64(10))],
+        );
+        let delta = KstatDelta::between(&prev, &cur).expect("expected a delta");
+        assert_eq!(
+            delta.stats["hits"],
+            StatDelta {
+                delta: -990,
+                rate: -990.0
+            }
+        );
+
+        // The same values in a 32-bit counter wrapped
+        let prev = named(
+            1_000_000_000,
+            0,
+            vec![("hits", KstatNamedData::DataUInt32(1000))],
+        );
+        let cur = named(
+            2_000_000_000,
+            0,
+            vec![("hits", KstatNamedData::DataUInt32(10))],
+        );
+        let delta = KstatDelta::between(&prev, &cur).expect("expected a delta");
+        assert_eq!(delta.stats["hits"].delta, i64::from(u32::MAX) - 989);
+    }
+}
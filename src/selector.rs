@@ -0,0 +1,188 @@
+use super::kstat_ctl::Kstat;
+use super::matcher::Matcher;
+use super::triplet::KstatTriplet;
+
+use std::ops::{Bound, RangeBounds, RangeInclusive};
+
+/// The instances a selector matches
+#[derive(Debug)]
+pub(crate) enum Instances {
+    One(i32),
+    Range(RangeInclusive<i32>),
+    Set(Vec<i32>),
+}
+
+impl Instances {
+    /// Convert any range of instances to an inclusive one. Ranges that cannot contain any
+    /// instance, e.g. `..i32::MIN`, become empty inclusive ranges.
+    fn range<R: RangeBounds<i32>>(range: R) -> Self {
+        let start = match range.start_bound() {
+            Bound::Included(&s) => Some(s),
+            Bound::Excluded(&s) => s.checked_add(1),
+            Bound::Unbounded => Some(i32::MIN),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&e) => Some(e),
+            Bound::Excluded(&e) => e.checked_sub(1),
+            Bound::Unbounded => Some(i32::MAX),
+        };
+        match (start, end) {
+            (Some(s), Some(e)) => Instances::Range(s..=e),
+            _ => Instances::Set(Vec::new()),
+        }
+    }
+
+    fn matches(&self, instance: i32) -> bool {
+        match *self {
+            Instances::One(i) => i == instance,
+            Instances::Range(ref r) => r.contains(&instance),
+            Instances::Set(ref s) => s.contains(&instance),
+        }
+    }
+}
+
+/// A module/instance/name/class tuple describing a set of kstats. Any field left unset matches
+/// every kstat.
+///
+/// # Example
+/// ```
+/// let selector = kstat::KstatSelector::new().module("cpu").name("sys");
+/// ```
+#[derive(Debug, Default)]
+pub struct KstatSelector {
+    pub(crate) module: Option<Matcher>,
+    pub(crate) instance: Option<Instances>,
+    pub(crate) name: Option<Matcher>,
+    pub(crate) class: Option<Matcher>,
+}
+
+impl KstatSelector {
+    /// Returns a `KstatSelector` that matches every kstat
+    pub fn new() -> Self {
+        KstatSelector::default()
+    }
+
+    /// Set the module filter of the selector
+    pub fn module<M: Into<Matcher>>(mut self, m: M) -> Self {
+        self.module = Some(m.into());
+        self
+    }
+
+    /// Set the instance filter of the selector
+    pub fn instance(mut self, i: i32) -> Self {
+        self.instance = Some(Instances::One(i));
+        self
+    }
+
+    /// Set the instance filter of the selector to match every instance in `range`
+    pub fn instance_in<R: RangeBounds<i32>>(mut self, range: R) -> Self {
+        self.instance = Some(Instances::range(range));
+        self
+    }
+
+    /// Set the instance filter of the selector to match any of `instances`
+    pub fn instances(mut self, instances: &[i32]) -> Self {
+        self.instance = Some(Instances::Set(instances.to_vec()));
+        self
+    }
+
+    /// Set the name filter of the selector
+    pub fn name<M: Into<Matcher>>(mut self, n: M) -> Self {
+        self.name = Some(n.into());
+        self
+    }
+
+    /// Set the class filter of the selector
+    pub fn class<M: Into<Matcher>>(mut self, c: M) -> Self {
+        self.class = Some(c.into());
+        self
+    }
+
+    /// Set the module filter of the selector to match any of `modules`
+    pub fn modules<I, S>(self, modules: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.module(Matcher::one_of(modules))
+    }
+
+    /// Set the name filter of the selector to match any of `names`
+    pub fn names<I, S>(self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.name(Matcher::one_of(names))
+    }
+
+    /// Set the class filter of the selector to match any of `classes`
+    pub fn classes<I, S>(self, classes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.class(Matcher::one_of(classes))
+    }
+
+    /// Returns the triplet this selector names if its module and name are exact matches and its
+    /// instance is a single one, so that the kstat can be looked up directly
+    pub(crate) fn triplet(&self) -> Option<KstatTriplet> {
+        let instance = match self.instance {
+            Some(Instances::One(i)) => i,
+            _ => return None,
+        };
+        match (&self.module, &self.name) {
+            (&Some(Matcher::Exact(ref m)), &Some(Matcher::Exact(ref n))) => {
+                KstatTriplet::new(m, instance, n)
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn matches(&self, kstat: &Kstat) -> bool {
+        if let Some(ref m) = self.module {
+            if !m.matches(&kstat.get_module()) {
+                return false;
+            }
+        }
+
+        if let Some(ref i) = self.instance {
+            if !i.matches(kstat.get_instance()) {
+                return false;
+            }
+        }
+
+        if let Some(ref n) = self.name {
+            if !n.matches(&kstat.get_name()) {
+                return false;
+            }
+        }
+
+        if let Some(ref c) = self.class {
+            if !c.matches(&kstat.get_class()) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_ranges() {
+        let r = Instances::range(0..4);
+        assert!(r.matches(0) && r.matches(3) && !r.matches(4) && !r.matches(-1));
+        let r = Instances::range(2..=2);
+        assert!(r.matches(2) && !r.matches(1) && !r.matches(3));
+        let r = Instances::range(30..);
+        assert!(r.matches(i32::MAX) && !r.matches(29));
+        assert!(!Instances::range(..i32::MIN).matches(i32::MIN));
+        assert!(Instances::Set(vec![0, 2, 4]).matches(4));
+        assert!(!Instances::Set(vec![0, 2, 4]).matches(1));
+    }
+}
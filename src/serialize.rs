@@ -0,0 +1,66 @@
+use serde::ser::{SerializeSeq, Serializer};
+use serde_json;
+
+use KstatData;
+
+use std::io::{self, Write};
+
+/// Write the kstats yielded by `kstats` to `writer` as a JSON array. Each kstat is written as
+/// soon as it is read and dropped before the next one, so passing a `KstatIter` never holds the
+/// whole sample in memory. A read error stops the output, leaving the array unterminated, and is
+/// returned unchanged.
+///
+/// # Example
+/// ```
+/// let reader = kstat::KstatReader::new().expect("failed to create kstat reader");
+/// let out = std::io::stdout();
+/// let kstats = reader.iter().expect("failed to update kstat chain");
+/// kstat::serialize_into(out.lock(), kstats).expect("failed to write kstats");
+/// ```
+pub fn serialize_into<W, I>(writer: W, kstats: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = io::Result<KstatData>>,
+{
+    let mut ser = serde_json::Serializer::new(writer);
+    let mut seq = ser.serialize_seq(None)?;
+    for kstat in kstats {
+        seq.serialize_element(&kstat?)?;
+    }
+    seq.end()?;
+    ser.into_inner().flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures;
+    use kstat_named::KstatNamedData;
+    use libc;
+
+    fn kstat(name: &str) -> KstatData {
+        fixtures::kstat(
+            "zfs",
+            0,
+            name,
+            vec![("hits", KstatNamedData::DataUInt64(7))],
+        )
+    }
+
+    #[test]
+    fn streams_an_array() {
+        let mut out = Vec::new();
+        let kstats = vec![Ok(kstat("arcstats")), Ok(kstat("zfetchstats"))];
+        serialize_into(&mut out, kstats).expect("failed to serialize");
+        let back: Vec<KstatData> = serde_json::from_slice(&out).expect("failed to deserialize");
+        assert_eq!(back.len(), 2);
+        assert_eq!(back[1].name, "zfetchstats");
+
+        let kstats = vec![
+            Ok(kstat("arcstats")),
+            Err(io::Error::from_raw_os_error(libc::EIO)),
+        ];
+        let e = serialize_into(Vec::new(), kstats).expect_err("serialized a failed read");
+        assert_eq!(e.raw_os_error(), Some(libc::EIO));
+    }
+}
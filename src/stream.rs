@@ -0,0 +1,98 @@
+use futures_core::Stream;
+use tokio::sync::mpsc;
+use tokio::task;
+use tokio::time::{self, Interval};
+
+use {Error, KstatData, KstatReader};
+
+use std::io;
+use std::pin::Pin;
+use std::sync::mpsc as std_mpsc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// A `Stream` of kstat snapshots, one per tick of a tokio interval. The blocking libkstat calls
+/// are made on tokio's blocking thread pool. If a read is still running when the next tick
+/// fires, that tick is skipped.
+///
+/// # Example
+/// ```
+/// # extern crate kstat;
+/// # extern crate tokio;
+/// # use std::time::Duration;
+/// let rt = tokio::runtime::Builder::new_current_thread()
+///     .enable_time()
+///     .build()
+///     .expect("failed to build runtime");
+/// let _guard = rt.enter();
+/// let stream = kstat::KstatStream::new(Duration::from_secs(1), || {
+///     kstat::KstatReader::builder().module("zone_vfs").build()
+/// })
+/// .expect("period is zero");
+/// ```
+#[derive(Debug)]
+pub struct KstatStream {
+    interval: Interval,
+    ticks: std_mpsc::SyncSender<()>,
+    results: mpsc::Receiver<io::Result<Vec<KstatData>>>,
+}
+
+impl KstatStream {
+    /// Returns a `KstatStream` that reads from the `KstatReader` returned by `build` every
+    /// `period`. The reader is created on, and never leaves, the blocking thread that reads it.
+    /// Must be called from within a tokio runtime. Returns an `InvalidInput` error if `period` is
+    /// zero.
+    pub fn new<F>(period: Duration, build: F) -> io::Result<Self>
+    where
+        F: FnOnce() -> io::Result<KstatReader> + Send + 'static,
+    {
+        // tokio panics on a zero period
+        if period == Duration::from_secs(0) {
+            return Err(Error::ZeroInterval.into());
+        }
+
+        let (ticks, tick_rx) = std_mpsc::sync_channel(1);
+        let (result_tx, results) = mpsc::channel(1);
+
+        task::spawn_blocking(move || {
+            let reader = match build() {
+                Ok(r) => r,
+                Err(e) => {
+                    let _ = result_tx.blocking_send(Err(e));
+                    return;
+                }
+            };
+
+            // Runs until the stream, and with it the sending half of `ticks`, is dropped
+            for _ in tick_rx.iter() {
+                if result_tx.blocking_send(reader.read()).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(KstatStream {
+            interval: time::interval(period),
+            ticks,
+            results,
+        })
+    }
+}
+
+impl Stream for KstatStream {
+    type Item = io::Result<Vec<KstatData>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        // Forward every elapsed tick to the reader. A full channel means a read is already
+        // pending, so the tick is dropped rather than queued.
+        while this.interval.poll_tick(cx).is_ready() {
+            if let Err(std_mpsc::TrySendError::Disconnected(_)) = this.ticks.try_send(()) {
+                break;
+            }
+        }
+
+        this.results.poll_recv(cx)
+    }
+}
@@ -0,0 +1,129 @@
+use futures_core::Stream;
+
+use sampler::run_every;
+use {KstatData, KstatReader};
+
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Default)]
+struct State {
+    /// the snapshot the stream has not yet returned
+    ready: Option<io::Result<Vec<KstatData>>>,
+    waker: Option<Waker>,
+    /// the reading thread has exited
+    done: bool,
+    /// the stream has been dropped
+    dropped: bool,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // The state is consistent after every update, so a panic elsewhere doesn't matter
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Hand `result` to the stream and wake it, returning false once the stream is gone
+    fn offer(&self, result: io::Result<Vec<KstatData>>) -> bool {
+        let mut state = self.lock();
+        if state.dropped {
+            return false;
+        }
+        state.ready = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+}
+
+/// A `Stream` of kstat snapshots that works with any executor, such as async-std or smol. The
+/// reader lives on a dedicated thread that reads once every period, so nothing blocks the
+/// executor and no runtime is needed to create the stream. If the previous snapshot has not been
+/// taken from the stream when a read is due, that read is skipped.
+///
+/// # Example
+/// ```
+/// # use std::time::Duration;
+/// let stream = kstat::KstatThreadStream::new(Duration::from_secs(1), || {
+///     kstat::KstatReader::builder().module("zone_vfs").build()
+/// });
+/// ```
+#[derive(Debug)]
+pub struct KstatThreadStream {
+    shared: Arc<Shared>,
+}
+
+impl KstatThreadStream {
+    /// Returns a `KstatThreadStream` that reads from the `KstatReader` returned by `build` every
+    /// `period`. The reader is created on, and never leaves, the thread that reads it. If `build`
+    /// fails or `period` is zero, the stream yields that error and ends.
+    pub fn new<F>(period: Duration, build: F) -> Self
+    where
+        F: FnOnce() -> io::Result<KstatReader> + Send + 'static,
+    {
+        let shared = Arc::new(Shared::default());
+        let theirs = Arc::clone(&shared);
+
+        thread::spawn(move || {
+            let looped = build().and_then(|reader| {
+                run_every(period, || {
+                    {
+                        let state = theirs.lock();
+                        if state.dropped {
+                            return false;
+                        }
+                        // The previous snapshot has not been taken yet, so this read is skipped
+                        if state.ready.is_some() {
+                            return true;
+                        }
+                    }
+                    theirs.offer(reader.read())
+                })
+            });
+            if let Err(e) = looped {
+                theirs.offer(Err(e));
+            }
+
+            let mut state = theirs.lock();
+            state.done = true;
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+
+        KstatThreadStream { shared }
+    }
+}
+
+impl Stream for KstatThreadStream {
+    type Item = io::Result<Vec<KstatData>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut state = self.shared.lock();
+        if let Some(result) = state.ready.take() {
+            return Poll::Ready(Some(result));
+        }
+        if state.done {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for KstatThreadStream {
+    fn drop(&mut self) {
+        // The reading thread exits at its next tick
+        self.shared.lock().dropped = true;
+    }
+}
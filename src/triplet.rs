@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::sync::Arc;
+
+/// The most strings a `TripletCache` holds before it starts over, so that looking up a
+/// changing set of kstats cannot grow it without bound
+const CACHE_CAPACITY: usize = 256;
+
+/// A fully specified module:instance:name, converted once into the C strings that
+/// `kstat_lookup()` takes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct KstatTriplet {
+    module: Arc<CStr>,
+    instance: i32,
+    name: Arc<CStr>,
+}
+
+impl KstatTriplet {
+    /// Returns `None` if `module` or `name` contain a NUL byte, since no kstat can match them
+    pub fn new(module: &str, instance: i32, name: &str) -> Option<Self> {
+        Some(KstatTriplet {
+            module: CString::new(module).ok()?.into(),
+            instance,
+            name: CString::new(name).ok()?.into(),
+        })
+    }
+
+    pub fn module(&self) -> &CStr {
+        &self.module
+    }
+
+    pub fn instance(&self) -> i32 {
+        self.instance
+    }
+
+    pub fn name(&self) -> &CStr {
+        &self.name
+    }
+}
+
+/// The C strings of recently looked up triplets, keyed by the strings they were converted from,
+/// so that looking up the same kstats every sample does not allocate
+#[derive(Debug, Default)]
+pub(crate) struct TripletCache {
+    strings: HashMap<String, Arc<CStr>>,
+}
+
+impl TripletCache {
+    /// Like `KstatTriplet::new`, but reuses the C strings of earlier triplets
+    pub fn triplet(&mut self, module: &str, instance: i32, name: &str) -> Option<KstatTriplet> {
+        Some(KstatTriplet {
+            module: self.get(module)?,
+            instance,
+            name: self.get(name)?,
+        })
+    }
+
+    fn get(&mut self, s: &str) -> Option<Arc<CStr>> {
+        if let Some(c) = self.strings.get(s) {
+            return Some(Arc::clone(c));
+        }
+        let c: Arc<CStr> = CString::new(s).ok()?.into();
+        if self.strings.len() >= CACHE_CAPACITY {
+            self.strings.clear();
+        }
+        self.strings.insert(s.to_string(), Arc::clone(&c));
+        Some(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_reuses_strings() {
+        let mut cache = TripletCache::default();
+        let a = cache.triplet("zfs", 0, "arcstats").unwrap();
+        let b = cache.triplet("zfs", 1, "zfs").unwrap();
+        assert_eq!(a, KstatTriplet::new("zfs", 0, "arcstats").unwrap());
+        assert!(Arc::ptr_eq(&a.module, &b.module));
+        assert!(Arc::ptr_eq(&b.module, &b.name));
+        assert_eq!(cache.strings.len(), 2);
+
+        assert!(cache.triplet("zfs", 0, "arc\0stats").is_none());
+        assert_eq!(cache.strings.len(), 2);
+
+        for i in 0..CACHE_CAPACITY {
+            cache
+                .triplet("cpu", i as i32, &format!("cpu{}", i))
+                .unwrap();
+        }
+        assert!(cache.strings.len() <= CACHE_CAPACITY);
+    }
+}
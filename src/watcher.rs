@@ -0,0 +1,103 @@
+use sampler::run_every;
+use {KstatMeta, KstatReader};
+
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::time::Duration;
+
+/// The kstats that appeared and disappeared between two polls of a `KstatWatcher`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ChainChanges {
+    /// kstats that were added, sorted by kid
+    pub added: Vec<KstatMeta>,
+    /// kstats that were removed, sorted by kid
+    pub removed: Vec<KstatMeta>,
+}
+
+impl ChainChanges {
+    /// Returns true if nothing was added or removed
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Reports which kstats are added to or removed from the chain, e.g. when a zone boots or a NIC
+/// appears, without reading any kstat data. Kstats are told apart by their kid, so a kstat that
+/// is deleted and recreated between two polls is reported as both removed and added.
+///
+/// # Example
+/// ```
+/// # use std::time::Duration;
+/// let reader = kstat::KstatReader::builder()
+///     .module("zones")
+///     .build()
+///     .expect("failed to create kstat reader");
+/// let mut watcher = kstat::KstatWatcher::new(reader).expect("failed to list kstat(s)");
+/// watcher.watch(Duration::from_secs(5), |changes| {
+///     for meta in &changes.expect("failed to update kstat chain").added {
+///         println!("zone {} booted", meta.name);
+///     }
+///     false
+/// })
+/// .expect("interval is zero");
+/// ```
+#[derive(Debug)]
+pub struct KstatWatcher {
+    reader: KstatReader,
+    known: HashMap<i32, KstatMeta>,
+}
+
+impl KstatWatcher {
+    /// Returns a `KstatWatcher` that watches the kstats matched by `reader`. The kstats that
+    /// exist now are the baseline for the first `poll`.
+    pub fn new(reader: KstatReader) -> io::Result<Self> {
+        let mut watcher = KstatWatcher {
+            reader,
+            known: HashMap::new(),
+        };
+        watcher.poll()?;
+        Ok(watcher)
+    }
+
+    /// Returns a reference to the underlying `KstatReader`
+    pub fn reader(&self) -> &KstatReader {
+        &self.reader
+    }
+
+    /// Update the chain and return what changed since the previous poll
+    pub fn poll(&mut self) -> io::Result<ChainChanges> {
+        let current: HashMap<i32, KstatMeta> = self
+            .reader
+            .list()?
+            .into_iter()
+            .map(|meta| (meta.kid, meta))
+            .collect();
+
+        let mut changes = ChainChanges::default();
+        for (kid, meta) in &current {
+            if !self.known.contains_key(kid) {
+                changes.added.push(meta.clone());
+            }
+        }
+        let previous = mem::replace(&mut self.known, current);
+        changes.removed = previous
+            .into_iter()
+            .filter(|(kid, _)| !self.known.contains_key(kid))
+            .map(|(_, meta)| meta)
+            .collect();
+
+        changes.added.sort_by_key(|m| m.kid);
+        changes.removed.sort_by_key(|m| m.kid);
+        Ok(changes)
+    }
+
+    /// Poll once every `interval`, handing each result to `f`, until `f` returns false. See
+    /// `KstatReader::every` for how ticks are scheduled and when this fails.
+    pub fn watch<F>(&mut self, interval: Duration, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(io::Result<ChainChanges>) -> bool,
+    {
+        run_every(interval, || f(self.poll()))
+    }
+}
@@ -0,0 +1,438 @@
+//! Exercises the crate against the live kernel. Every test is ignored by default since the
+//! results depend on the machine; run them on illumos with `cargo test -- --ignored`.
+//!
+//! The assertions only check what holds on any illumos system: that reads succeed, that
+//! identities and timestamps are sane, and that derived numbers are within their bounds.
+
+#![cfg(any(target_os = "illumos", target_os = "solaris"))]
+
+extern crate kstat;
+
+use kstat::helpers::zfs::CacheStats;
+use kstat::helpers::{self, caps, cpu, deverr, link, memcap, memory, zfs, zones};
+use kstat::{
+    BurstCapture, Data, Error, ErrorPolicy, KstatReader, KstatSampler, KstatSelector, KstatType,
+    KstatWatcher,
+};
+
+use std::io;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+fn read_module(module: &str) -> Vec<kstat::KstatData> {
+    let reader = KstatReader::builder()
+        .module(module)
+        .build()
+        .expect("failed to create kstat reader");
+    reader.read().expect("failed to read kstat(s)")
+}
+
+#[test]
+#[ignore]
+fn read_everything() {
+    let reader = KstatReader::new().expect("failed to create kstat reader");
+    let stats = reader.read().expect("failed to read kstat(s)");
+    assert!(!stats.is_empty());
+    for stat in &stats {
+        assert!(!stat.module.is_empty());
+        assert!(stat.snaptime >= stat.crtime);
+        assert!(stat.snaptime_as_systemtime() <= SystemTime::now());
+    }
+}
+
+#[test]
+#[ignore]
+fn filters() {
+    let reader = KstatReader::builder()
+        .module("cpu_info")
+        .instance(0)
+        .build()
+        .expect("failed to create kstat reader");
+    let stats = reader.read().expect("failed to read kstat(s)");
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].module, "cpu_info");
+    assert_eq!(stats[0].instance, 0);
+
+    let reader = KstatReader::builder()
+        .add_selector(KstatSelector::new().module("unix").name("system_misc"))
+        .add_selector(KstatSelector::new().module("cpu_info"))
+        .build()
+        .expect("failed to create kstat reader");
+    for stat in reader.read().expect("failed to read kstat(s)") {
+        assert!(stat.module == "unix" || stat.module == "cpu_info");
+    }
+
+    let reader = KstatReader::builder()
+        .module("no_such_module")
+        .build()
+        .expect("failed to create kstat reader");
+    assert!(reader.read().expect("failed to read kstat(s)").is_empty());
+}
+
+#[test]
+#[ignore]
+fn read_one() {
+    // The reader's own filters do not apply
+    let reader = KstatReader::builder()
+        .module("no_such_module")
+        .build()
+        .expect("failed to create kstat reader");
+    let misc = reader
+        .read_one("unix", 0, "system_misc")
+        .expect("failed to read kstat")
+        .expect("unix:0:system_misc not found");
+    assert_eq!((misc.module.as_str(), misc.instance), ("unix", 0));
+    assert!(misc.get_u64("nproc").is_some());
+    assert!(reader
+        .read_one("unix", 0, "no_such_kstat")
+        .expect("failed to read")
+        .is_none());
+    assert!(reader
+        .read_one("unix\0", 0, "system_misc")
+        .expect("failed to read")
+        .is_none());
+}
+
+#[test]
+#[ignore]
+fn repeated_reads_use_the_same_chain() {
+    let reader = KstatReader::builder()
+        .module("cpu_info")
+        .build()
+        .expect("failed to create kstat reader");
+    let first = reader.read().expect("failed to read kstat(s)");
+    let second = reader.read().expect("failed to read kstat(s)");
+    assert_eq!(first.len(), second.len());
+    for (a, b) in first.iter().zip(second.iter()) {
+        assert_eq!(a.id(), b.id());
+        assert!(b.snaptime >= a.snaptime);
+    }
+}
+
+#[test]
+#[ignore]
+fn list_does_not_read() {
+    let reader = KstatReader::new().expect("failed to create kstat reader");
+    let list = reader.list().expect("failed to list kstat(s)");
+    let read = reader.read().expect("failed to read kstat(s)");
+    // list includes every type, read only named and I/O kstats
+    assert!(list.len() >= read.len());
+    assert!(list.iter().any(|m| m.kstat_type == KstatType::Named));
+}
+
+#[test]
+#[ignore]
+fn watcher() {
+    let reader = KstatReader::new().expect("failed to create kstat reader");
+    let mut watcher = KstatWatcher::new(reader).expect("failed to list kstat(s)");
+    // Anything may come and go in between, but the same kstat cannot be both added and removed
+    let changes = watcher.poll().expect("failed to poll");
+    for meta in &changes.added {
+        assert!(changes.removed.iter().all(|m| m.kid != meta.kid));
+    }
+}
+
+#[test]
+#[ignore]
+fn io_kstats() {
+    let reader = KstatReader::builder()
+        .class("disk")
+        .build()
+        .expect("failed to create kstat reader");
+    for stat in reader.read().expect("failed to read kstat(s)") {
+        if let Data::Io(ref io) = stat.data {
+            assert!(io.wcnt < 1_000_000 && io.rcnt < 1_000_000);
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn error_policies() {
+    for policy in &[
+        ErrorPolicy::Strict,
+        ErrorPolicy::IgnoreTransient,
+        ErrorPolicy::IgnoreAll,
+    ] {
+        let reader = KstatReader::builder()
+            .error_policy(*policy)
+            .build()
+            .expect("failed to create kstat reader");
+        match reader.read_outcome() {
+            Ok(outcome) => {
+                assert!(!outcome.data.is_empty());
+                for e in &outcome.skipped {
+                    assert!(Error::from_io(e).and_then(|e| e.id()).is_some());
+                }
+            }
+            // Only a strict reader may fail, and the error must name the kstat
+            Err(e) => {
+                assert!(matches!(*policy, ErrorPolicy::Strict));
+                assert!(Error::from_io(&e).is_some());
+            }
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn reopen() {
+    let reader = KstatReader::builder()
+        .module("cpu_info")
+        .reopen_after(1)
+        .build()
+        .expect("failed to create kstat reader");
+    assert!(!reader.read().expect("failed to read kstat(s)").is_empty());
+}
+
+#[test]
+#[ignore]
+fn sampler() {
+    let reader = KstatReader::builder()
+        .module("cpu")
+        .name("sys")
+        .build()
+        .expect("failed to create kstat reader");
+    let mut sampler = KstatSampler::new(reader);
+    assert!(sampler.sample().expect("failed to sample").is_empty());
+    thread::sleep(Duration::from_millis(100));
+    let deltas = sampler.sample().expect("failed to sample");
+    assert!(!deltas.is_empty());
+    for delta in &deltas {
+        assert!(delta.interval > 0);
+    }
+    let timings = sampler.last_timings().expect("expected timings");
+    assert!(timings.total() > Duration::from_secs(0));
+}
+
+#[test]
+#[ignore]
+fn burst_capture() {
+    let reader = KstatReader::builder()
+        .module("cpu")
+        .name("sys")
+        .build()
+        .expect("failed to create kstat reader");
+    let burst = BurstCapture::new(Duration::from_millis(50), Duration::from_millis(500));
+    let mut out = Vec::new();
+    let samples = burst
+        .capture(&reader, &mut out)
+        .expect("failed to capture burst");
+    assert!(samples >= 5);
+    let lines = String::from_utf8(out).expect("capture is not UTF-8");
+    assert!(lines.lines().all(|l| l.starts_with("cpu:sys,")));
+}
+
+#[test]
+#[ignore]
+fn cpu_helpers() {
+    let info = cpu::info().expect("failed to read cpu_info");
+    assert!(!info.is_empty());
+    let topology = cpu::CpuTopology::read().expect("failed to read the cpu topology");
+    for i in &info {
+        assert!(topology.location(i.cpu_id).is_some());
+    }
+    let stats = read_module("cpu");
+    let host = topology.aggregate(&stats, cpu::CpuGrouping::Host);
+    assert!(host.len() <= 1);
+    cpu::power().expect("failed to read cpu power");
+
+    let prev = cpu::times().expect("failed to read cpu times");
+    thread::sleep(Duration::from_millis(100));
+    let cur = cpu::times().expect("failed to read cpu times");
+    for u in cpu::utilization(&prev, &cur) {
+        let total = u.user_pct + u.kernel_pct + u.idle_pct;
+        assert!((total - 100.0).abs() < 0.001);
+    }
+}
+
+#[test]
+#[ignore]
+fn network_helpers() {
+    let tcp = helpers::tcp::TcpStats::read(0).expect("failed to read tcp");
+    let icmp = helpers::icmp::IcmpStats::read(0).expect("failed to read icmp");
+    thread::sleep(Duration::from_millis(100));
+    let tcp_rates = helpers::tcp::TcpStats::read(0)
+        .expect("failed to read tcp")
+        .rates(&tcp);
+    let icmp_rates = helpers::icmp::IcmpStats::read(0)
+        .expect("failed to read icmp")
+        .rates(&icmp);
+    assert!(tcp_rates.in_segs >= 0.0);
+    assert!(icmp_rates.in_msgs >= 0.0);
+
+    let links = link::read().expect("failed to read link kstats");
+    for c in link::read().expect("failed to read link kstats") {
+        if let Some(rates) = links
+            .iter()
+            .find(|p| p.link == c.link)
+            .and_then(|p| c.rates(p))
+        {
+            assert!(rates.rbytes >= 0.0 && rates.obytes >= 0.0);
+        }
+    }
+
+    // SCTP and IPsec are loadable modules, so they may not be present
+    let _ = helpers::sctp::SctpStats::read(0);
+    let _ = helpers::ipsec::AhStats::read(0);
+    let _ = helpers::ipsec::EspStats::read(0);
+}
+
+#[test]
+#[ignore]
+fn vm_helpers() {
+    let prev = helpers::segmap::SegmapStats::read().expect("failed to read segmap");
+    let cur = helpers::segmap::SegmapStats::read().expect("failed to read segmap");
+    if let Some(ratio) = cur.hit_ratio(&prev) {
+        assert!((0.0..=1.0).contains(&ratio));
+    }
+
+    let mem = memory::MemorySummary::read().expect("failed to read system_pages");
+    assert!(mem.physmem > 0 && mem.freemem <= mem.physmem);
+
+    // ZFS is not loaded on every system
+    if let Ok(arc) = zfs::ArcStats::read_arc() {
+        assert!(arc.c_max >= arc.c_min);
+        if let Some(ratio) = arc.hit_ratio() {
+            assert!((0.0..=1.0).contains(&ratio));
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn zone_helpers() {
+    let prev_caps = caps::read().expect("failed to read cpucaps");
+    let prev_mem = memcap::read().expect("failed to read memory caps");
+    thread::sleep(Duration::from_millis(100));
+    let cur_caps = caps::read().expect("failed to read cpucaps");
+    let cur_mem = memcap::read().expect("failed to read memory caps");
+
+    for (p, c) in prev_caps.iter().zip(cur_caps.iter()) {
+        if let Some(usage) = c.usage_since(p) {
+            assert!(usage.throttled >= 0.0 && usage.throttled <= 1.0);
+        }
+    }
+    for (p, c) in prev_mem.iter().zip(cur_mem.iter()) {
+        if let Some(rates) = c.rates(p) {
+            assert!(rates.pagedout >= 0.0);
+        }
+    }
+
+    let mut tracker = caps::CpuCapTracker::new(10);
+    tracker.record(&prev_caps);
+    tracker.record(&cur_caps);
+    assert_eq!(tracker.series().count(), cur_caps.len());
+
+    let zones = zones::read().expect("failed to read zone kstats");
+    assert!(zones.iter().any(|z| z.misc.is_some()));
+    for zone in &zones {
+        assert!(!zone.zonename.is_empty());
+    }
+}
+
+#[test]
+#[ignore]
+fn device_errors() {
+    for dev in deverr::read().expect("failed to read device errors") {
+        assert!(!dev.key.instance_name().is_empty());
+    }
+}
+
+#[test]
+#[ignore]
+fn influx_exporter() {
+    let stats = read_module("cpu_info");
+    let mut influx = kstat::exporters::influx::InfluxWriter::new(Vec::new());
+    influx.host("test");
+    influx.write(&stats).expect("failed to write line protocol");
+    let out = String::from_utf8(influx.into_inner()).expect("line protocol is not utf8");
+    assert_eq!(out.lines().count(), stats.len());
+    assert!(out.lines().all(|l| l.starts_with("cpu_info:cpu_info")));
+}
+
+#[cfg(feature = "statsd")]
+#[test]
+#[ignore]
+fn statsd_exporter() {
+    let socket = ::std::net::UdpSocket::bind("127.0.0.1:0").expect("failed to bind");
+    let exporter = kstat::exporters::statsd::StatsdExporter::new(
+        socket.local_addr().expect("no local address"),
+    )
+    .expect("failed to create statsd exporter");
+    exporter
+        .send_gauges(&read_module("cpu_info"))
+        .expect("failed to send gauges");
+}
+
+#[test]
+#[ignore]
+fn open_errors_carry_a_kstat_error() {
+    // Not an error path that can be provoked on demand, but any error must carry its details
+    let e: io::Error = match KstatReader::new() {
+        Ok(_) => return,
+        Err(e) => e,
+    };
+    assert!(Error::from_io(&e).is_some());
+}
+
+#[test]
+#[ignore]
+fn dump_chain_debug() {
+    let ctl = kstat::KstatCtl::new().expect("failed to open kstat handle");
+    let mut out = Vec::new();
+    ctl.dump_chain_debug(&mut out)
+        .expect("failed to dump kstat chain");
+    let out = String::from_utf8(out).expect("dump is not utf8");
+    assert!(out.lines().count() > 1);
+    assert!(out.lines().any(|l| l.contains(" unix:0:system_misc ")));
+}
+
+#[test]
+#[ignore]
+fn read_map() {
+    let reader = KstatReader::builder()
+        .module("cpu")
+        .name("sys")
+        .build()
+        .expect("failed to create kstat reader");
+    let map = reader.read_map().expect("failed to read kstat(s)");
+    assert!(!map.is_empty());
+    for (id, data) in &map {
+        assert_eq!(*id, data.id());
+    }
+}
+
+#[test]
+#[ignore]
+fn sorted_read() {
+    let reader = KstatReader::builder()
+        .module("cpu")
+        .sorted(true)
+        .build()
+        .expect("failed to create kstat reader");
+    let stats = reader.read().expect("failed to read kstat(s)");
+    let ids: Vec<_> = stats
+        .iter()
+        .map(|s| (&s.module, s.instance, &s.name))
+        .collect();
+    let mut sorted = ids.clone();
+    sorted.sort();
+    assert_eq!(ids, sorted);
+}
+
+#[test]
+#[ignore]
+#[allow(deprecated)]
+fn deprecated_setters() {
+    let mut reader = KstatReader::new().expect("failed to create kstat reader");
+    reader.module("cpu").name("sys");
+    let stats = reader.read().expect("failed to read kstat(s)");
+    assert!(!stats.is_empty());
+    assert!(stats.iter().all(|s| s.module == "cpu" && s.name == "sys"));
+
+    // Narrowing the filters of a reader that has already read takes effect on the next read
+    reader.instance(0);
+    let stats = reader.read().expect("failed to read kstat(s)");
+    assert_eq!(stats.len(), 1);
+}